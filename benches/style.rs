@@ -0,0 +1,67 @@
+//! Styling and layout of the `examples/` documents, scaled up by repeating them with
+//! distinct class names, together with a stylesheet that grows at the same rate.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use robinson::{css, layout, parser, style};
+
+const CLASSES: [&str; 7] = ["a", "b", "c", "d", "e", "f", "g"];
+
+/// `copies` copies of each example document, with every class `x` in copy `i` also
+/// called `x-i`, and stylesheet rules that target each copy separately.
+fn scaled_example(copies: usize) -> (String, String) {
+    let documents = [include_str!("../examples/test.html"), include_str!("../examples/test2.html")];
+    let stylesheets = [include_str!("../examples/test.css"), include_str!("../examples/test2.css")];
+    let mut html = String::from("<html><body>");
+    let mut css: String = stylesheets.concat();
+    for i in 0..copies {
+        for document in documents {
+            let mut copy = document.to_string();
+            for class in CLASSES {
+                copy = copy.replace(&format!("class=\"{class}\""), &format!("class=\"{class} {class}-{i}\""));
+            }
+            html.push_str(&format!("<div id=\"copy-{i}\">{copy}</div>"));
+        }
+        for (j, class) in CLASSES.iter().enumerate() {
+            let next = CLASSES[(j + 1) % CLASSES.len()];
+            css.push_str(&format!(".{class}-{i} {{ padding: {j}px; }}\n"));
+            // Indexed under a class every copy shares, so only the ancestor filter tells copies apart.
+            css.push_str(&format!("#copy-{i} .{class} > .{next} {{ margin-left: 1px; }}\n"));
+            css.push_str(&format!("div.{class}-{i} ~ .{next}-{i}, .{next}-{i} + .{class}-{i} {{ border-top-width: 1px; }}\n"));
+        }
+    }
+    html.push_str("</body></html>");
+    (html, css)
+}
+
+fn style_scaled_examples(c: &mut Criterion) {
+    let mut group = c.benchmark_group("style_tree");
+    for copies in [1, 16, 64] {
+        let (html, source) = scaled_example(copies);
+        let root = parser::parse(html);
+        let stylesheet = css::parse(source);
+        group.bench_with_input(BenchmarkId::from_parameter(copies), &copies, |b, _| {
+            b.iter(|| style::style_tree(&root, &[&stylesheet]))
+        });
+    }
+    group.finish();
+}
+
+fn layout_scaled_examples(c: &mut Criterion) {
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+    let mut group = c.benchmark_group("layout_tree");
+    for copies in [1, 16, 64] {
+        let (html, source) = scaled_example(copies);
+        let root = parser::parse(html);
+        let stylesheet = css::parse(source);
+        let styled = style::style_tree(&root, &[&stylesheet]);
+        group.bench_with_input(BenchmarkId::from_parameter(copies), &copies, |b, _| {
+            b.iter(|| layout::layout_tree(&styled, viewport))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, style_scaled_examples, layout_scaled_examples);
+criterion_main!(benches);
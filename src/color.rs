@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+use crate::css::Color;
+
+/// The named colors of CSS Color 4, as sRGB.
+const NAMED_COLORS: [(&str, [u8; 3]); 148] = [
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+lazy_static! {
+    static ref COLOR_NAME: HashMap<&'static str, [u8; 3]> = NAMED_COLORS.iter().copied().collect();
+}
+
+/// Look up a named color, ignoring ASCII case. `transparent` is included.
+pub fn named_color(name: &str) -> Option<Color> {
+    let name = name.to_ascii_lowercase();
+    if name == "transparent" {
+        return Some(Color { r: 0, g: 0, b: 0, a: 0 });
+    }
+    COLOR_NAME.get(&*name).map(|&[r, g, b]| Color { r, g, b, a: 255 })
+}
+
+/// The shortest name of an opaque color, or `transparent` for fully transparent black.
+pub fn color_name(color: Color) -> Option<&'static str> {
+    if color == (Color { r: 0, g: 0, b: 0, a: 0 }) {
+        return Some("transparent");
+    }
+    if color.a != 255 {
+        return None;
+    }
+    NAMED_COLORS.iter()
+        .filter(|(_, rgb)| *rgb == [color.r, color.g, color.b])
+        .map(|&(name, _)| name)
+        .min_by_key(|name| name.len())
+}
+
+/// Convert a channel in the 0.0..=1.0 range to a byte, clamping out-of-gamut values.
+fn to_byte(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Apply the sRGB transfer function to a linear-light channel.
+fn gamma_encode(v: f32) -> f32 {
+    let sign = v.signum();
+    let v = v.abs();
+    if v <= 0.0031308 {
+        sign * 12.92 * v
+    } else {
+        sign * (1.055 * v.powf(1.0 / 2.4) - 0.055)
+    }
+}
+
+fn multiply(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+impl Color {
+    /// Build a color from sRGB channels in the 0.0..=1.0 range.
+    pub fn from_srgb(r: f32, g: f32, b: f32, alpha: f32) -> Color {
+        Color { r: to_byte(r), g: to_byte(g), b: to_byte(b), a: to_byte(alpha) }
+    }
+
+    fn from_linear_srgb(rgb: [f32; 3], alpha: f32) -> Color {
+        let [r, g, b] = rgb.map(gamma_encode);
+        Color::from_srgb(r, g, b, alpha)
+    }
+
+    /// `hue` in degrees, `saturation` and `lightness` in 0.0..=1.0.
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32, alpha: f32) -> Color {
+        let hue = hue.rem_euclid(360.0);
+        let channel = |n: f32| {
+            let k = (n + hue / 30.0) % 12.0;
+            let a = saturation * lightness.min(1.0 - lightness);
+            lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+        };
+        Color::from_srgb(channel(0.0), channel(8.0), channel(4.0), alpha)
+    }
+
+    /// `hue` in degrees, `whiteness` and `blackness` in 0.0..=1.0.
+    pub fn from_hwb(hue: f32, whiteness: f32, blackness: f32, alpha: f32) -> Color {
+        if whiteness + blackness >= 1.0 {
+            let gray = whiteness / (whiteness + blackness);
+            return Color::from_srgb(gray, gray, gray, alpha);
+        }
+        let pure = Color::from_hsl(hue, 1.0, 0.5, 1.0);
+        let channel = |c: u8| c as f32 / 255.0 * (1.0 - whiteness - blackness) + whiteness;
+        Color::from_srgb(channel(pure.r), channel(pure.g), channel(pure.b), alpha)
+    }
+
+    /// CIE Lab with a D50 white point; `lightness` in 0..=100.
+    pub fn from_lab(lightness: f32, a: f32, b: f32, alpha: f32) -> Color {
+        const KAPPA: f32 = 24389.0 / 27.0;
+        const EPSILON: f32 = 216.0 / 24389.0;
+        const D50_WHITE: [f32; 3] = [0.3457 / 0.3585, 1.0, (1.0 - 0.3457 - 0.3585) / 0.3585];
+        // Bradford chromatic adaptation from D50 to D65, then XYZ to linear sRGB.
+        const D50_TO_D65: [[f32; 3]; 3] = [
+            [0.9554734, -0.023098456, 0.063259244],
+            [-0.02836971, 1.0099953, 0.021041442],
+            [0.012314015, -0.020507649, 1.3303659],
+        ];
+        const XYZ_TO_LINEAR_SRGB: [[f32; 3]; 3] = [
+            [3.24097, -1.5373832, -0.49861076],
+            [-0.96924365, 1.8759675, 0.04155506],
+            [0.05563008, -0.20397696, 1.0569715],
+        ];
+
+        let f1 = (lightness + 16.0) / 116.0;
+        let f0 = a / 500.0 + f1;
+        let f2 = f1 - b / 200.0;
+        let inverse = |f: f32| if f.powi(3) > EPSILON { f.powi(3) } else { (116.0 * f - 16.0) / KAPPA };
+        let y = if lightness > KAPPA * EPSILON { f1.powi(3) } else { lightness / KAPPA };
+        let xyz = [inverse(f0) * D50_WHITE[0], y * D50_WHITE[1], inverse(f2) * D50_WHITE[2]];
+        Color::from_linear_srgb(multiply(&XYZ_TO_LINEAR_SRGB, multiply(&D50_TO_D65, xyz)), alpha)
+    }
+
+    /// `hue` in degrees.
+    pub fn from_lch(lightness: f32, chroma: f32, hue: f32, alpha: f32) -> Color {
+        let (sin, cos) = hue.to_radians().sin_cos();
+        Color::from_lab(lightness, chroma * cos, chroma * sin, alpha)
+    }
+
+    /// Oklab; `lightness` in 0.0..=1.0.
+    pub fn from_oklab(lightness: f32, a: f32, b: f32, alpha: f32) -> Color {
+        const LAB_TO_LMS: [[f32; 3]; 3] = [
+            [1.0, 0.39633778, 0.21580376],
+            [1.0, -0.10556135, -0.06385417],
+            [1.0, -0.08948418, -1.2914855],
+        ];
+        const LMS_TO_LINEAR_SRGB: [[f32; 3]; 3] = [
+            [4.0767417, -3.3077116, 0.23096994],
+            [-1.268438, 2.6097574, -0.3413194],
+            [-0.0041960864, -0.7034186, 1.7076147],
+        ];
+        let lms = multiply(&LAB_TO_LMS, [lightness, a, b]).map(|c| c.powi(3));
+        Color::from_linear_srgb(multiply(&LMS_TO_LINEAR_SRGB, lms), alpha)
+    }
+
+    /// `hue` in degrees.
+    pub fn from_oklch(lightness: f32, chroma: f32, hue: f32, alpha: f32) -> Color {
+        let (sin, cos) = hue.to_radians().sin_cos();
+        Color::from_oklab(lightness, chroma * cos, chroma * sin, alpha)
+    }
+}
+
+/// One argument of a color function, before it is interpreted for a particular channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Component {
+    Number(f32),
+    Percentage(f32),
+    /// An angle, in degrees.
+    Angle(f32),
+    /// The `none` keyword, which behaves as zero.
+    None,
+}
+
+impl Component {
+    /// Resolve a number or percentage, where 100% corresponds to `full`.
+    fn scaled(self, full: f32) -> f32 {
+        match self {
+            Component::Number(n) => n,
+            Component::Percentage(p) => p / 100.0 * full,
+            Component::Angle(_) | Component::None => 0.0,
+        }
+    }
+
+    /// Resolve a fraction in 0.0..=1.0, where a bare number is a percentage
+    /// (the modern syntax of `hsl()` and `hwb()`).
+    fn fraction(self) -> f32 {
+        match self {
+            Component::Number(n) | Component::Percentage(n) => n / 100.0,
+            Component::Angle(_) | Component::None => 0.0,
+        }
+    }
+
+    /// Resolve a hue, in degrees.
+    fn hue(self) -> f32 {
+        match self {
+            Component::Number(deg) | Component::Angle(deg) => deg,
+            Component::Percentage(_) | Component::None => 0.0,
+        }
+    }
+}
+
+/// Evaluate `rgb()`, `rgba()`, `hsl()`, `hsla()`, `hwb()`, `lab()`, `lch()`,
+/// `oklab()` or `oklch()`. Returns `None` for an unknown function or the
+/// wrong number of arguments.
+pub fn color_function(name: &str, channels: &[Component], alpha: Option<Component>) -> Option<Color> {
+    let alpha = alpha.map_or(1.0, |a| a.scaled(1.0));
+    let &[c0, c1, c2] = channels else {
+        return None;
+    };
+    Some(match name {
+        "rgb" | "rgba" => Color::from_srgb(c0.scaled(255.0) / 255.0, c1.scaled(255.0) / 255.0,
+                                           c2.scaled(255.0) / 255.0, alpha),
+        "hsl" | "hsla" => Color::from_hsl(c0.hue(), c1.fraction(), c2.fraction(), alpha),
+        "hwb" => Color::from_hwb(c0.hue(), c1.fraction(), c2.fraction(), alpha),
+        "lab" => Color::from_lab(c0.scaled(100.0), c1.scaled(125.0), c2.scaled(125.0), alpha),
+        "lch" => Color::from_lch(c0.scaled(100.0), c1.scaled(150.0), c2.hue(), alpha),
+        "oklab" => Color::from_oklab(c0.scaled(1.0), c1.scaled(0.4), c2.scaled(0.4), alpha),
+        "oklch" => Color::from_oklch(c0.scaled(1.0), c1.scaled(0.4), c2.hue(), alpha),
+        _ => return None,
+    })
+}
+
+/// Whether `name` is one of the color functions understood by `color_function`.
+pub fn is_color_function(name: &str) -> bool {
+    ["rgb", "rgba", "hsl", "hsla", "hwb", "lab", "lch", "oklab", "oklch"].contains(&name)
+}
+
+#[test]
+fn color_space_conversions() {
+    let rgb = |c: Color| (c.r, c.g, c.b, c.a);
+    assert_eq!(Some((102, 51, 153, 255)), named_color("RebeccaPurple").map(rgb));
+    assert_eq!((0, 255, 0, 255), rgb(Color::from_hsl(120.0, 1.0, 0.5, 1.0)));
+    assert_eq!((255, 128, 0, 128), rgb(Color::from_hsl(30.0, 1.0, 0.5, 0.5)));
+    assert_eq!((128, 128, 128, 255), rgb(Color::from_hwb(0.0, 0.5, 0.5, 1.0)));
+    assert_eq!((255, 255, 255, 255), rgb(Color::from_lab(100.0, 0.0, 0.0, 1.0)));
+    assert_eq!((255, 0, 0, 255), rgb(Color::from_lch(54.29, 106.84, 40.85, 1.0)));
+    assert_eq!((255, 0, 0, 255), rgb(Color::from_oklch(0.62796, 0.25768, 29.23, 1.0)));
+    assert_eq!((0, 0, 0, 255), rgb(Color::from_oklab(0.0, 0.0, 0.0, 1.0)));
+}
+
+#[test]
+fn named_colors() {
+    for &(name, [r, g, b]) in NAMED_COLORS.iter() {
+        let color = Color { r, g, b, a: 255 };
+        assert_eq!(Some(color), named_color(name));
+        assert_eq!(Some(color), named_color(&name.to_ascii_uppercase()));
+        assert_eq!(Some(crate::css::Value::ColorValue(color)), crate::css::parse_value(name.to_string()));
+        // Aliases such as `aqua` and `cyan` share a name.
+        assert_eq!(Some([r, g, b]), color_name(color).and_then(|name| COLOR_NAME.get(name).copied()));
+    }
+    assert_eq!(148, COLOR_NAME.len());
+    assert_eq!(Some(Color { r: 0, g: 0, b: 0, a: 0 }), named_color("Transparent"));
+    assert_eq!(Some("transparent"), color_name(Color { r: 0, g: 0, b: 0, a: 0 }));
+    assert_eq!(None, named_color("bluish"));
+}
+
+#[test]
+fn color_syntaxes() {
+    let cases = [
+        // Hex colors.
+        ("#0f8", Some((0, 255, 136, 255))),
+        ("#0f88", Some((0, 255, 136, 136))),
+        ("#00ff88", Some((0, 255, 136, 255))),
+        ("#00FF8880", Some((0, 255, 136, 128))),
+        ("#", None),
+        ("#12", None),
+        ("#12345", None),
+        ("#1234567", None),
+        ("#123456789", None),
+        ("#ggg", None),
+        // `rgb()` and `rgba()`, in the legacy and modern syntaxes.
+        ("rgb(255, 0, 0)", Some((255, 0, 0, 255))),
+        ("rgba(255, 0, 0, 0.5)", Some((255, 0, 0, 128))),
+        ("rgb(255 0 0 / 50%)", Some((255, 0, 0, 128))),
+        ("rgb(100% 0% 0%)", Some((255, 0, 0, 255))),
+        ("rgba(0 0 255)", Some((0, 0, 255, 255))),
+        ("RGB(300 -20 0)", Some((255, 0, 0, 255))),
+        ("rgb(none 255 none / none)", Some((0, 255, 0, 0))),
+        ("rgb()", None),
+        ("rgb(1, 2)", None),
+        ("rgb(1 2 3 4)", None),
+        ("rgb(1, 2, 3, 4, 5)", None),
+        ("rgb(1 2 3 / 4 / 5)", None),
+        ("rgb(1 / 2 3)", None),
+        ("rgb(foo)", None),
+        ("rgb(from red r g b)", None),
+        ("rgb(1 2 3", None),
+        // `hsl()`, `hsla()` and `hwb()`, with hues in any angle unit.
+        ("hsl(120, 100%, 50%)", Some((0, 255, 0, 255))),
+        ("hsl(120 100 50)", Some((0, 255, 0, 255))),
+        ("hsla(120 100% 25% / 0.25)", Some((0, 128, 0, 64))),
+        ("hsl(0.5turn 100% 50%)", Some((0, 255, 255, 255))),
+        ("hsl(200grad 100% 50%)", Some((0, 255, 255, 255))),
+        ("hsl(3.14159rad 100% 50%)", Some((0, 255, 255, 255))),
+        ("hsl(10px 50% 50%)", None),
+        ("hwb(0 0% 0%)", Some((255, 0, 0, 255))),
+        ("hwb(120 0% 50%)", Some((0, 128, 0, 255))),
+        ("hwb(0 100% 0% / 0)", Some((255, 255, 255, 0))),
+        ("hwb(0 0% 0% /)", None),
+        // Lab, LCH, Oklab and Oklch.
+        ("lab(100 0 0)", Some((255, 255, 255, 255))),
+        ("lab(0% 0 0)", Some((0, 0, 0, 255))),
+        ("lab(1 2 3 4)", None),
+        ("lch(54.29 106.84 40.85)", Some((255, 0, 0, 255))),
+        ("oklab(0 0 0)", Some((0, 0, 0, 255))),
+        ("oklab(100% 0 0)", Some((255, 255, 255, 255))),
+        ("oklch(62.8% 0.258 29.23)", Some((255, 0, 0, 255))),
+    ];
+    for (source, expected) in cases {
+        let color = match crate::css::parse_value(source.to_string()) {
+            Some(crate::css::Value::ColorValue(c)) => Some((c.r, c.g, c.b, c.a)),
+            Some(value) => panic!("{source} parsed as {value:?}"),
+            None => None,
+        };
+        assert_eq!(expected, color, "{source}");
+    }
+}
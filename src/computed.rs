@@ -0,0 +1,570 @@
+use crate::css::{
+    Calc,
+    Color,
+    LengthContext,
+    Unit,
+    Value,
+};
+use crate::properties;
+use crate::style::PropertyMap;
+
+/// The computed values that layout and painting use, with each property in a typed field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputedStyle {
+    pub display: Display,
+    pub box_model: BoxValues,
+    pub border: BorderValues,
+    pub text: TextValues,
+    pub font: FontValues,
+    pub background: BackgroundValues,
+}
+
+/// The two-value `display` model: how a box takes part in its parent's layout, and how
+/// it lays out its own contents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Display {
+    /// No box is generated for the element or its descendants.
+    None,
+    /// No box is generated for the element, but its children and pseudo-elements
+    /// generate boxes as if they were children of its parent.
+    Contents,
+    Box {
+        outside: DisplayOutside,
+        inside: DisplayInside,
+        /// Whether the box also generates a `::marker` box.
+        list_item: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayOutside {
+    Block,
+    Inline,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayInside {
+    /// Block and inline layout, in the parent's block formatting context if it can be.
+    Flow,
+    /// Block and inline layout in a new block formatting context.
+    FlowRoot,
+}
+
+impl Display {
+    pub const BLOCK: Display = Display::new(DisplayOutside::Block, DisplayInside::Flow, false);
+    pub const INLINE: Display = Display::new(DisplayOutside::Inline, DisplayInside::Flow, false);
+    pub const INLINE_BLOCK: Display = Display::new(DisplayOutside::Inline, DisplayInside::FlowRoot, false);
+    pub const FLOW_ROOT: Display = Display::new(DisplayOutside::Block, DisplayInside::FlowRoot, false);
+    pub const LIST_ITEM: Display = Display::new(DisplayOutside::Block, DisplayInside::Flow, true);
+
+    const fn new(outside: DisplayOutside, inside: DisplayInside, list_item: bool) -> Display {
+        Display::Box { outside, inside, list_item }
+    }
+
+    /// Parse a value of `display`: one of the legacy single keywords, or an outer and an
+    /// inner display type and `list-item` in any order. A missing outer type is `block`,
+    /// and a missing inner type is `flow`.
+    pub fn parse(value: &Value) -> Option<Display> {
+        let keywords: Vec<&str> = match *value {
+            Value::Keyword(ref k) => vec![k],
+            Value::List(ref items) => items.iter()
+                .map(|item| match *item {
+                    Value::Keyword(ref k) => Some(&**k),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+            _ => return None,
+        };
+        match keywords[..] {
+            ["none"] => return Some(Display::None),
+            ["contents"] => return Some(Display::Contents),
+            ["inline-block"] => return Some(Display::INLINE_BLOCK),
+            _ => {}
+        }
+        let (mut outside, mut inside, mut list_item) = (None, None, false);
+        for keyword in keywords {
+            match keyword {
+                "block" if outside.is_none() => outside = Some(DisplayOutside::Block),
+                "inline" if outside.is_none() => outside = Some(DisplayOutside::Inline),
+                "flow" if inside.is_none() => inside = Some(DisplayInside::Flow),
+                "flow-root" if inside.is_none() => inside = Some(DisplayInside::FlowRoot),
+                "list-item" if !list_item => list_item = true,
+                _ => return None,
+            }
+        }
+        Some(Display::new(outside.unwrap_or(DisplayOutside::Block), inside.unwrap_or(DisplayInside::Flow), list_item))
+    }
+
+    pub fn is_list_item(self) -> bool {
+        matches!(self, Display::Box { list_item: true, .. })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoxValues {
+    pub width: LengthPercentageOrAuto,
+    pub height: LengthPercentageOrAuto,
+    pub margin: Sides<LengthPercentageOrAuto>,
+    pub padding: Sides<LengthPercentage>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BorderValues {
+    pub width: Sides<LengthPercentage>,
+    pub style: Sides<LineStyle>,
+    /// `currentcolor` is already replaced by the element's `color`.
+    pub color: Sides<Color>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextValues {
+    pub color: Color,
+    pub text_align: TextAlign,
+    pub text_indent: LengthPercentage,
+    pub white_space: WhiteSpace,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontValues {
+    /// In px.
+    pub size: f32,
+    pub weight: u16,
+    pub style: FontStyle,
+    /// Family names in order of preference, including generic families such as `serif`.
+    pub family: Vec<String>,
+    pub line_height: LineHeight,
+}
+
+impl FontValues {
+    /// The font properties among the computed values of an element, whose font size
+    /// in px is `size`.
+    pub fn from_values(values: &PropertyMap, size: f32) -> FontValues {
+        let get = |name: &str| values.get(name).cloned().or_else(|| properties::initial_value(name));
+        FontValues {
+            size,
+            weight: match get("font-weight") {
+                Some(Value::Number(n)) => n as u16,
+                _ => 400,
+            },
+            style: match get("font-style") {
+                Some(Value::Keyword(ref k)) if k == "italic" => FontStyle::Italic,
+                Some(Value::Keyword(ref k)) if k == "oblique" => FontStyle::Oblique,
+                _ => FontStyle::Normal,
+            },
+            family: font_families(get("font-family").as_ref()),
+            line_height: match get("line-height") {
+                Some(Value::Number(n)) => LineHeight::Number(n),
+                Some(Value::Length(v, Unit::Percent)) => LineHeight::Length(v / 100.0 * size),
+                Some(Value::Length(v, Unit::Px)) => LineHeight::Length(v),
+                _ => LineHeight::Normal,
+            },
+        }
+    }
+
+    /// The used line height in px, taking `normal` to be 1.2 times the font size.
+    pub fn used_line_height(&self) -> f32 {
+        match self.line_height {
+            LineHeight::Normal => 1.2 * self.size,
+            LineHeight::Number(n) => n * self.size,
+            LineHeight::Length(px) => px,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackgroundValues {
+    pub color: Color,
+}
+
+/// One value for each side of a box.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sides<T> {
+    pub top: T,
+    pub right: T,
+    pub bottom: T,
+    pub left: T,
+}
+
+/// A computed length. Font-relative and absolute units are already in px; percentages,
+/// viewport units and math functions that use them are resolved during layout.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LengthPercentage {
+    Length(f32, Unit),
+    Calc(Box<Calc>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LengthPercentageOrAuto {
+    Auto,
+    LengthPercentage(LengthPercentage),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineStyle {
+    None,
+    Hidden,
+    Dotted,
+    Dashed,
+    Solid,
+    Double,
+    Groove,
+    Ridge,
+    Inset,
+    Outset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Start,
+    End,
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineHeight {
+    Normal,
+    /// A multiple of the font size.
+    Number(f32),
+    /// In px.
+    Length(f32),
+}
+
+impl LengthPercentage {
+    pub fn zero() -> LengthPercentage {
+        LengthPercentage::Length(0.0, Unit::Px)
+    }
+
+    pub fn to_px(&self, context: &LengthContext) -> f32 {
+        match *self {
+            LengthPercentage::Length(v, unit) => unit.to_px(v, context),
+            LengthPercentage::Calc(ref calc) => calc.to_px(context),
+        }
+    }
+
+    pub fn has_percentage(&self) -> bool {
+        match *self {
+            LengthPercentage::Length(_, unit) => unit == Unit::Percent,
+            LengthPercentage::Calc(ref calc) => calc.any_unit(&|unit| unit == Unit::Percent),
+        }
+    }
+
+    /// A length, a math function, or the unitless zero.
+    fn from_value(value: &Value) -> Option<LengthPercentage> {
+        match *value {
+            Value::Length(v, unit) => Some(LengthPercentage::Length(v, unit)),
+            Value::Number(0.0) => Some(LengthPercentage::zero()),
+            Value::Calc(ref calc) => Some(LengthPercentage::Calc(calc.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl LengthPercentageOrAuto {
+    /// The length in px, or `None` for `auto`.
+    pub fn to_px(&self, context: &LengthContext) -> Option<f32> {
+        match *self {
+            LengthPercentageOrAuto::Auto => None,
+            LengthPercentageOrAuto::LengthPercentage(ref length) => Some(length.to_px(context)),
+        }
+    }
+
+    fn from_value(value: &Value) -> Option<LengthPercentageOrAuto> {
+        match *value {
+            Value::Keyword(ref k) if k == "auto" => Some(LengthPercentageOrAuto::Auto),
+            ref value => LengthPercentage::from_value(value).map(LengthPercentageOrAuto::LengthPercentage),
+        }
+    }
+}
+
+impl ComputedStyle {
+    /// Convert the computed values of an element into typed form. Properties that
+    /// aren't set take their initial value, and values this engine doesn't implement
+    /// are treated like the initial value too.
+    pub fn from_values(values: &PropertyMap, parent: Option<&ComputedStyle>) -> ComputedStyle {
+        let get = |name: &str| values.get(name).cloned().or_else(|| properties::initial_value(name));
+        let keyword = |name: &str| match get(name) {
+            Some(Value::Keyword(k)) => k,
+            _ => String::new(),
+        };
+        let length = |name: &str| get(name).as_ref()
+            .and_then(LengthPercentage::from_value)
+            .unwrap_or_else(LengthPercentage::zero);
+        let length_or_auto = |name: &str| get(name).as_ref()
+            .and_then(LengthPercentageOrAuto::from_value)
+            .unwrap_or(LengthPercentageOrAuto::Auto);
+
+        let black = Color { r: 0, g: 0, b: 0, a: 255 };
+        let color = match get("color") {
+            Some(Value::ColorValue(color)) => color,
+            _ => parent.map_or(black, |p| p.text.color),
+        };
+        let color_of = |name: &str| match get(name) {
+            Some(Value::ColorValue(c)) => c,
+            _ => color,
+        };
+        let transparent = Color { r: 0, g: 0, b: 0, a: 0 };
+
+        let display = match get("display").as_ref().and_then(Display::parse) {
+            // The root element always generates a box.
+            Some(Display::Contents) if parent.is_none() => Display::BLOCK,
+            Some(display) => display,
+            None => Display::INLINE,
+        };
+        let border_width = |name: &str| match get(name) {
+            Some(Value::Keyword(ref k)) => LengthPercentage::Length(match &**k {
+                "thin" => 1.0,
+                "thick" => 5.0,
+                _ => 3.0,
+            }, Unit::Px),
+            _ => length(name),
+        };
+
+        let font_size = match get("font-size") {
+            Some(Value::Length(size, Unit::Px)) => size,
+            _ => parent.map_or(MEDIUM_FONT_SIZE, |p| p.font.size),
+        };
+        let font = FontValues::from_values(values, font_size);
+
+        ComputedStyle {
+            display,
+            box_model: BoxValues {
+                width: length_or_auto("width"),
+                height: length_or_auto("height"),
+                margin: sides(&properties::MARGIN, &length_or_auto),
+                padding: sides(&properties::PADDING, &length),
+            },
+            border: BorderValues {
+                width: sides(&properties::BORDER_WIDTH, &border_width),
+                style: sides(&properties::BORDER_STYLE, &|name| line_style(&keyword(name))),
+                color: sides(&properties::BORDER_COLOR, &color_of),
+            },
+            text: TextValues {
+                color,
+                text_align: match &*keyword("text-align") {
+                    "end" => TextAlign::End,
+                    "left" => TextAlign::Left,
+                    "right" => TextAlign::Right,
+                    "center" => TextAlign::Center,
+                    "justify" => TextAlign::Justify,
+                    _ => TextAlign::Start,
+                },
+                text_indent: length("text-indent"),
+                white_space: match &*keyword("white-space") {
+                    "pre" => WhiteSpace::Pre,
+                    "nowrap" => WhiteSpace::Nowrap,
+                    "pre-wrap" => WhiteSpace::PreWrap,
+                    "pre-line" => WhiteSpace::PreLine,
+                    _ => WhiteSpace::Normal,
+                },
+            },
+            font,
+            background: BackgroundValues {
+                color: match get("background-color") {
+                    Some(Value::ColorValue(c)) => c,
+                    Some(Value::Keyword(ref k)) if k == "currentcolor" => color,
+                    _ => transparent,
+                },
+            },
+        }
+    }
+}
+
+/// The values of four longhands such as `border-*-width`, named in the order top,
+/// right, bottom, left.
+fn sides<T>(names: &[&str; 4], f: &dyn Fn(&str) -> T) -> Sides<T> {
+    Sides { top: f(names[0]), right: f(names[1]), bottom: f(names[2]), left: f(names[3]) }
+}
+
+/// The initial `font-size`, in px.
+pub const MEDIUM_FONT_SIZE: f32 = 16.0;
+
+fn line_style(keyword: &str) -> LineStyle {
+    match keyword {
+        "hidden" => LineStyle::Hidden,
+        "dotted" => LineStyle::Dotted,
+        "dashed" => LineStyle::Dashed,
+        "solid" => LineStyle::Solid,
+        "double" => LineStyle::Double,
+        "groove" => LineStyle::Groove,
+        "ridge" => LineStyle::Ridge,
+        "inset" => LineStyle::Inset,
+        "outset" => LineStyle::Outset,
+        _ => LineStyle::None,
+    }
+}
+
+/// The computed `font-size` in px of a keyword. Absolute sizes scale `medium` by the
+/// factors in CSS Fonts; `larger` and `smaller` scale the parent's size by 1.2.
+pub fn font_size_keyword(value: &Value, parent: f32) -> Option<f32> {
+    let Value::Keyword(ref keyword) = *value else { return None };
+    let scale = match &**keyword {
+        "xx-small" => 3.0 / 5.0,
+        "x-small" => 3.0 / 4.0,
+        "small" => 8.0 / 9.0,
+        "medium" => 1.0,
+        "large" => 6.0 / 5.0,
+        "x-large" => 3.0 / 2.0,
+        "xx-large" => 2.0,
+        "xxx-large" => 3.0,
+        "larger" => return Some(parent * 1.2),
+        "smaller" => return Some(parent / 1.2),
+        _ => return None,
+    };
+    Some(MEDIUM_FONT_SIZE * scale)
+}
+
+/// The computed `font-weight`, a number. `bolder` and `lighter` are relative to the
+/// parent's weight, following the table in CSS Fonts.
+pub fn font_weight(value: &Value, parent: f32) -> f32 {
+    match *value {
+        Value::Number(n) if (1.0..=1000.0).contains(&n) => n,
+        Value::Keyword(ref k) if k == "bold" => 700.0,
+        Value::Keyword(ref k) if k == "bolder" => match parent {
+            p if p < 350.0 => 400.0,
+            p if p < 550.0 => 700.0,
+            p => p.max(900.0),
+        },
+        Value::Keyword(ref k) if k == "lighter" => match parent {
+            p if p < 550.0 => p.min(100.0),
+            p if p < 750.0 => 400.0,
+            _ => 700.0,
+        },
+        _ => 400.0,
+    }
+}
+
+/// A family is a string, or a sequence of identifiers joined by spaces.
+fn font_families(value: Option<&Value>) -> Vec<String> {
+    let family = |value: &Value| match *value {
+        Value::Keyword(ref name) | Value::Str(ref name) => Some(name.clone()),
+        Value::List(ref words) => words.iter()
+            .map(|w| match *w {
+                Value::Keyword(ref word) => Some(word.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|words| words.join(" ")),
+        _ => None,
+    };
+    match value {
+        Some(Value::CommaList(families)) => families.iter().filter_map(family).collect(),
+        Some(value) => family(value).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+#[test]
+fn typed_computed_style() {
+    let root = crate::parser::parse("<div><p><b>x</b></p></div>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        div { width: 50%; margin: 0 auto; border: thin solid; color: #ff0000; font: italic 20px/1.5 "Open Sans", serif; }
+        p { border-left: 2px dashed #0000ff; padding: 1em; background-color: currentcolor; height: calc(10px + 1vh); }
+    "#.to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let (div, p) = (styled.style(), styled.children[0].style());
+    let b = styled.children[0].children[0].style();
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+
+    assert_eq!(LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Length(50.0, Unit::Percent)), div.box_model.width);
+    assert_eq!(LengthPercentageOrAuto::Auto, div.box_model.margin.left);
+    assert_eq!((LengthPercentage::Length(1.0, Unit::Px), LineStyle::Solid), (div.border.width.top.clone(), div.border.style.top));
+    assert_eq!(red, div.border.color.top);
+    assert_eq!((20.0, FontStyle::Italic, LineHeight::Number(1.5)), (div.font.size, div.font.style, div.font.line_height));
+    assert_eq!(vec!["Open Sans", "serif"], div.font.family);
+
+    assert_eq!(Color { r: 0, g: 0, b: 255, a: 255 }, p.border.color.left);
+    assert_eq!(LineStyle::None, p.border.style.top);
+    assert_eq!(LengthPercentage::Length(20.0, Unit::Px), p.box_model.padding.right);
+    assert_eq!((red, red), (p.text.color, p.background.color));
+    assert!(matches!(p.box_model.height, LengthPercentageOrAuto::LengthPercentage(LengthPercentage::Calc(_))));
+    // `bolder` is relative to the inherited weight.
+    assert_eq!((400, 700), (p.font.weight, b.font.weight));
+}
+
+#[test]
+fn font_properties() {
+    let root = crate::parser::parse("<div><p><em><b>x</b></em></p><h1></h1></div>".to_string());
+    let stylesheet = crate::css::parse("
+        div { font-size: x-large; line-height: 150%; font-family: Georgia, 'Times New Roman', serif; }
+        p { font-size: smaller; font-weight: 300; }
+        em { font-size: 2em; line-height: 1.5em; font-weight: lighter; }
+        b { font-size: 50%; line-height: 2; font-weight: bolder; }
+        h1 { font: bold xx-large/normal sans-serif; }
+    ".to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let div = styled.style();
+    let p = styled.children[0].style();
+    let em = styled.children[0].children[0].style();
+    let b = styled.children[0].children[0].children[0].style();
+    let h1 = styled.children[1].style();
+
+    assert_eq!((24.0, LineHeight::Length(36.0)), (div.font.size, div.font.line_height));
+    assert_eq!(vec!["Georgia", "Times New Roman", "serif"], div.font.family);
+    // A percentage line height is inherited as a length, not recomputed for each element.
+    assert_eq!((20.0, LineHeight::Length(36.0), 300), (p.font.size, p.font.line_height, p.font.weight));
+    assert_eq!((40.0, 60.0, 100), (em.font.size, em.font.used_line_height(), em.font.weight));
+    assert_eq!((20.0, 40.0, 400), (b.font.size, b.font.used_line_height(), b.font.weight));
+    assert_eq!((32.0, LineHeight::Normal, 700), (h1.font.size, h1.font.line_height, h1.font.weight));
+    assert_eq!(vec!["sans-serif"], h1.font.family);
+    assert_eq!(38.4, h1.font.used_line_height());
+}
+
+#[test]
+fn font_relative_units() {
+    let root = crate::parser::parse("<div><p></p></div>".to_string());
+    let stylesheet = crate::css::parse("
+        div { font-size: 20px; margin-left: 2ex; padding-left: 1ch; }
+        p { font-size: 2ex; }
+    ".to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let div = styled.style();
+    let p = styled.children[0].style();
+
+    // `ex` is the x-height of the selected font and `ch` the advance of its "0".
+    let font = crate::font::select(&div.font);
+    let x_height = font.metrics(20.0).x_height;
+    assert_ne!(10.0, x_height);
+    let px = |px| LengthPercentage::Length(px, Unit::Px);
+    assert_eq!(LengthPercentageOrAuto::LengthPercentage(px(2.0 * x_height)), div.box_model.margin.left);
+    assert_eq!(px(font.zero_advance(20.0)), div.box_model.padding.left);
+    // In `font-size`, they are relative to the parent's font.
+    assert_eq!(2.0 * x_height, p.font.size);
+}
+
+#[test]
+fn display_values() {
+    let parse = |source: &str| -> Option<Display> {
+        let sheet = crate::css::parse(format!("a {{ display: {source}; }}"));
+        sheet.rules[0].as_style().unwrap().declarations.first().and_then(|d| Display::parse(&d.value))
+    };
+    assert_eq!(Some(Display::BLOCK), parse("block"));
+    assert_eq!(Some(Display::BLOCK), parse("block flow"));
+    assert_eq!(Some(Display::INLINE), parse("inline"));
+    assert_eq!(Some(Display::INLINE_BLOCK), parse("inline-block"));
+    assert_eq!(Some(Display::INLINE_BLOCK), parse("flow-root inline"));
+    assert_eq!(Some(Display::FLOW_ROOT), parse("flow-root"));
+    assert_eq!(Some(Display::LIST_ITEM), parse("list-item"));
+    assert_eq!(Some(Display::LIST_ITEM), parse("flow list-item block"));
+    assert_eq!(Some(Display::Contents), parse("contents"));
+    assert_eq!(None, parse("block inline"));
+    assert_eq!(None, parse("contents block"));
+}
@@ -0,0 +1,218 @@
+use crate::css::Value;
+use crate::dom::ElementData;
+use crate::style::PropertyMap;
+
+/// A single counter instance, created by `counter-reset` or implicitly by
+/// `counter-increment`/`counter-set` when no instance of that name is in scope.
+struct Counter {
+    name: String,
+    value: i32,
+    /// Nesting depth of the element that created the counter.
+    depth: usize,
+}
+
+/// The CSS counters in scope at the current point of a document-order traversal.
+///
+/// A counter created by an element is visible to that element, its descendants,
+/// and its following siblings and their descendants.
+#[derive(Default)]
+pub struct Counters {
+    stack: Vec<Counter>,
+    depth: usize,
+}
+
+impl Counters {
+    /// Enter the children of the element currently being styled. Returns a mark
+    /// to pass to `leave_children` once all of them have been styled.
+    pub fn enter_children(&mut self) -> usize {
+        self.depth += 1;
+        self.stack.len()
+    }
+
+    /// Drop the counters created by the children of an element.
+    pub fn leave_children(&mut self, mark: usize) {
+        self.depth -= 1;
+        self.stack.truncate(mark);
+    }
+
+    /// Apply `counter-reset`, `counter-set` and `counter-increment`, in that order.
+    pub fn update(&mut self, values: &PropertyMap) {
+        for (name, value) in counter_list(values.get("counter-reset"), 0) {
+            self.reset(name, value);
+        }
+        for (name, value) in counter_list(values.get("counter-set"), 0) {
+            self.instance(&name).value = value;
+        }
+        for (name, value) in counter_list(values.get("counter-increment"), 1) {
+            self.instance(&name).value += value;
+        }
+    }
+
+    /// List items increment the `list-item` counter, unless their `counter-increment`
+    /// names it explicitly. Call after `update`.
+    pub fn increment_list_item(&mut self, values: &PropertyMap) {
+        if !counter_list(values.get("counter-increment"), 1).iter().any(|(name, _)| name == "list-item") {
+            self.instance("list-item").value += 1;
+        }
+    }
+
+    fn reset(&mut self, name: String, value: i32) {
+        // A reset replaces a counter of the same name created by a preceding sibling.
+        if let Some(counter) = self.stack.iter_mut().rev().find(|c| c.name == name) {
+            if counter.depth == self.depth {
+                counter.value = value;
+                return;
+            }
+        }
+        self.stack.push(Counter { name, value, depth: self.depth });
+    }
+
+    fn instance(&mut self, name: &str) -> &mut Counter {
+        match self.stack.iter().rposition(|c| c.name == name) {
+            Some(i) => &mut self.stack[i],
+            None => {
+                self.stack.push(Counter { name: name.to_string(), value: 0, depth: self.depth });
+                self.stack.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// The value of the innermost counter called `name`, or 0 if none is in scope.
+    pub fn value(&self, name: &str) -> i32 {
+        self.stack.iter().rev().find(|c| c.name == name).map_or(0, |c| c.value)
+    }
+
+    /// The values of all counters called `name`, outermost first.
+    pub fn values(&self, name: &str) -> Vec<i32> {
+        self.stack.iter().filter(|c| c.name == name).map(|c| c.value).collect()
+    }
+}
+
+/// Parse `<name> <integer>?` pairs, as used by the counter properties.
+fn counter_list(value: Option<&Value>, default: i32) -> Vec<(String, i32)> {
+    let items = match value {
+        Some(Value::List(items)) => items.as_slice(),
+        Some(value) => std::slice::from_ref(value),
+        None => &[],
+    };
+    let mut counters: Vec<(String, i32)> = Vec::new();
+    for item in items {
+        match *item {
+            Value::Keyword(ref name) if name != "none" => counters.push((name.clone(), default)),
+            Value::Number(n) => if let Some(last) = counters.last_mut() {
+                last.1 = n as i32;
+            },
+            _ => {}
+        }
+    }
+    counters
+}
+
+/// Evaluate the `content` property of a pseudo-element into its generated text.
+/// Returns `None` when no box should be generated (`none`, `normal`, or no value).
+pub fn generate_content(values: &PropertyMap, elem: &ElementData, counters: &Counters) -> Option<String> {
+    let items = match values.get("content")? {
+        Value::Keyword(k) if k == "none" || k == "normal" => return None,
+        Value::List(items) => items.as_slice(),
+        value => std::slice::from_ref(value),
+    };
+    let mut text = String::new();
+    for item in items {
+        match *item {
+            Value::Str(ref s) => text.push_str(s),
+            Value::Function(ref name, ref args) => match (&**name, args.as_slice()) {
+                ("attr", [Value::Keyword(attr), ..]) => {
+                    text.push_str(elem.attributes.get(attr).map_or("", |v| v));
+                }
+                ("counter", [Value::Keyword(counter), rest @ ..]) => {
+                    text.push_str(&format_counter(counters.value(counter), counter_style(rest.first())));
+                }
+                ("counters", [Value::Keyword(counter), Value::Str(separator), rest @ ..]) => {
+                    let style = counter_style(rest.first());
+                    let values: Vec<String> = counters.values(counter).iter()
+                        .map(|&v| format_counter(v, style))
+                        .collect();
+                    text.push_str(&values.join(separator));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    Some(text)
+}
+
+/// The text of a list item's `::marker`: its `content` if that isn't `normal`, or
+/// else the list item's `list-style-type` followed by a suffix. Returns `None` when
+/// the marker generates no box.
+pub fn marker_content(values: &PropertyMap, elem: &ElementData, counters: &Counters) -> Option<String> {
+    match values.get("content") {
+        None => {}
+        Some(Value::Keyword(k)) if k == "normal" => {}
+        Some(_) => return generate_content(values, elem, counters),
+    }
+    let style = counter_style(values.get("list-style-type"));
+    match style {
+        "none" => None,
+        "disc" | "circle" | "square" => Some(format!("{} ", format_counter(0, style))),
+        _ => Some(format!("{}. ", format_counter(counters.value("list-item"), style))),
+    }
+}
+
+fn counter_style(value: Option<&Value>) -> &str {
+    match value {
+        Some(Value::Keyword(style)) => style,
+        _ => "decimal",
+    }
+}
+
+/// Render a counter value in one of the predefined counter styles.
+pub fn format_counter(value: i32, style: &str) -> String {
+    match style {
+        "none" => String::new(),
+        "disc" => "\u{2022}".to_string(),
+        "circle" => "\u{25E6}".to_string(),
+        "square" => "\u{25AA}".to_string(),
+        "decimal-leading-zero" if (0..10).contains(&value) => format!("0{value}"),
+        "lower-alpha" | "lower-latin" if value > 0 => alphabetic(value),
+        "upper-alpha" | "upper-latin" if value > 0 => alphabetic(value).to_ascii_uppercase(),
+        "lower-roman" if (1..4000).contains(&value) => roman(value),
+        "upper-roman" if (1..4000).contains(&value) => roman(value).to_ascii_uppercase(),
+        _ => value.to_string(),
+    }
+}
+
+fn alphabetic(mut value: i32) -> String {
+    let mut result = Vec::new();
+    while value > 0 {
+        value -= 1;
+        result.push(b'a' + (value % 26) as u8);
+        value /= 26;
+    }
+    result.reverse();
+    String::from_utf8(result).unwrap()
+}
+
+fn roman(mut value: i32) -> String {
+    const NUMERALS: [(i32, &str); 13] = [
+        (1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"),
+        (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i"),
+    ];
+    let mut result = String::new();
+    for &(n, numeral) in NUMERALS.iter() {
+        while value >= n {
+            result.push_str(numeral);
+            value -= n;
+        }
+    }
+    result
+}
+
+#[test]
+fn counter_styles() {
+    assert_eq!("7", format_counter(7, "decimal"));
+    assert_eq!("07", format_counter(7, "decimal-leading-zero"));
+    assert_eq!("ab", format_counter(28, "lower-alpha"));
+    assert_eq!("MCMXCIV", format_counter(1994, "upper-roman"));
+    assert_eq!("-3", format_counter(-3, "lower-roman"));
+}
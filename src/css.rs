@@ -1,54 +1,479 @@
-use std::collections::HashMap;
-use lazy_static::lazy_static;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::color::{self, Component};
+use crate::media::{self, Comparison, Device, MediaList, MediaQuery, MediaType, MediaCondition, MediaFeature};
+use crate::properties;
+use crate::supports::SupportsCondition;
 
 #[derive(Debug, PartialEq)]
 pub struct Stylesheet {
-    pub rules: Vec<Rule>,
+    pub rules: Vec<CssRule>,
+    pub origin: Origin,
+}
+
+impl Stylesheet {
+    /// The style rules that apply on `device`, in source order, taken from inside
+    /// any conditional rules whose conditions hold.
+    pub fn style_rules(&self, device: &Device) -> Vec<&Rule> {
+        self.layered_style_rules(device, &mut Vec::new()).into_iter().map(|r| r.rule).collect()
+    }
+
+    /// Like `style_rules`, also giving the cascade layer of each rule. Every layer is
+    /// added to `layers` the first time it is declared, after its parent layers.
+    /// Anonymous layers get names that can't clash with those of named layers.
+    pub fn layered_style_rules<'a>(&'a self, device: &Device, layers: &mut Vec<LayerName>) -> Vec<LayeredRule<'a>> {
+        let mut rules = Vec::new();
+        collect_style_rules(&self.rules, device, &Vec::new(), layers, &mut rules);
+        rules
+    }
+
+    /// The URLs of the `@import` rules through which `rule` is part of this stylesheet,
+    /// outermost first. It is empty for the stylesheet's own rules, and `None` if `rule`
+    /// isn't part of it.
+    pub fn import_chain(&self, rule: &Rule) -> Option<Vec<String>> {
+        import_chain(&self.rules, rule)
+    }
+}
+
+/// A style rule with the cascade layer it belongs to; the empty name is outside any layer.
+pub struct LayeredRule<'a> {
+    pub layer: LayerName,
+    pub rule: &'a Rule,
+}
+
+/// The full name of a cascade layer, such as `["components", "buttons"]` for `components.buttons`.
+pub type LayerName = Vec<String>;
+
+fn collect_style_rules<'a>(rules: &'a [CssRule], device: &Device, layer: &LayerName,
+                           layers: &mut Vec<LayerName>, result: &mut Vec<LayeredRule<'a>>) {
+    for rule in rules {
+        match *rule {
+            CssRule::Style(ref rule) => result.push(LayeredRule { layer: layer.clone(), rule }),
+            CssRule::Media(ref media, ref rules) => if media.matches(device) {
+                collect_style_rules(rules, device, layer, layers, result);
+            },
+            CssRule::Supports(ref condition, ref rules) => if condition.matches() {
+                collect_style_rules(rules, device, layer, layers, result);
+            },
+            CssRule::Import(ref import) => match import.stylesheet {
+                Some(ref stylesheet) if import.media.matches(device) =>
+                    collect_style_rules(&stylesheet.rules, device, layer, layers, result),
+                _ => {}
+            },
+            CssRule::Layer(ref name, ref rules) => {
+                let name = match *name {
+                    Some(ref name) => name.clone(),
+                    None => vec![format!("<anonymous {}>", layers.len())],
+                };
+                let name = declare_layer(layers, [&layer[..], &name[..]].concat());
+                collect_style_rules(rules, device, &name, layers, result);
+            }
+            CssRule::LayerStatement(ref names) => for name in names {
+                declare_layer(layers, [&layer[..], &name[..]].concat());
+            },
+        }
+    }
+}
+
+fn import_chain(rules: &[CssRule], target: &Rule) -> Option<Vec<String>> {
+    rules.iter().find_map(|rule| match *rule {
+        CssRule::Style(ref rule) => std::ptr::eq(rule, target).then(Vec::new),
+        CssRule::Media(_, ref rules) | CssRule::Supports(_, ref rules) | CssRule::Layer(_, ref rules) =>
+            import_chain(rules, target),
+        CssRule::Import(ref import) => {
+            let mut chain = import_chain(&import.stylesheet.as_ref()?.rules, target)?;
+            chain.insert(0, import.url.clone());
+            Some(chain)
+        }
+        CssRule::LayerStatement(_) => None,
+    })
+}
+
+/// Add a layer and its parent layers to `layers`, unless they are already there.
+fn declare_layer(layers: &mut Vec<LayerName>, name: LayerName) -> LayerName {
+    for depth in 1..=name.len() {
+        if !layers.iter().any(|l| *l == name[..depth]) {
+            layers.push(name[..depth].to_vec());
+        }
+    }
+    name
+}
+
+/// Where a stylesheet comes from. Later origins win for normal declarations,
+/// and the order is reversed for `!important` ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
+}
+
+/// A top-level or nested rule of a stylesheet.
+#[derive(Debug, PartialEq)]
+pub enum CssRule {
+    Style(Rule),
+    /// `@media <media-query-list> { <rules> }`
+    Media(MediaList, Vec<CssRule>),
+    /// `@supports <condition> { <rules> }`
+    Supports(SupportsCondition, Vec<CssRule>),
+    /// `@import <url> <media-query-list>;`
+    Import(ImportRule),
+    /// `@layer <name>? { <rules> }`, where a missing name makes an anonymous layer.
+    Layer(Option<LayerName>, Vec<CssRule>),
+    /// `@layer <name>#;`, which only fixes the order of the layers.
+    LayerStatement(Vec<LayerName>),
 }
 
 #[derive(Debug, PartialEq)]
+pub struct ImportRule {
+    pub url: String,
+    pub media: MediaList,
+    /// The imported stylesheet, once it has been loaded by `load`. Imports that
+    /// couldn't be loaded, or that would form a cycle, stay empty.
+    pub stylesheet: Option<Stylesheet>,
+}
+
+impl CssRule {
+    pub fn as_style(&self) -> Option<&Rule> {
+        match *self {
+            CssRule::Style(ref rule) => Some(rule),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    /// Where the rule starts in the source it was parsed from.
+    pub location: SourceLocation,
 }
 
-#[derive(Debug, PartialEq)]
+/// Rules with the same selectors and declarations are equal wherever they appear.
+impl PartialEq for Rule {
+    fn eq(&self, other: &Rule) -> bool {
+        self.selectors == other.selectors && self.declarations == other.declarations
+    }
+}
+
+/// A line and column in a stylesheet's source, both starting at 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Selector {
     Simple(SimpleSelector),
+    /// `left <combinator> right`, where `right` is the subject of the selector.
+    Complex(Box<Selector>, Combinator, SimpleSelector),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Combinator {
+    /// `a b`
+    Descendant,
+    /// `a > b`
+    Child,
+    /// `a + b`
+    NextSibling,
+    /// `a ~ b`
+    SubsequentSibling,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    pub pseudo_element: Option<PseudoElement>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PseudoClass {
+    Root,
+    Empty,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+    /// `:nth-child(An+B [of S]?)`
+    NthChild(Nth, Vec<Selector>),
+    /// `:nth-last-child(An+B [of S]?)`
+    NthLastChild(Nth, Vec<Selector>),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+    Not(Vec<Selector>),
+    Is(Vec<Selector>),
+    Where(Vec<Selector>),
+    Has(Vec<RelativeSelector>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PseudoElement {
+    Before,
+    After,
+    /// The marker box of a list item.
+    Marker,
+}
+
+/// The `An+B` microsyntax used by the `:nth-*` pseudo-classes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Nth {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl Nth {
+    /// Whether the 1-based `index` is of the form `An+B` for some n >= 0.
+    pub fn matches(&self, index: i32) -> bool {
+        if self.a == 0 {
+            return index == self.b;
+        }
+        let n = index - self.b;
+        n % self.a == 0 && n / self.a >= 0
+    }
+}
+
+/// An argument of `:has()`, anchored at the element being matched.
+/// `:has(> img)` is a `Child` combinator followed by `img`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelativeSelector {
+    pub combinator: Combinator,
+    pub selector: Selector,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    pub important: bool,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Keyword(String),
     Length(f32, Unit),
+    Number(f32),
     ColorValue(Color),
+    Str(String),
+    /// A functional notation such as `attr(title)`; arguments are the comma-separated parts.
+    Function(String, Vec<Value>),
+    /// Space-separated component values, e.g. `"#" counter(item)`.
+    List(Vec<Value>),
+    /// Comma-separated values, e.g. `Georgia, serif`.
+    CommaList(Vec<Value>),
+    /// The `/` delimiter, as in `font: 12px/1.5 serif`.
+    Slash,
+    /// A `calc()`, `min()`, `max()` or `clamp()` expression.
+    Calc(Box<Calc>),
+    /// A number with a unit this engine doesn't implement, such as `1fr`. No property accepts it.
+    Dimension(f32, String),
+    /// The value of a custom property such as `--accent`: its token stream, kept as source text.
+    Tokens(String),
+    /// A value that references `var()`, with the property it was declared for (possibly a
+    /// shorthand) and its token stream. It is parsed once variables are substituted at
+    /// computed-value time.
+    Pending(String, String),
+}
+
+/// The expression tree of a math function, kept until the lengths it refers to are known.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Calc {
+    Number(f32),
+    Length(f32, Unit),
+    Sum(Vec<Calc>),
+    Negate(Box<Calc>),
+    Product(Box<Calc>, Box<Calc>),
+    Quotient(Box<Calc>, Box<Calc>),
+    Min(Vec<Calc>),
+    Max(Vec<Calc>),
+    /// `clamp(min, value, max)`
+    Clamp(Box<Calc>, Box<Calc>, Box<Calc>),
+}
+
+impl Calc {
+    /// Evaluate the expression, resolving every length to px.
+    pub fn to_px(&self, context: &LengthContext) -> f32 {
+        match *self {
+            Calc::Number(n) => n,
+            Calc::Length(v, unit) => unit.to_px(v, context),
+            Calc::Sum(ref terms) => terms.iter().map(|t| t.to_px(context)).sum(),
+            Calc::Negate(ref a) => -a.to_px(context),
+            Calc::Product(ref a, ref b) => a.to_px(context) * b.to_px(context),
+            Calc::Quotient(ref a, ref b) => a.to_px(context) / b.to_px(context),
+            Calc::Min(ref args) => args.iter().map(|a| a.to_px(context)).fold(f32::INFINITY, f32::min),
+            Calc::Max(ref args) => args.iter().map(|a| a.to_px(context)).fold(f32::NEG_INFINITY, f32::max),
+            Calc::Clamp(ref min, ref value, ref max) =>
+                value.to_px(context).min(max.to_px(context)).max(min.to_px(context)),
+        }
+    }
+
+    /// Rebuild the expression with every length leaf replaced by `f(value, unit)`.
+    pub fn map_lengths(&self, f: &dyn Fn(f32, Unit) -> Calc) -> Calc {
+        let map_all = |args: &[Calc]| args.iter().map(|a| a.map_lengths(f)).collect();
+        let map = |a: &Calc| Box::new(a.map_lengths(f));
+        match *self {
+            Calc::Number(n) => Calc::Number(n),
+            Calc::Length(v, unit) => f(v, unit),
+            Calc::Sum(ref terms) => Calc::Sum(map_all(terms)),
+            Calc::Negate(ref a) => Calc::Negate(map(a)),
+            Calc::Product(ref a, ref b) => Calc::Product(map(a), map(b)),
+            Calc::Quotient(ref a, ref b) => Calc::Quotient(map(a), map(b)),
+            Calc::Min(ref args) => Calc::Min(map_all(args)),
+            Calc::Max(ref args) => Calc::Max(map_all(args)),
+            Calc::Clamp(ref min, ref value, ref max) => Calc::Clamp(map(min), map(value), map(max)),
+        }
+    }
+
+    /// Whether any length in the expression uses a unit for which `test` holds.
+    pub fn any_unit(&self, test: &dyn Fn(Unit) -> bool) -> bool {
+        match *self {
+            Calc::Number(_) => false,
+            Calc::Length(_, unit) => test(unit),
+            Calc::Negate(ref a) => a.any_unit(test),
+            Calc::Product(ref a, ref b) | Calc::Quotient(ref a, ref b) => a.any_unit(test) || b.any_unit(test),
+            Calc::Sum(ref args) | Calc::Min(ref args) | Calc::Max(ref args) => args.iter().any(|a| a.any_unit(test)),
+            Calc::Clamp(ref min, ref value, ref max) =>
+                min.any_unit(test) || value.any_unit(test) || max.any_unit(test),
+        }
+    }
 }
 
 impl Value {
-    pub fn to_px(&self) -> f32 {
+    /// Resolve a length to px. Anything that isn't a length counts as zero.
+    pub fn to_px(&self, context: &LengthContext) -> f32 {
         match *self {
-            Value::Length(f, Unit::Px) => f,
+            Value::Length(f, unit) => unit.to_px(f, context),
+            Value::Calc(ref calc) => calc.to_px(context),
             _ => 0.0
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Unit {
     Px,
+    // Font-relative
+    Em,
+    Rem,
+    Ex,
+    Ch,
+    // Viewport-relative
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+    Percent,
+    // Absolute
+    Pt,
+    Pc,
+    In,
+    Cm,
+    Mm,
+}
+
+/// What relative lengths are resolved against.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LengthContext {
+    /// The computed font size of the element, for `em`, `ex` and `ch`.
+    pub font_size: f32,
+    /// The computed font size of the root element, for `rem`.
+    pub root_font_size: f32,
+    /// The length that `100%` refers to, usually the containing block's width.
+    pub percentage_base: f32,
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+    /// The x-height of the element's font, for `ex`. Half the font size if unknown.
+    pub x_height: Option<f32>,
+    /// The advance of "0" in the element's font, for `ch`. Half the font size if unknown.
+    pub zero_advance: Option<f32>,
+}
+
+impl Unit {
+    /// How the unit is written, e.g. `px` or `%`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::Px => "px",
+            Unit::Em => "em",
+            Unit::Rem => "rem",
+            Unit::Ex => "ex",
+            Unit::Ch => "ch",
+            Unit::Vw => "vw",
+            Unit::Vh => "vh",
+            Unit::Vmin => "vmin",
+            Unit::Vmax => "vmax",
+            Unit::Percent => "%",
+            Unit::Pt => "pt",
+            Unit::Pc => "pc",
+            Unit::In => "in",
+            Unit::Cm => "cm",
+            Unit::Mm => "mm",
+        }
+    }
+
+    /// The unit written as `name`, ignoring case. `%` isn't an identifier, so it's not included.
+    pub fn from_name(name: &str) -> Option<Unit> {
+        Some(match &*name.to_ascii_lowercase() {
+            "px" => Unit::Px,
+            "em" => Unit::Em,
+            "rem" => Unit::Rem,
+            "ex" => Unit::Ex,
+            "ch" => Unit::Ch,
+            "vw" => Unit::Vw,
+            "vh" => Unit::Vh,
+            "vmin" => Unit::Vmin,
+            "vmax" => Unit::Vmax,
+            "pt" => Unit::Pt,
+            "pc" => Unit::Pc,
+            "in" => Unit::In,
+            "cm" => Unit::Cm,
+            "mm" => Unit::Mm,
+            _ => return None,
+        })
+    }
+
+    /// The size of one unit in px, for units that don't depend on any context.
+    pub fn absolute_px(self) -> Option<f32> {
+        match self {
+            Unit::Px => Some(1.0),
+            Unit::In => Some(96.0),
+            Unit::Cm => Some(96.0 / 2.54),
+            Unit::Mm => Some(96.0 / 25.4),
+            Unit::Pt => Some(96.0 / 72.0),
+            Unit::Pc => Some(16.0),
+            _ => None,
+        }
+    }
+
+    pub fn is_font_relative(self) -> bool {
+        matches!(self, Unit::Em | Unit::Rem | Unit::Ex | Unit::Ch)
+    }
+
+    pub fn to_px(self, value: f32, context: &LengthContext) -> f32 {
+        if let Some(px) = self.absolute_px() {
+            return value * px;
+        }
+        let LengthContext { font_size, root_font_size, percentage_base, viewport_width, viewport_height, .. } = *context;
+        value * match self {
+            Unit::Em => font_size,
+            Unit::Rem => root_font_size,
+            Unit::Ex => context.x_height.unwrap_or(font_size / 2.0),
+            Unit::Ch => context.zero_advance.unwrap_or(font_size / 2.0),
+            Unit::Vw => viewport_width / 100.0,
+            Unit::Vh => viewport_height / 100.0,
+            Unit::Vmin => viewport_width.min(viewport_height) / 100.0,
+            Unit::Vmax => viewport_width.max(viewport_height) / 100.0,
+            Unit::Percent => percentage_base / 100.0,
+            _ => unreachable!(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -61,181 +486,1300 @@ pub struct Color {
 
 impl Copy for Color {}
 
-lazy_static! {
-    static ref COLOR_NAME: HashMap<&'static str, Vec<u8>> = {
-        let mut map = HashMap::new();
-        map.insert("red", vec![255, 0, 0]);
-        map.insert("blue", vec![0, 0, 255]);
-        map
-    };
+/// Parse a declaration value on its own, e.g. after `var()` substitution.
+/// Returns `None` for an empty or invalid value.
+pub fn parse_value(source: String) -> Option<Value> {
+    let mut parser = Parser::new(source);
+    parser.consume_whitespace();
+    if parser.eof() {
+        return None;
+    }
+    let value = parser.parse_comma_separated_values();
+    parser.consume_whitespace();
+    match parser.eof() && !parser.invalid {
+        true => Some(value),
+        false => None,
+    }
 }
 
+/// Parse an author stylesheet.
 pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser { pos: 0, input: source };
-    Stylesheet { rules: parser.parse_rules() }
+    parse_with_origin(source, Origin::Author)
+}
+
+pub fn parse_with_origin(source: String, origin: Origin) -> Stylesheet {
+    let mut parser = Parser::new(source);
+    Stylesheet { rules: parser.parse_rules(true), origin }
+}
+
+/// Parse the stylesheet in the file at `path`, then load its `@import`s, resolving
+/// their URLs relative to the file that contains them.
+pub fn load(path: &Path, origin: Origin) -> io::Result<Stylesheet> {
+    let mut stylesheet = parse_with_origin(fs::read_to_string(path)?, origin);
+    let path = path.canonicalize()?;
+    load_imports(&mut stylesheet.rules, &path, origin, &mut vec![path.clone()]);
+    Ok(stylesheet)
+}
+
+/// `loading` holds the files currently being loaded, so that an import cycle is skipped.
+fn load_imports(rules: &mut [CssRule], path: &Path, origin: Origin, loading: &mut Vec<PathBuf>) {
+    let base = path.parent().unwrap_or(Path::new(""));
+    for rule in rules {
+        let import = match *rule {
+            CssRule::Import(ref mut import) => import,
+            _ => continue,
+        };
+        let Ok(path) = base.join(import.url.trim_start_matches("file://")).canonicalize() else {
+            continue;
+        };
+        if loading.contains(&path) {
+            continue;
+        }
+        let Ok(source) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let mut stylesheet = parse_with_origin(source, origin);
+        loading.push(path.clone());
+        load_imports(&mut stylesheet.rules, &path, origin, loading);
+        loading.pop();
+        import.stylesheet = Some(stylesheet);
+    }
 }
 
 struct Parser {
     pos: usize,
     input: String,
+    /// The location of a position already reached, to count lines and columns from.
+    last_location: (usize, SourceLocation),
+    /// Set when a value is invalid, such as `#ggg`, so that its declaration is dropped.
+    invalid: bool,
 }
 
 impl Parser {
-    fn parse_rules(&mut self) -> Vec<Rule> {
+    fn new(input: String) -> Parser {
+        Parser { pos: 0, input, last_location: (0, SourceLocation { line: 1, column: 1 }), invalid: false }
+    }
+
+    /// The location of the current position.
+    fn location(&mut self) -> SourceLocation {
+        let (mut pos, mut location) = self.last_location;
+        if pos > self.pos {
+            (pos, location) = (0, SourceLocation { line: 1, column: 1 });
+        }
+        for c in self.input[pos..self.pos].chars() {
+            match c {
+                '\n' => location = SourceLocation { line: location.line + 1, column: 1 },
+                _ => location.column += 1,
+            }
+        }
+        self.last_location = (self.pos, location);
+        location
+    }
+
+    /// Parse rules up to the end of the input, or up to the `}` closing a block.
+    fn parse_rules(&mut self, top_level: bool) -> Vec<CssRule> {
         let mut rules = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.eof() { break }
-            rules.push(self.parse_rule());
+            if self.eof() {
+                break;
+            }
+            if self.next_char() == '}' {
+                if !top_level {
+                    break;
+                }
+                // A stray `}` at the top level starts a rule that is invalid.
+                self.consume_char();
+                self.skip_rule();
+                continue;
+            }
+            if self.next_char() == '@' {
+                match self.parse_at_rule() {
+                    // `@import` is only valid at the top level, before any other rule.
+                    Some(CssRule::Import(_)) if !top_level ||
+                        !rules.iter().all(|r| matches!(r, CssRule::Import(_) | CssRule::LayerStatement(_))) => {}
+                    rule => rules.extend(rule),
+                }
+            } else {
+                rules.extend(self.parse_rule().map(CssRule::Style));
+            }
+        }
+        rules
+    }
+
+    /// Parse an at-rule. Unknown and invalid at-rules are skipped.
+    fn parse_at_rule(&mut self) -> Option<CssRule> {
+        assert_eq!(self.consume_char(), '@');
+        let name = self.parse_identifier().to_ascii_lowercase();
+        self.consume_whitespace();
+        let rule = match &*name {
+            "media" => self.parse_media_query_list()
+                .and_then(|media| Ok(CssRule::Media(media, self.parse_block()?))),
+            "supports" => self.parse_supports_condition()
+                .and_then(|condition| Ok(CssRule::Supports(condition, self.parse_block()?))),
+            "layer" => self.parse_layer(),
+            "import" => self.parse_import(),
+            _ => Err(format!("Unknown at-rule @{name}")),
+        };
+        if rule.is_err() {
+            self.skip_rule();
+        }
+        rule.ok()
+    }
+
+    /// `@layer <name>, ...;` or `@layer <name>? { <rules> }`, after the `@layer`.
+    fn parse_layer(&mut self) -> Result<CssRule, String> {
+        let mut names = Vec::new();
+        if !self.eof() && self.next_char() != '{' {
+            loop {
+                let mut name = vec![self.parse_identifier()];
+                while self.consume_if('.') {
+                    name.push(self.parse_identifier());
+                }
+                if name.iter().any(String::is_empty) {
+                    return Err("Expected a layer name".to_string());
+                }
+                names.push(name);
+                self.consume_whitespace();
+                if !self.consume_if(',') {
+                    break;
+                }
+                self.consume_whitespace();
+            }
+        }
+        if self.eof() || self.consume_if(';') {
+            return match names.is_empty() {
+                true => Err("Expected a layer name".to_string()),
+                false => Ok(CssRule::LayerStatement(names)),
+            };
+        }
+        if names.len() > 1 {
+            return Err("A layer block can only have one name".to_string());
+        }
+        self.parse_block().map(|rules| CssRule::Layer(names.pop(), rules))
+    }
+
+    /// `@import <url> <media-query-list>;`, after the `@import`.
+    fn parse_import(&mut self) -> Result<CssRule, String> {
+        self.invalid = false;
+        let url = match self.input[self.pos..].starts_with(['"', '\'']) {
+            true => Some(self.parse_string()),
+            false => match self.parse_value() {
+                Value::Function(ref name, ref args) if name == "url" => match args[..] {
+                    [Value::Str(ref url)] => Some(url.clone()),
+                    _ => None,
+                },
+                _ => None,
+            },
+        };
+        let url = url.filter(|_| !self.invalid).ok_or("Expected a URL in @import")?;
+        let media = self.parse_media_query_list()?;
+        if !self.eof() && !self.consume_if(';') {
+            return Err("Expected ; after @import".to_string());
+        }
+        Ok(CssRule::Import(ImportRule { url, media, stylesheet: None }))
+    }
+
+    /// Parse a `{ <rules> }` block. A block left open at the end of the input ends there.
+    fn parse_block(&mut self) -> Result<Vec<CssRule>, String> {
+        self.consume_whitespace();
+        if self.eof() || self.next_char() != '{' {
+            return Err("Expected {".to_string());
+        }
+        self.consume_char();
+        let rules = self.parse_rules(false);
+        if !self.eof() {
+            self.consume_char();
+        }
+        Ok(rules)
+    }
+
+    /// Skip the rest of a rule: up to a `;`, or past its block.
+    fn skip_rule(&mut self) {
+        let mut depth = 0;
+        while !self.eof() {
+            match self.consume_char() {
+                ';' if depth == 0 => break,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                '"' | '\'' => {
+                    self.pos -= 1;
+                    self.parse_string();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// `not <in-parens>`, or `<in-parens>` joined by `and` or by `or`.
+    fn parse_supports_condition(&mut self) -> Result<SupportsCondition, String> {
+        self.consume_whitespace();
+        if self.eof() {
+            return Err("Expected a supports condition".to_string());
+        }
+        if self.next_char() != '(' {
+            let start = self.pos;
+            match &*self.parse_identifier().to_ascii_lowercase() {
+                "not" => return Ok(SupportsCondition::Not(Box::new(self.parse_supports_in_parens()?))),
+                _ => {
+                    // A function such as `selector()`, which we don't evaluate.
+                    self.pos = start;
+                    return self.parse_supports_in_parens();
+                }
+            }
+        }
+        let mut operator = None;
+        let mut conditions = vec![self.parse_supports_in_parens()?];
+        loop {
+            self.consume_whitespace();
+            let start = self.pos;
+            let id = self.parse_identifier().to_ascii_lowercase();
+            if id != "and" && id != "or" {
+                self.pos = start;
+                break;
+            }
+            if operator.get_or_insert_with(|| id.clone()) != &id {
+                return Err("Cannot mix `and` and `or` in a supports condition".to_string());
+            }
+            conditions.push(self.parse_supports_in_parens()?);
+        }
+        Ok(match operator.as_deref() {
+            None => conditions.remove(0),
+            Some("and") => SupportsCondition::And(conditions),
+            _ => SupportsCondition::Or(conditions),
+        })
+    }
+
+    /// `( <condition> )`, `( <property>: <value> )`, or anything else in parentheses or
+    /// a function, which is kept as source text and evaluates to false.
+    fn parse_supports_in_parens(&mut self) -> Result<SupportsCondition, String> {
+        self.consume_whitespace();
+        let start = self.pos;
+        if self.eof() {
+            return Err("Expected ( in supports condition".to_string());
+        }
+        if self.next_char() == '(' {
+            self.consume_char();
+            self.consume_whitespace();
+            let inner = self.pos;
+            let nested = self.input[inner..].starts_with('(') || self.parse_identifier().eq_ignore_ascii_case("not");
+            self.pos = inner;
+            if nested {
+                let condition = self.parse_supports_condition()?;
+                self.consume_whitespace();
+                return match self.consume_if(')') {
+                    true => Ok(condition),
+                    false => Err("Expected ) in supports condition".to_string()),
+                };
+            }
+            let name = self.parse_identifier();
+            self.consume_whitespace();
+            if !name.is_empty() && !self.eof() && self.next_char() == ':' {
+                self.consume_char();
+                let end = self.closing_paren()?;
+                let value = self.input[self.pos..end].trim().to_string();
+                self.pos = end + 1;
+                return Ok(SupportsCondition::Declaration(name.to_ascii_lowercase(), value));
+            }
+        } else {
+            self.parse_identifier();
+            if !self.consume_if('(') {
+                return Err("Expected ( in supports condition".to_string());
+            }
+        }
+        self.pos = self.closing_paren()? + 1;
+        Ok(SupportsCondition::Unknown(self.input[start..self.pos].to_string()))
+    }
+
+    /// The position of the `)` that closes the parentheses the parser is in.
+    fn closing_paren(&self) -> Result<usize, String> {
+        let mut depth = 0;
+        let mut quote = None;
+        for (i, c) in self.input[self.pos..].char_indices() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') if depth == 0 => return Ok(self.pos + i),
+                (None, ')') => depth -= 1,
+                _ => {}
+            }
+        }
+        Err("Unclosed parenthesis".to_string())
+    }
+
+    /// Parse a comma-separated list of media queries, stopping before `{` or `;`.
+    fn parse_media_query_list(&mut self) -> Result<MediaList, String> {
+        let mut queries = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.next_char() == '{' || self.next_char() == ';' {
+                break;
+            }
+            queries.push(self.parse_media_query()?);
+            self.consume_whitespace();
+            if !self.eof() && !self.consume_if(',') && !matches!(self.next_char(), '{' | ';') {
+                return Err("Expected , between media queries".to_string());
+            }
+        }
+        Ok(MediaList(queries))
+    }
+
+    /// `[not | only]? <media-type> [and <condition-without-or>]?` or `<condition>`.
+    fn parse_media_query(&mut self) -> Result<MediaQuery, String> {
+        if self.next_char() == '(' {
+            return Ok(MediaQuery { negated: false, media_type: MediaType::All, condition: Some(self.parse_media_condition()?) });
+        }
+        let start = self.pos;
+        let mut negated = false;
+        let mut ident = self.parse_identifier().to_ascii_lowercase();
+        match &*ident {
+            "not" | "only" => {
+                self.consume_whitespace();
+                if self.next_char() == '(' {
+                    // `not (condition)` negates a condition rather than a media type.
+                    self.pos = start;
+                    return Ok(MediaQuery { negated: false, media_type: MediaType::All, condition: Some(self.parse_media_condition()?) });
+                }
+                negated = ident == "not";
+                ident = self.parse_identifier().to_ascii_lowercase();
+            }
+            _ => {}
+        }
+        if ident.is_empty() {
+            return Err("Expected a media type".to_string());
+        }
+        let media_type = match &*ident {
+            "all" => MediaType::All,
+            "screen" => MediaType::Screen,
+            "print" => MediaType::Print,
+            _ => MediaType::Unknown(ident),
+        };
+        self.consume_whitespace();
+        let start = self.pos;
+        let condition = match &*self.parse_identifier().to_ascii_lowercase() {
+            "and" => Some(self.parse_media_condition()?),
+            _ => {
+                self.pos = start;
+                None
+            }
+        };
+        Ok(MediaQuery { negated, media_type, condition })
+    }
+
+    /// `not <in-parens>`, or `<in-parens>` joined by `and` or by `or`.
+    fn parse_media_condition(&mut self) -> Result<MediaCondition, String> {
+        self.consume_whitespace();
+        if self.eof() || self.next_char() != '(' {
+            return match &*self.parse_identifier().to_ascii_lowercase() {
+                "not" => Ok(MediaCondition::Not(Box::new(self.parse_media_in_parens()?))),
+                id => Err(format!("Unexpected {id} in media condition"))
+            };
+        }
+        let first = self.parse_media_in_parens()?;
+        let mut operator = None;
+        let mut conditions = vec![first];
+        loop {
+            self.consume_whitespace();
+            let start = self.pos;
+            let id = self.parse_identifier().to_ascii_lowercase();
+            if id != "and" && id != "or" {
+                self.pos = start;
+                break;
+            }
+            if operator.get_or_insert_with(|| id.clone()) != &id {
+                return Err("Cannot mix `and` and `or` in a media condition".to_string());
+            }
+            conditions.push(self.parse_media_in_parens()?);
+        }
+        Ok(match operator.as_deref() {
+            None => conditions.remove(0),
+            Some("and") => MediaCondition::And(conditions),
+            _ => MediaCondition::Or(conditions),
+        })
+    }
+
+    /// `( <condition> )`, `( <feature> )`, or anything else in parentheses, which is kept
+    /// as source text and never matches.
+    fn parse_media_in_parens(&mut self) -> Result<MediaCondition, String> {
+        self.consume_whitespace();
+        let start = self.pos;
+        if !self.consume_if('(') {
+            return Err("Expected ( in media condition".to_string());
+        }
+        self.consume_whitespace();
+        if self.eof() {
+            return Err("Unclosed parenthesis".to_string());
+        }
+        let inner = self.pos;
+        let nested = self.next_char() == '(' || self.parse_identifier().eq_ignore_ascii_case("not");
+        self.pos = inner;
+        if nested {
+            let condition = self.parse_media_condition()?;
+            self.consume_whitespace();
+            return match self.consume_if(')') {
+                true => Ok(condition),
+                false => Err("Expected ) in media condition".to_string()),
+            };
+        }
+        let feature = self.parse_media_feature();
+        self.consume_whitespace();
+        match feature {
+            Some(feature) if !self.eof() && self.next_char() == ')' => {
+                self.consume_char();
+                Ok(MediaCondition::Feature(feature))
+            }
+            _ => {
+                self.pos = inner;
+                self.pos = self.closing_paren()? + 1;
+                Ok(MediaCondition::Unknown(self.input[start..self.pos].to_string()))
+            }
+        }
+    }
+
+    /// `<name>`, `<name>: <value>`, `<name> <op> <value>`, `<value> <op> <name>`
+    /// or `<value> <op> <name> <op> <value>`.
+    fn parse_media_feature(&mut self) -> Option<MediaFeature> {
+        if self.next_char().is_ascii_alphabetic() || (self.next_char() == '-' && !self.at_number()) {
+            let name = self.parse_identifier().to_ascii_lowercase();
+            self.consume_whitespace();
+            if self.eof() {
+                return None;
+            }
+            return match self.next_char() {
+                ')' => Some(MediaFeature::Discrete(name, None)),
+                ':' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    let value = self.parse_value();
+                    let range = |name: &str, comparison| MediaFeature::Range(name.to_string(), vec![(comparison, value.clone())]);
+                    if let Some(name) = name.strip_prefix("min-").filter(|n| media::is_range_feature(n)) {
+                        Some(range(name, Comparison::GreaterOrEqual))
+                    } else if let Some(name) = name.strip_prefix("max-").filter(|n| media::is_range_feature(n)) {
+                        Some(range(name, Comparison::LessOrEqual))
+                    } else if media::is_range_feature(&name) {
+                        Some(range(&name, Comparison::Equal))
+                    } else {
+                        Some(MediaFeature::Discrete(name, Some(value)))
+                    }
+                }
+                _ => {
+                    let comparison = self.parse_comparison()?;
+                    self.consume_whitespace();
+                    Some(MediaFeature::Range(name, vec![(comparison, self.parse_value())]))
+                }
+            };
+        }
+        let low = self.parse_value();
+        self.consume_whitespace();
+        let first = self.parse_comparison()?.flip();
+        self.consume_whitespace();
+        let name = self.parse_identifier().to_ascii_lowercase();
+        let mut comparisons = vec![(first, low)];
+        self.consume_whitespace();
+        if self.eof() || self.next_char() != ')' {
+            let second = self.parse_comparison()?;
+            self.consume_whitespace();
+            comparisons.push((second, self.parse_value()));
+        }
+        Some(MediaFeature::Range(name, comparisons))
+    }
+
+    fn parse_comparison(&mut self) -> Option<Comparison> {
+        if self.eof() {
+            return None;
+        }
+        let comparison = match self.consume_char() {
+            '<' => Comparison::Less,
+            '>' => Comparison::Greater,
+            '=' => return Some(Comparison::Equal),
+            _ => return None,
+        };
+        if self.eof() || self.next_char() != '=' {
+            return Some(comparison);
+        }
+        self.consume_char();
+        Some(match comparison {
+            Comparison::Less => Comparison::LessOrEqual,
+            _ => Comparison::GreaterOrEqual,
+        })
+    }
+
+    /// Whether the input continues with a (possibly signed) number.
+    fn at_number(&self) -> bool {
+        self.input[self.pos..].trim_start_matches(['+', '-']).starts_with(|c: char| c.is_ascii_digit() || c == '.')
+    }
+
+    /// Parse a style rule. A rule whose selector is invalid is skipped, and `None` returned.
+    fn parse_rule(&mut self) -> Option<Rule> {
+        let location = self.location();
+        match self.parse_selectors() {
+            Ok(selectors) => Some(Rule {
+                location,
+                selectors,
+                declarations: self.parse_declarations(),
+            }),
+            Err(_) => {
+                self.skip_rule();
+                None
+            }
+        }
+    }
+
+    fn parse_selectors(&mut self) -> Result<Vec<Selector>, String> {
+        let mut selectors = self.parse_selector_list()?;
+        if !self.input[self.pos..].starts_with('{') {
+            return Err("Expected { after selector list".to_string());
+        }
+        selectors.sort_by_key(|s| std::cmp::Reverse(s.specificity()));
+        Ok(selectors)
+    }
+
+    /// Parse a comma-separated list of complex selectors, stopping before `{` or `)`.
+    fn parse_selector_list(&mut self) -> Result<Vec<Selector>, String> {
+        let mut selectors = Vec::new();
+        loop {
+            self.consume_whitespace();
+            selectors.push(self.parse_complex_selector()?);
+            self.consume_whitespace();
+            if !self.consume_if(',') {
+                break;
+            }
+        }
+        Ok(selectors)
+    }
+
+    fn parse_complex_selector(&mut self) -> Result<Selector, String> {
+        let mut selector = Selector::Simple(self.parse_simple_selector()?);
+        while let Some(combinator) = self.parse_combinator()? {
+            let right = self.parse_simple_selector()?;
+            selector = Selector::Complex(Box::new(selector), combinator, right);
+        }
+        Ok(selector)
+    }
+
+    /// Consume a combinator if one follows, leaving the position at the next compound selector.
+    fn parse_combinator(&mut self) -> Result<Option<Combinator>, String> {
+        let start = self.pos;
+        self.consume_whitespace();
+        let whitespace = self.pos > start;
+        if self.eof() {
+            return Ok(None);
+        }
+        let combinator = match self.next_char() {
+            '>' => Combinator::Child,
+            '+' => Combinator::NextSibling,
+            '~' => Combinator::SubsequentSibling,
+            ',' | '{' | ')' => return Ok(None),
+            _ if whitespace => return Ok(Some(Combinator::Descendant)),
+            c => return Err(format!("Unexpected character {c} in selector"))
+        };
+        self.consume_char();
+        self.consume_whitespace();
+        Ok(Some(combinator))
+    }
+
+    fn parse_simple_selector(&mut self) -> Result<SimpleSelector, String> {
+        let mut selector = SimpleSelector::default();
+        while !self.eof() {
+            match self.next_char() {
+                '#' => {
+                    self.consume_char();
+                    selector.id = Some(self.parse_identifier());
+                }
+                '.' => {
+                    self.consume_char();
+                    selector.class.push(self.parse_identifier());
+                }
+                ':' => {
+                    self.consume_char();
+                    if self.consume_if(':') {
+                        selector.pseudo_element = Some(self.parse_pseudo_element()?);
+                    } else if let Some(pseudo_element) = self.parse_legacy_pseudo_element() {
+                        selector.pseudo_element = Some(pseudo_element);
+                    } else {
+                        selector.pseudo_classes.push(self.parse_pseudo_class()?);
+                    }
+                }
+                '*' => {
+                    // universal selector
+                    self.consume_char();
+                }
+                c if valid_identifier_char(c) => {
+                    selector.tag_name = Some(self.parse_identifier());
+                }
+                _ => break
+            }
+        }
+        Ok(selector)
+    }
+
+    fn parse_pseudo_class(&mut self) -> Result<PseudoClass, String> {
+        let name = self.parse_identifier().to_ascii_lowercase();
+        if self.eof() || self.next_char() != '(' {
+            return Ok(match &*name {
+                "root" => PseudoClass::Root,
+                "empty" => PseudoClass::Empty,
+                "first-child" => PseudoClass::FirstChild,
+                "last-child" => PseudoClass::LastChild,
+                "only-child" => PseudoClass::OnlyChild,
+                "first-of-type" => PseudoClass::FirstOfType,
+                "last-of-type" => PseudoClass::LastOfType,
+                "only-of-type" => PseudoClass::OnlyOfType,
+                _ => return Err(format!("unrecognized pseudo-class :{name}"))
+            });
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let pseudo_class = match &*name {
+            "nth-child" => {
+                let nth = self.parse_nth()?;
+                PseudoClass::NthChild(nth, self.parse_nth_of()?)
+            }
+            "nth-last-child" => {
+                let nth = self.parse_nth()?;
+                PseudoClass::NthLastChild(nth, self.parse_nth_of()?)
+            }
+            "nth-of-type" => PseudoClass::NthOfType(self.parse_nth()?),
+            "nth-last-of-type" => PseudoClass::NthLastOfType(self.parse_nth()?),
+            "not" => PseudoClass::Not(self.parse_selector_list()?),
+            "is" => PseudoClass::Is(self.parse_selector_list()?),
+            "where" => PseudoClass::Where(self.parse_selector_list()?),
+            "has" => PseudoClass::Has(self.parse_relative_selector_list()?),
+            _ => return Err(format!("unrecognized pseudo-class :{name}()"))
+        };
+        self.consume_whitespace();
+        match self.consume_if(')') {
+            true => Ok(pseudo_class),
+            false => Err(format!("Expected ) after :{name}()")),
+        }
+    }
+
+    fn parse_pseudo_element(&mut self) -> Result<PseudoElement, String> {
+        match &*self.parse_identifier().to_ascii_lowercase() {
+            "before" => Ok(PseudoElement::Before),
+            "after" => Ok(PseudoElement::After),
+            "marker" => Ok(PseudoElement::Marker),
+            name => Err(format!("unrecognized pseudo-element ::{name}"))
+        }
+    }
+
+    /// `:before` and `:after` are accepted with a single colon for compatibility with CSS 2.
+    fn parse_legacy_pseudo_element(&mut self) -> Option<PseudoElement> {
+        let start = self.pos;
+        let pseudo_element = match &*self.parse_identifier().to_ascii_lowercase() {
+            "before" => Some(PseudoElement::Before),
+            "after" => Some(PseudoElement::After),
+            _ => None,
+        };
+        if pseudo_element.is_none() {
+            self.pos = start;
+        }
+        pseudo_element
+    }
+
+    fn parse_nth(&mut self) -> Result<Nth, String> {
+        let rest = self.input[self.pos..].to_ascii_lowercase();
+        for (keyword, nth) in [("odd", Nth { a: 2, b: 1 }), ("even", Nth { a: 2, b: 0 })] {
+            if rest.starts_with(keyword) {
+                self.pos += keyword.len();
+                return Ok(nth);
+            }
+        }
+        let s: String = self.consume_while(|c| matches!(c, '0'..='9' | '+' | '-' | 'n' | 'N') || c.is_whitespace())
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let parse_int = |s: &str| s.parse::<i32>().map_err(|_| format!("invalid An+B value {s}"));
+        Ok(match s.split_once('n') {
+            Some((a, b)) => Nth {
+                a: match a {
+                    "" | "+" => 1,
+                    "-" => -1,
+                    a => parse_int(a)?,
+                },
+                b: if b.is_empty() { 0 } else { parse_int(b)? },
+            },
+            None => Nth { a: 0, b: parse_int(&s)? },
+        })
+    }
+
+    /// Parse the optional `of S` part of `:nth-child()` and `:nth-last-child()`.
+    fn parse_nth_of(&mut self) -> Result<Vec<Selector>, String> {
+        self.consume_whitespace();
+        if self.input[self.pos..].to_ascii_lowercase().starts_with("of") {
+            self.pos += 2;
+            self.parse_selector_list()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn parse_relative_selector_list(&mut self) -> Result<Vec<RelativeSelector>, String> {
+        let mut selectors = Vec::new();
+        loop {
+            self.consume_whitespace();
+            let combinator = match self.input[self.pos..].chars().next() {
+                Some('>') => Some(Combinator::Child),
+                Some('+') => Some(Combinator::NextSibling),
+                Some('~') => Some(Combinator::SubsequentSibling),
+                _ => None,
+            };
+            if combinator.is_some() {
+                self.consume_char();
+                self.consume_whitespace();
+            }
+            selectors.push(RelativeSelector {
+                combinator: combinator.unwrap_or(Combinator::Descendant),
+                selector: self.parse_complex_selector()?,
+            });
+            self.consume_whitespace();
+            if !self.consume_if(',') {
+                break;
+            }
+        }
+        Ok(selectors)
+    }
+
+    fn parse_declarations(&mut self) -> Vec<Declaration> {
+        assert_eq!(self.consume_char(), '{');
+        let mut declarations = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() {
+                break;
+            }
+            if self.next_char() == '}' {
+                self.consume_char();
+                break;
+            }
+            declarations.extend(self.parse_declaration());
+        }
+        declarations
+    }
+
+    /// Parse a declaration. One with an invalid value is dropped, and `None` returned.
+    fn parse_declaration(&mut self) -> Option<Declaration> {
+        self.invalid = false;
+        let property_name = self.parse_identifier();
+        self.consume_whitespace();
+        if !self.consume_if(':') {
+            self.skip_declaration();
+            return None;
+        }
+        self.consume_whitespace();
+        let start = self.pos;
+        let value = if property_name.starts_with("--") {
+            let tokens = self.consume_tokens();
+            match tokens.to_ascii_lowercase() {
+                keyword if properties::CSS_WIDE_KEYWORDS.contains(&&*keyword) => Value::Keyword(keyword),
+                _ => Value::Tokens(tokens),
+            }
+        } else if self.references_variables() {
+            Value::Pending(property_name.clone(), self.consume_tokens())
+        } else {
+            let value = self.parse_comma_separated_values();
+            if !properties::is_valid(&property_name, &value) {
+                self.invalid = true;
+            }
+            value
+        };
+        let important = self.parse_important();
+        self.consume_whitespace();
+        // The semicolon after the last declaration in a block is optional.
+        if !self.eof() && self.next_char() != '}' && self.consume_char() != ';' {
+            self.invalid = true;
+        }
+
+        if self.invalid {
+            self.pos = start;
+            self.skip_declaration();
+            return None;
+        }
+        Some(Declaration {
+            name: property_name,
+            value,
+            important,
+        })
+    }
+
+    /// Skip the rest of an invalid declaration, up to and including its `;`.
+    fn skip_declaration(&mut self) {
+        loop {
+            self.pos = self.tokens_end();
+            if self.eof() || self.next_char() != '!' {
+                break;
+            }
+            self.consume_char();
+        }
+        if !self.eof() && self.next_char() == ';' {
+            self.consume_char();
+        }
+    }
+
+    /// The end of the current declaration value: the first `;`, `}` or `!` outside of
+    /// any brackets or strings.
+    fn tokens_end(&self) -> usize {
+        let mut depth = 0;
+        let mut quote = None;
+        let mut escaped = false;
+        for (i, c) in self.input[self.pos..].char_indices() {
+            match (quote, c) {
+                _ if escaped => escaped = false,
+                (_, '\\') => escaped = true,
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '(' | '[' | '{') => depth += 1,
+                (None, ')' | ']') if depth > 0 => depth -= 1,
+                (None, '}') if depth > 0 => depth -= 1,
+                (None, ';' | '}' | '!') if depth == 0 => return self.pos + i,
+                _ => {}
+            }
+        }
+        self.input.len()
+    }
+
+    /// Consume the rest of a declaration value as an unparsed token stream.
+    fn consume_tokens(&mut self) -> String {
+        let end = self.tokens_end();
+        let tokens = self.input[self.pos..end].trim().to_string();
+        self.pos = end;
+        tokens
+    }
+
+    fn references_variables(&self) -> bool {
+        self.input[self.pos..self.tokens_end()].to_ascii_lowercase().contains("var(")
+    }
+
+    fn parse_important(&mut self) -> bool {
+        self.consume_whitespace();
+        if self.eof() || self.next_char() != '!' {
+            return false;
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        match &*self.parse_identifier().to_ascii_lowercase() {
+            "important" => true,
+            _ => {
+                self.invalid = true;
+                false
+            }
         }
-        rules
     }
 
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    fn parse_comma_separated_values(&mut self) -> Value {
+        let mut values = vec![self.parse_component_values()];
+        while !self.eof() && self.next_char() == ',' {
+            self.consume_char();
+            values.push(self.parse_component_values());
+        }
+        if values.len() == 1 {
+            values.remove(0)
+        } else {
+            Value::CommaList(values)
         }
     }
 
-    fn parse_selectors(&mut self) -> Vec<Selector> {
-        let mut selectors = Vec::new();
+    /// Parse space-separated values up to the end of a declaration or function argument.
+    fn parse_component_values(&mut self) -> Value {
+        let mut values = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
             self.consume_whitespace();
+            if self.eof() {
+                break;
+            }
             match self.next_char() {
-                ',' => {
-                    self.consume_char();
-                    self.consume_whitespace();
-                }
-                '{' => break,
-                c => panic!("Unexpected character {c} in selector list")
+                ';' | '}' | ',' | ')' | '!' => break,
+                _ => values.push(self.parse_value()),
+            }
+            if self.invalid {
+                break;
             }
         }
-        selectors.sort_by(|a,b| b.specificity().cmp(&a.specificity()));
-        selectors
+        if values.len() == 1 {
+            values.remove(0)
+        } else {
+            Value::List(values)
+        }
     }
 
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
-        let mut selector = SimpleSelector {
-            tag_name: None,
-            id: None,
-            class: Vec::new(),
-        };
-        while !self.eof() {
-            match self.next_char() {
-                '#' => {
-                    self.consume_char();
-                    selector.id = Some(self.parse_identifier());
-                }
-                '.' => {
-                    self.consume_char();
-                    selector.class.push(self.parse_identifier());
+    fn parse_value(&mut self) -> Value {
+        if self.eof() {
+            self.invalid = true;
+            return Value::Keyword(String::new());
+        }
+        match self.next_char() {
+            '0'..='9' | '.' => self.parse_length(),
+            '-' | '+' if self.input[self.pos + 1..].starts_with(|c: char| c.is_ascii_digit() || c == '.') =>
+                self.parse_length(),
+            '#' => self.parse_color(),
+            '"' | '\'' => Value::Str(self.parse_string()),
+            '/' => {
+                self.consume_char();
+                Value::Slash
+            }
+            _ => {
+                let id = self.parse_identifier();
+                if id.is_empty() {
+                    // A character that can't start a value, such as `@` or `[`.
+                    self.invalid = true;
+                    return Value::Keyword(self.consume_char().to_string());
                 }
-                '*' => {
-                    // universal selector
-                    self.consume_char();
+                if !self.eof() && self.next_char() == '(' {
+                    let name = id.to_ascii_lowercase();
+                    if let Some(calc) = self.parse_math_function(&name) {
+                        return Value::Calc(Box::new(calc));
+                    }
+                    if color::is_color_function(&name) {
+                        return self.parse_color_function(&name);
+                    }
+                    if name == "url" {
+                        return Value::Function(name, vec![Value::Str(self.parse_url())]);
+                    }
+                    return Value::Function(name, self.parse_arguments());
                 }
-                c if valid_identifier_char(c) => {
-                    selector.tag_name = Some(self.parse_identifier());
+                if let Some(color) = color::named_color(&id) {
+                    Value::ColorValue(color)
+                } else if id.eq_ignore_ascii_case("currentcolor") {
+                    Value::Keyword("currentcolor".to_string())
+                } else {
+                    Value::Keyword(id)
                 }
-                _ => break
             }
         }
-        selector
     }
 
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert_eq!(self.consume_char(), '{');
-        let mut declarations = Vec::new();
+    /// Parse the arguments of `calc()`, `min()`, `max()` or `clamp()`, starting at the `(`.
+    /// Invalid arguments make the declaration invalid.
+    fn parse_math_function(&mut self, name: &str) -> Option<Calc> {
+        if !["calc", "min", "max", "clamp"].contains(&name) {
+            return None;
+        }
+        assert_eq!(self.consume_char(), '(');
+        let mut args = vec![self.parse_calc_sum()];
+        while !self.invalid && !self.eof() && self.next_char() == ',' {
+            self.consume_char();
+            args.push(self.parse_calc_sum());
+        }
+        if self.invalid || self.eof() || self.consume_char() != ')' {
+            self.invalid = true;
+            return Some(Calc::Number(0.0));
+        }
+        Some(match (name, args.len()) {
+            ("calc", 1) => args.remove(0),
+            ("min", _) => Calc::Min(args),
+            ("max", _) => Calc::Max(args),
+            ("clamp", 3) => {
+                let (max, value, min) = (args.pop().unwrap(), args.pop().unwrap(), args.pop().unwrap());
+                Calc::Clamp(Box::new(min), Box::new(value), Box::new(max))
+            }
+            _ => {
+                self.invalid = true;
+                Calc::Number(0.0)
+            }
+        })
+    }
+
+    /// `<product> [ [ '+' | '-' ] <product> ]*`, where the operators must be surrounded by whitespace.
+    fn parse_calc_sum(&mut self) -> Calc {
+        self.consume_whitespace();
+        let mut terms = vec![self.parse_calc_product()];
         loop {
             self.consume_whitespace();
-            if self.next_char() == '}' {
-                self.consume_char();
+            if self.invalid || self.eof() {
+                break;
+            }
+            let operator = self.next_char();
+            let spaced = self.input[self.pos + 1..].starts_with(char::is_whitespace);
+            if !(operator == '+' || operator == '-') || !spaced {
                 break;
             }
-            declarations.push(self.parse_declaration());
+            self.consume_char();
+            self.consume_whitespace();
+            let term = self.parse_calc_product();
+            terms.push(if operator == '-' { Calc::Negate(Box::new(term)) } else { term });
         }
-        declarations
+        if terms.len() == 1 { terms.remove(0) } else { Calc::Sum(terms) }
     }
 
-    fn parse_declaration(&mut self) -> Declaration {
-        let property_name = self.parse_identifier();
-        self.consume_whitespace();
-        assert_eq!(self.consume_char(), ':');
-        self.consume_whitespace();
-        let value = self.parse_value();
-        self.consume_whitespace();
-        assert_eq!(self.consume_char(), ';');
-
-        Declaration {
-            name: property_name,
-            value,
+    /// `<value> [ [ '*' | '/' ] <value> ]*`
+    fn parse_calc_product(&mut self) -> Calc {
+        let mut product = self.parse_calc_value();
+        loop {
+            self.consume_whitespace();
+            if self.invalid || self.eof() {
+                break;
+            }
+            let operator = self.next_char();
+            if operator != '*' && operator != '/' {
+                break;
+            }
+            self.consume_char();
+            self.consume_whitespace();
+            let operand = Box::new(self.parse_calc_value());
+            product = if operator == '*' {
+                Calc::Product(Box::new(product), operand)
+            } else {
+                Calc::Quotient(Box::new(product), operand)
+            };
         }
+        product
     }
 
-    fn parse_value(&mut self) -> Value {
-        match self.next_char() {
-            '0'..='9' => self.parse_length(),
-            '#' => self.parse_color(),
+    fn parse_calc_value(&mut self) -> Calc {
+        if self.eof() {
+            self.invalid = true;
+            return Calc::Number(0.0);
+        }
+        if self.next_char() == '(' {
+            self.consume_char();
+            let sum = self.parse_calc_sum();
+            if self.eof() || self.consume_char() != ')' {
+                self.invalid = true;
+            }
+            return sum;
+        }
+        match self.parse_value() {
+            Value::Number(n) => Calc::Number(n),
+            Value::Length(v, unit) => Calc::Length(v, unit),
+            Value::Calc(calc) => *calc,
+            // Keywords and unknown units, such as `auto` or `1fr`.
             _ => {
-                let id = self.parse_identifier();
-                if COLOR_NAME.contains_key(&id.as_str()) {
-                    let color = COLOR_NAME.get(&id.as_str()).unwrap();
-                    Value::ColorValue(Color {
-                        r: color[0],
-                        g: color[1],
-                        b: color[2],
-                        a: 255
-                    })
-                } else {
-                    Value::Keyword(id)
+                self.invalid = true;
+                Calc::Number(0.0)
+            }
+        }
+    }
+
+    fn parse_arguments(&mut self) -> Vec<Value> {
+        assert_eq!(self.consume_char(), '(');
+        let mut arguments = Vec::new();
+        loop {
+            arguments.push(self.parse_component_values());
+            if self.eof() {
+                self.invalid = true;
+                break;
+            }
+            match self.next_char() {
+                ',' => {}
+                ')' => {
+                    self.consume_char();
+                    break;
+                }
+                _ => {
+                    self.invalid = true;
+                    break;
+                }
+            }
+            self.consume_char();
+        }
+        arguments
+    }
+
+    fn parse_string(&mut self) -> String {
+        let quote = self.consume_char();
+        let mut result = String::new();
+        // A string left open at the end of the input ends there.
+        while !self.eof() {
+            match self.consume_char() {
+                c if c == quote => break,
+                '\\' => {
+                    let hex = self.consume_while_limited(6, |c| c.is_ascii_hexdigit());
+                    if hex.is_empty() {
+                        if self.eof() {
+                            break;
+                        }
+                        result.push(self.consume_char());
+                    } else {
+                        if !self.eof() && self.next_char().is_whitespace() {
+                            self.consume_char();
+                        }
+                        let code = u32::from_str_radix(&hex, 16).unwrap();
+                        result.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
                 }
+                c => result.push(c),
             }
         }
+        result
     }
 
     fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+        let value = self.parse_float();
+        if !self.eof() && self.next_char() == '%' {
+            self.consume_char();
+            return Value::Length(value, Unit::Percent);
+        }
+        let unit = self.parse_identifier();
+        if unit.is_empty() {
+            return Value::Number(value);
+        }
+        match Unit::from_name(&unit) {
+            Some(unit) => Value::Length(value, unit),
+            None => Value::Dimension(value, unit),
+        }
     }
 
     fn parse_float(&mut self) -> f32 {
-        let s = self.consume_while(|c| match c {
-            '0'..='9' | '.' => true,
-            _ => false,
-        });
-        s.parse().unwrap()
+        let sign = match self.next_char() {
+            '-' => { self.consume_char(); -1.0 }
+            '+' => { self.consume_char(); 1.0 }
+            _ => 1.0,
+        };
+        let s = self.consume_while(|c| matches!(c, '0'..='9' | '.'));
+        match s.parse::<f32>() {
+            Ok(value) => sign * value,
+            Err(_) => {
+                self.invalid = true;
+                0.0
+            }
+        }
     }
 
-    fn parse_unit(&mut self) -> Unit {
-        match &*self.parse_identifier().to_ascii_lowercase() {
-            "px" => Unit::Px,
-            _ => panic!("unrecognized unit")
+    /// The argument of `url()`, starting at the `(`. Unquoted URLs are taken verbatim.
+    /// Anything after the URL makes the declaration invalid.
+    fn parse_url(&mut self) -> String {
+        assert_eq!(self.consume_char(), '(');
+        self.consume_whitespace();
+        let url = match self.input[self.pos..].starts_with(['"', '\'']) {
+            true => self.parse_string(),
+            false => self.consume_while(|c| c != ')' && !c.is_whitespace()),
+        };
+        self.consume_whitespace();
+        if self.eof() || self.consume_char() != ')' {
+            self.invalid = true;
         }
+        url
     }
 
+    /// `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`.
     fn parse_color(&mut self) -> Value {
         assert_eq!(self.consume_char(), '#');
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255
+        let hex = self.consume_while(|c| c.is_ascii_hexdigit());
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Value::ColorValue(match hex.len() {
+            3 | 4 => Color {
+                r: digit(0) * 17,
+                g: digit(1) * 17,
+                b: digit(2) * 17,
+                a: if hex.len() == 4 { digit(3) * 17 } else { 255 }
+            },
+            6 | 8 => Color {
+                r: pair(0),
+                g: pair(2),
+                b: pair(4),
+                a: if hex.len() == 8 { pair(6) } else { 255 }
+            },
+            _ => {
+                self.invalid = true;
+                return Value::Keyword(format!("#{hex}"));
+            }
         })
     }
 
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos .. self.pos + 2];
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+    /// Parse the arguments of a color function such as `rgb()` or `oklch()`,
+    /// starting at the `(`. Accepts both the legacy comma-separated syntax and
+    /// the modern space-separated one with an optional `/ alpha`. Invalid arguments
+    /// make the declaration invalid.
+    fn parse_color_function(&mut self, name: &str) -> Value {
+        assert_eq!(self.consume_char(), '(');
+        let mut channels = Vec::new();
+        let mut alpha = None;
+        let mut commas = false;
+        loop {
+            self.consume_whitespace();
+            if self.invalid || self.eof() {
+                break;
+            }
+            match self.next_char() {
+                ')' => break,
+                ',' => {
+                    self.consume_char();
+                    commas = true;
+                    continue;
+                }
+                '/' if alpha.is_none() => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    alpha = Some(self.parse_color_component());
+                    continue;
+                }
+                _ => {}
+            }
+            let component = self.parse_color_component();
+            // The fourth comma-separated argument of the legacy syntax is the alpha.
+            match channels.len() {
+                _ if alpha.is_some() => self.invalid = true,
+                3 if commas => alpha = Some(component),
+                3 => self.invalid = true,
+                _ => channels.push(component),
+            }
+        }
+        let color = color::color_function(name, &channels, alpha);
+        match color {
+            Some(color) if !self.invalid && !self.eof() && self.consume_char() == ')' => Value::ColorValue(color),
+            _ => {
+                self.invalid = true;
+                Value::Function(name.to_string(), Vec::new())
+            }
+        }
+    }
+
+    fn parse_color_component(&mut self) -> Component {
+        if self.eof() {
+            self.invalid = true;
+            return Component::None;
+        }
+        if !self.next_char().is_ascii_digit() && !"+-.".contains(self.next_char()) {
+            if !self.parse_identifier().eq_ignore_ascii_case("none") {
+                // Such as relative colors, `rgb(from red r g b)`, which aren't supported.
+                self.invalid = true;
+            }
+            return Component::None;
+        }
+        let value = self.parse_float();
+        if !self.eof() && self.next_char() == '%' {
+            self.consume_char();
+            return Component::Percentage(value);
+        }
+        match &*self.parse_identifier().to_ascii_lowercase() {
+            "" => Component::Number(value),
+            "deg" => Component::Angle(value),
+            "rad" => Component::Angle(value.to_degrees()),
+            "grad" => Component::Angle(value * 0.9),
+            "turn" => Component::Angle(value * 360.0),
+            _ => {
+                self.invalid = true;
+                Component::None
+            }
+        }
     }
 
     fn parse_identifier(&mut self) -> String {
         self.consume_while(valid_identifier_char)
     }
 
+    /// Consume whitespace and comments.
     fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+        loop {
+            self.consume_while(char::is_whitespace);
+            if !self.input[self.pos..].starts_with("/*") {
+                break;
+            }
+            self.pos = match self.input[self.pos + 2..].find("*/") {
+                Some(end) => self.pos + 2 + end + 2,
+                None => self.input.len(),
+            };
+        }
     }
 
     fn consume_while<F>(&mut self, test: F) -> String
@@ -247,6 +1791,24 @@ impl Parser {
         result
     }
 
+    fn consume_while_limited<F>(&mut self, limit: usize, test: F) -> String
+            where F: Fn(char) -> bool {
+        let mut result = String::new();
+        while result.len() < limit && !self.eof() && test(self.next_char()) {
+            result.push(self.consume_char());
+        }
+        result
+    }
+
+    /// Consume `c` if it is the next character.
+    fn consume_if(&mut self, c: char) -> bool {
+        let found = !self.eof() && self.next_char() == c;
+        if found {
+            self.consume_char();
+        }
+        found
+    }
+
     fn consume_char(&mut self) -> char {
         let mut iter = self.input[self.pos..].char_indices();
         let (_, cur_char) = iter.next().unwrap();
@@ -266,44 +1828,92 @@ impl Parser {
 }
 
 fn valid_identifier_char(c: char) -> bool {
-    match c {
-        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true,
-        _ => false,
-    }
+    matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_')
 }
 
 pub type Specificity = (usize, usize, usize);
 
 impl Selector {
+    /// The pseudo-element this selector targets, given by its rightmost compound selector.
+    pub fn pseudo_element(&self) -> Option<PseudoElement> {
+        match *self {
+            Selector::Simple(ref simple) | Selector::Complex(_, _, ref simple) => simple.pseudo_element,
+        }
+    }
+
+    /// The rightmost compound selector, which the element being matched must match itself.
+    pub fn subject(&self) -> &SimpleSelector {
+        match *self {
+            Selector::Simple(ref simple) | Selector::Complex(_, _, ref simple) => simple,
+        }
+    }
+
+    pub fn specificity(&self) -> Specificity {
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            Selector::Complex(ref left, _, ref right) => add_specificity(left.specificity(), right.specificity()),
+        }
+    }
+}
+
+impl SimpleSelector {
     pub fn specificity(&self) -> Specificity {
-        let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().count();
-        let b = simple.class.len();
-        let c = simple.tag_name.iter().count();
-        (a, b, c)
+        let a = self.id.iter().count();
+        let b = self.class.len();
+        let c = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+        self.pseudo_classes.iter()
+            .map(PseudoClass::specificity)
+            .fold((a, b, c), add_specificity)
     }
 }
 
+impl PseudoClass {
+    /// `:is()`, `:not()` and `:has()` take the specificity of their most specific argument,
+    /// `:where()` contributes nothing, and `:nth-child(An+B of S)` adds the most specific
+    /// argument of `S` to that of the pseudo-class itself.
+    pub fn specificity(&self) -> Specificity {
+        match *self {
+            PseudoClass::Not(ref list) | PseudoClass::Is(ref list) => max_specificity(list.iter()),
+            PseudoClass::Where(_) => (0, 0, 0),
+            PseudoClass::Has(ref list) => max_specificity(list.iter().map(|r| &r.selector)),
+            PseudoClass::NthChild(_, ref of) | PseudoClass::NthLastChild(_, ref of) =>
+                add_specificity((0, 1, 0), max_specificity(of.iter())),
+            _ => (0, 1, 0),
+        }
+    }
+}
+
+fn add_specificity(x: Specificity, y: Specificity) -> Specificity {
+    (x.0 + y.0, x.1 + y.1, x.2 + y.2)
+}
+
+fn max_specificity<'a, I>(selectors: I) -> Specificity
+    where I: Iterator<Item=&'a Selector> {
+    selectors.map(Selector::specificity).max().unwrap_or((0, 0, 0))
+}
+
 #[test]
 fn parse_simple_css() {
     let parsed = parse("body { margin: 8px; }".to_string());
-    let mut selectors = Vec::new();
-    selectors.push(Selector::Simple(SimpleSelector {
+    let selectors = vec![Selector::Simple(SimpleSelector {
         tag_name: Some("body".to_string()),
         id: None,
         class: Vec::new(),
-    }));
-    let mut declarations = Vec::new();
-    declarations.push(Declaration {
+        pseudo_classes: Vec::new(),
+        pseudo_element: None,
+    })];
+    let declarations = vec![Declaration {
         name: "margin".to_string(),
         value: Value::Length(8.0, Unit::Px),
-    });
-    let mut rules = Vec::new();
-    rules.push(Rule {
+        important: false,
+    }];
+    let rules = vec![Rule {
         selectors,
         declarations,
-    });
-    let expected = Stylesheet { rules };
+        location: SourceLocation { line: 1, column: 1 },
+    }];
+    let rules = rules.into_iter().map(CssRule::Style).collect();
+    let expected = Stylesheet { rules, origin: Origin::Author };
     assert_eq!(expected, parsed);
 }
 
@@ -311,14 +1921,14 @@ fn parse_simple_css() {
 fn parse_rgb_color() {
     // #FF0000 = red
     let parsed = parse("body { background: #FF0000; }".to_string());
-    let mut selectors = Vec::new();
-    selectors.push(Selector::Simple(SimpleSelector {
+    let selectors = vec![Selector::Simple(SimpleSelector {
         tag_name: Some("body".to_string()),
         id: None,
         class: Vec::new(),
-    }));
-    let mut declarations = Vec::new();
-    declarations.push(Declaration {
+        pseudo_classes: Vec::new(),
+        pseudo_element: None,
+    })];
+    let declarations = vec![Declaration {
         name: "background".to_string(),
         value: Value::ColorValue(Color{
             r: 255,
@@ -326,27 +1936,29 @@ fn parse_rgb_color() {
             b: 0,
             a: 255,
         }),
-    });
-    let mut rules = Vec::new();
-    rules.push(Rule {
+        important: false,
+    }];
+    let rules = vec![Rule {
         selectors,
         declarations,
-    });
-    let expected = Stylesheet { rules };
+        location: SourceLocation { line: 1, column: 1 },
+    }];
+    let rules = rules.into_iter().map(CssRule::Style).collect();
+    let expected = Stylesheet { rules, origin: Origin::Author };
     assert_eq!(expected, parsed);
 }
 
 #[test]
 fn parse_color_name_red() {
     let parsed = parse("body { background: red; }".to_string());
-    let mut selectors = Vec::new();
-    selectors.push(Selector::Simple(SimpleSelector {
+    let selectors = vec![Selector::Simple(SimpleSelector {
         tag_name: Some("body".to_string()),
         id: None,
         class: Vec::new(),
-    }));
-    let mut declarations = Vec::new();
-    declarations.push(Declaration {
+        pseudo_classes: Vec::new(),
+        pseudo_element: None,
+    })];
+    let declarations = vec![Declaration {
         name: "background".to_string(),
         value: Value::ColorValue(Color{
             r: 255,
@@ -354,27 +1966,29 @@ fn parse_color_name_red() {
             b: 0,
             a: 255,
         }),
-    });
-    let mut rules = Vec::new();
-    rules.push(Rule {
+        important: false,
+    }];
+    let rules = vec![Rule {
         selectors,
         declarations,
-    });
-    let expected = Stylesheet { rules };
+        location: SourceLocation { line: 1, column: 1 },
+    }];
+    let rules = rules.into_iter().map(CssRule::Style).collect();
+    let expected = Stylesheet { rules, origin: Origin::Author };
     assert_eq!(expected, parsed);
 }
 
 #[test]
 fn parse_color_name_blue() {
     let parsed = parse("body { background: blue; }".to_string());
-    let mut selectors = Vec::new();
-    selectors.push(Selector::Simple(SimpleSelector {
+    let selectors = vec![Selector::Simple(SimpleSelector {
         tag_name: Some("body".to_string()),
         id: None,
         class: Vec::new(),
-    }));
-    let mut declarations = Vec::new();
-    declarations.push(Declaration {
+        pseudo_classes: Vec::new(),
+        pseudo_element: None,
+    })];
+    let declarations = vec![Declaration {
         name: "background".to_string(),
         value: Value::ColorValue(Color{
             r: 0,
@@ -382,12 +1996,328 @@ fn parse_color_name_blue() {
             b: 255,
             a: 255,
         }),
-    });
-    let mut rules = Vec::new();
-    rules.push(Rule {
+        important: false,
+    }];
+    let rules = vec![Rule {
         selectors,
         declarations,
-    });
-    let expected = Stylesheet { rules };
+        location: SourceLocation { line: 1, column: 1 },
+    }];
+    let rules = rules.into_iter().map(CssRule::Style).collect();
+    let expected = Stylesheet { rules, origin: Origin::Author };
     assert_eq!(expected, parsed);
 }
+
+#[test]
+fn pseudo_class_specificity() {
+    let specificity = |source: &str| {
+        let stylesheet = parse(format!("{source} {{ display: block; }}"));
+        stylesheet.rules[0].as_style().unwrap().selectors[0].specificity()
+    };
+    assert_eq!((0, 1, 1), specificity("li:first-child"));
+    assert_eq!((1, 0, 1), specificity("p:not(.a, #b)"));
+    assert_eq!((0, 1, 0), specificity(":is(.a, b)"));
+    assert_eq!((0, 0, 1), specificity(":where(#a .b) p"));
+    assert_eq!((0, 2, 1), specificity("li:nth-child(2n+1 of .item)"));
+    assert_eq!((0, 1, 2), specificity("div:has(> img.icon)"));
+    assert_eq!((0, 1, 2), specificity("ul > li:empty"));
+}
+
+#[test]
+fn parse_an_plus_b() {
+    let nth = |source: &str| {
+        match parse(format!("li:nth-child({source}) {{}}")).rules[0].as_style().unwrap().selectors[0] {
+            Selector::Simple(SimpleSelector { ref pseudo_classes, .. }) => match pseudo_classes[0] {
+                PseudoClass::NthChild(nth, _) => nth,
+                _ => panic!("expected :nth-child"),
+            },
+            _ => panic!("expected a simple selector"),
+        }
+    };
+    assert_eq!(Nth { a: 2, b: 1 }, nth("odd"));
+    assert_eq!(Nth { a: 2, b: 0 }, nth("even"));
+    assert_eq!(Nth { a: 0, b: 3 }, nth("3"));
+    assert_eq!(Nth { a: -1, b: 3 }, nth("-n + 3"));
+    assert_eq!(Nth { a: 1, b: 0 }, nth("n"));
+    assert_eq!(Nth { a: 3, b: -2 }, nth("3n-2"));
+}
+
+#[test]
+fn skip_rules_with_invalid_selectors() {
+    let parsed = parse("p:hover { color: red; } a, b:nth-child(x) { margin: 0 } li:not(:focus) {} p:first-child { margin: 1px }".to_string());
+    assert_eq!(1, parsed.rules.len());
+    assert_eq!((0, 1, 1), parsed.rules[0].as_style().unwrap().selectors[0].specificity());
+
+    let parsed = parse("::selection { color: red } input::placeholder, p::before { color: red } p::after { content: 'x' }".to_string());
+    assert_eq!(1, parsed.rules.len());
+    assert_eq!(Some(PseudoElement::After), parsed.rules[0].as_style().unwrap().selectors[0].pseudo_element());
+
+    // A stray `}` makes the rule it starts invalid, but not the rest of the stylesheet.
+    let parsed = parse("} a { color: red } p { margin: 0 }".to_string());
+    assert_eq!(1, parsed.rules.len());
+
+    // So does a selector cut off by the end of the input.
+    for source in ["p", "a:", "a::", "a >", "a:not(", "a:has(>", "a:nth-child(2n of"] {
+        assert_eq!(1, parse(format!("p {{ margin: 0 }} {source}")).rules.len(), "{source}");
+    }
+}
+
+#[test]
+fn parse_generated_content() {
+    let parsed = parse("a::before { content: \"\\2192  \" attr(href) counter(item, upper-roman); }".to_string());
+    let rule = parsed.rules[0].as_style().unwrap();
+    assert_eq!(Some(PseudoElement::Before), rule.selectors[0].pseudo_element());
+    assert_eq!((0, 0, 2), rule.selectors[0].specificity());
+    assert_eq!(Value::List(vec![
+        Value::Str("\u{2192} ".to_string()),
+        Value::Function("attr".to_string(), vec![Value::Keyword("href".to_string())]),
+        Value::Function("counter".to_string(), vec![
+            Value::Keyword("item".to_string()),
+            Value::Keyword("upper-roman".to_string()),
+        ]),
+    ]), rule.declarations[0].value);
+
+    let legacy = parse("li:after { counter-increment: item -1; }".to_string());
+    assert_eq!(Some(PseudoElement::After), legacy.rules[0].as_style().unwrap().selectors[0].pseudo_element());
+    assert_eq!(Value::List(vec![Value::Keyword("item".to_string()), Value::Number(-1.0)]),
+               legacy.rules[0].as_style().unwrap().declarations[0].value);
+}
+
+#[test]
+fn parse_important() {
+    let parsed = parse_with_origin("p { color: red !important; margin: 8px ! IMPORTANT; display: block; }".to_string(), Origin::User);
+    assert_eq!(Origin::User, parsed.origin);
+    let important: Vec<_> = parsed.rules[0].as_style().unwrap().declarations.iter().map(|d| d.important).collect();
+    assert_eq!(vec![true, true, false], important);
+    assert_eq!(Value::Length(8.0, Unit::Px), parsed.rules[0].as_style().unwrap().declarations[1].value);
+
+    // Declarations with anything but `!important` after their value are dropped.
+    let parsed = parse("p { color: red !importnt; margin: 1px !; padding: 2px 3px; display red; width: 1px ) } a {}".to_string());
+    assert_eq!(2, parsed.rules.len());
+    let names: Vec<_> = parsed.rules[0].as_style().unwrap().declarations.iter().map(|d| &*d.name).collect();
+    assert_eq!(vec!["padding"], names);
+}
+
+#[test]
+fn skip_invalid_values() {
+    let parsed = parse("p { a: @x; b: [a] 1px; c: 1.2.3; d: 1px } q { content: 'open".to_string());
+    assert_eq!(2, parsed.rules.len());
+    let names: Vec<_> = parsed.rules[0].as_style().unwrap().declarations.iter().map(|d| &*d.name).collect();
+    assert_eq!(vec!["d"], names);
+    // Strings and blocks left open at the end of the input are closed there.
+    assert_eq!(Value::Str("open".to_string()), parsed.rules[1].as_style().unwrap().declarations[0].value);
+}
+
+#[test]
+fn parse_comments() {
+    let parsed = parse("/* a */ p /* b */ { /* c */ margin: 8px; /* d */ } /* e */".to_string());
+    assert_eq!(1, parsed.rules.len());
+    assert_eq!(1, parsed.rules[0].as_style().unwrap().declarations.len());
+}
+
+#[test]
+fn parse_multiple_values() {
+    let parsed = parse("p { margin: 0 auto; font: italic 12px/1.5 Times New Roman, serif; }".to_string());
+    let keyword = |k: &str| Value::Keyword(k.to_string());
+    let declarations = &parsed.rules[0].as_style().unwrap().declarations;
+    assert_eq!(Value::List(vec![Value::Number(0.0), keyword("auto")]), declarations[0].value);
+    assert_eq!(Value::CommaList(vec![
+        Value::List(vec![
+            keyword("italic"), Value::Length(12.0, Unit::Px), Value::Slash, Value::Number(1.5),
+            keyword("Times"), keyword("New"), keyword("Roman"),
+        ]),
+        keyword("serif"),
+    ]), declarations[1].value);
+}
+
+#[test]
+fn resolve_units() {
+    let context = LengthContext {
+        font_size: 20.0,
+        root_font_size: 16.0,
+        percentage_base: 400.0,
+        viewport_width: 800.0,
+        viewport_height: 600.0,
+        ..Default::default()
+    };
+    let parsed = parse("p { a: 2em 1.5rem 1ex 25% 10vw 10vh 10vmin 10vmax 72pt 1pc 1in 2.54cm 10mm 3px; }".to_string());
+    let px: Vec<f32> = match parsed.rules[0].as_style().unwrap().declarations[0].value {
+        Value::List(ref values) => values.iter().map(|v| v.to_px(&context)).collect(),
+        _ => panic!("expected a list"),
+    };
+    let expected = [40.0, 24.0, 10.0, 100.0, 80.0, 60.0, 60.0, 80.0, 96.0, 16.0, 96.0, 96.0, 37.795277, 3.0];
+    for (actual, expected) in px.iter().zip(expected) {
+        assert!((actual - expected).abs() < 1e-3, "{actual} != {expected}");
+    }
+}
+
+#[test]
+fn parse_math_functions() {
+    let parsed = parse("p { width: calc(100% - 2 * (1em + 4px)); height: clamp(10px, 50vh, max(20px, 30%)); }".to_string());
+    let context = LengthContext {
+        font_size: 10.0,
+        root_font_size: 16.0,
+        percentage_base: 200.0,
+        viewport_width: 800.0,
+        viewport_height: 600.0,
+        ..Default::default()
+    };
+    let declarations = &parsed.rules[0].as_style().unwrap().declarations;
+    assert_eq!(Value::Calc(Box::new(Calc::Sum(vec![
+        Calc::Length(100.0, Unit::Percent),
+        Calc::Negate(Box::new(Calc::Product(
+            Box::new(Calc::Number(2.0)),
+            Box::new(Calc::Sum(vec![Calc::Length(1.0, Unit::Em), Calc::Length(4.0, Unit::Px)])),
+        ))),
+    ]))), declarations[0].value);
+    assert_eq!(172.0, declarations[0].value.to_px(&context));
+    assert_eq!(60.0, declarations[1].value.to_px(&context));
+}
+
+#[test]
+fn skip_invalid_math_functions() {
+    let parsed = parse("p {
+        a: calc(1px+2px); b: calc(1px + auto); c: calc(100% - 1fr); d: calc(1px, 2px); e: calc((1px + 2px) * 2 3);
+        f: clamp(1px, 2px); g: clamp(1px, 2px, 3px, 4px); h: min(); i: max(1px,); j: calc(1px 2px);
+        k: min(1px); l: max(1px, 2em, 3%); m: clamp(1px, 2px, 3px); n: calc(1px - -2px);
+    }".to_string());
+    let names: Vec<_> = parsed.rules[0].as_style().unwrap().declarations.iter().map(|d| &*d.name).collect();
+    assert_eq!(vec!["k", "l", "m", "n"], names);
+
+    // Math functions are valid for numbers only when they contain no lengths, and for
+    // lengths only when they do.
+    let parsed = parse("p { opacity: calc(1 / 2); flex-grow: min(1px, 2); width: calc(2 * 3); height: calc(2 * 3px); }".to_string());
+    let names: Vec<_> = parsed.rules[0].as_style().unwrap().declarations.iter().map(|d| &*d.name).collect();
+    assert_eq!(vec!["opacity", "height"], names);
+}
+
+#[test]
+fn parse_color_syntaxes() {
+    let parsed = parse(r#"p {
+        a: #f00; b: #f008; c: #00ff0080; d: Transparent; e: currentColor; f: RebeccaPurple;
+        g: rgb(255, 0, 0); h: rgba(0 100% 0 / 50%); i: hsl(120deg 100% 50%); j: hsla(0.5turn, 100%, 50%, 0.25);
+        k: hwb(0 0% 0%); l: lab(100 0 0); m: oklch(62.8% 0.258 29.23); n: rgb(none none 255 / none);
+    }"#.to_string());
+    let colors: Vec<_> = parsed.rules[0].as_style().unwrap().declarations.iter().map(|d| match d.value {
+        Value::ColorValue(c) => Some((c.r, c.g, c.b, c.a)),
+        _ => None,
+    }).collect();
+    assert_eq!(vec![
+        Some((255, 0, 0, 255)), Some((255, 0, 0, 136)), Some((0, 255, 0, 128)), Some((0, 0, 0, 0)),
+        None, Some((102, 51, 153, 255)),
+        Some((255, 0, 0, 255)), Some((0, 255, 0, 128)), Some((0, 255, 0, 255)), Some((0, 255, 255, 64)),
+        Some((255, 0, 0, 255)), Some((255, 255, 255, 255)), Some((255, 0, 0, 255)), Some((0, 0, 255, 0)),
+    ], colors);
+    assert_eq!(Value::Keyword("currentcolor".to_string()), parsed.rules[0].as_style().unwrap().declarations[4].value);
+
+    // Declarations with invalid hex colors are dropped.
+    let parsed = parse("p { color: #ggg; background: #12345; border-color: #0f0 }".to_string());
+    let names: Vec<_> = parsed.rules[0].as_style().unwrap().declarations.iter().map(|d| &*d.name).collect();
+    assert_eq!(vec!["border-color"], names);
+    assert_eq!(None, parse_value("#ggg".to_string()));
+
+    // So are those with invalid color functions.
+    let parsed = parse("p {
+        a: rgb(1, 2); b: rgb(foo); c: rgb(from red r g b); d: hsl(10px 50% 50%); e: rgb(1 2 3 / 4 5);
+        f: rgb(1 2 3 / 4 / 5); g: hwb(0 0% 0% /); j: rgb(1 / 2 3); h: rgb(1, 2, 3, 4, 5); i: rgb(1 2 3 4);
+    }".to_string());
+    assert!(parsed.rules[0].as_style().unwrap().declarations.is_empty());
+    assert_eq!(None, parse_value("rgb(1 2 3".to_string()));
+}
+
+#[test]
+fn parse_custom_properties() {
+    let parsed = parse("p { --x: { a: b } 1px; --y: INHERIT; margin: var(--x) 0 !important; }".to_string());
+    let declarations = &parsed.rules[0].as_style().unwrap().declarations;
+    assert_eq!(Value::Tokens("{ a: b } 1px".to_string()), declarations[0].value);
+    assert_eq!(Value::Keyword("inherit".to_string()), declarations[1].value);
+    assert_eq!(Value::Pending("margin".to_string(), "var(--x) 0".to_string()), declarations[2].value);
+    assert!(declarations[2].important);
+}
+
+#[test]
+fn parse_imports() {
+    let parsed = parse(r#"
+        @charset "utf-8";
+        @import foo.css;
+        @import url(a b);
+        @import url(base.css);
+        @import "print.css" print;
+        @import url( 'quoted.css' ) screen and (min-width: 600px);
+        p { background: url(images/bg.png); color: url(a b); }
+        @import "too-late.css";
+        @media print { @import "nested.css"; }
+    "#.to_string());
+    let imports: Vec<_> = parsed.rules.iter().filter_map(|rule| match *rule {
+        CssRule::Import(ref import) => Some((import.url.as_str(), import.media.0.len())),
+        _ => None,
+    }).collect();
+    assert_eq!(vec![("base.css", 0), ("print.css", 1), ("quoted.css", 1)], imports);
+    assert_eq!(Value::Function("url".to_string(), vec![Value::Str("images/bg.png".to_string())]),
+               parsed.rules[3].as_style().unwrap().declarations[0].value);
+    assert_eq!(1, parsed.rules[3].as_style().unwrap().declarations.len());
+    assert_eq!(Some(&CssRule::Media(MediaList(vec![MediaQuery {
+        negated: false, media_type: MediaType::Print, condition: None,
+    }]), vec![])), parsed.rules.get(4));
+}
+
+#[test]
+fn parse_layers() {
+    let parsed = parse("
+        @layer reset, base.buttons;
+        @layer base { p {} }
+        @layer { a {} }
+        @layer !; p { color: red }
+        @layer a, b { p { color: red } }
+        @layer a.; @layer a,; @layer;
+        b {}
+    ".to_string());
+    let name = |parts: &[&str]| -> LayerName { parts.iter().map(|part| part.to_string()).collect() };
+    assert_eq!(5, parsed.rules.len());
+    assert_eq!(CssRule::LayerStatement(vec![name(&["reset"]), name(&["base", "buttons"])]), parsed.rules[0]);
+    assert!(matches!(parsed.rules[1], CssRule::Layer(Some(ref layer), ref rules) if *layer == name(&["base"]) && rules.len() == 1));
+    assert!(matches!(parsed.rules[2], CssRule::Layer(None, ref rules) if rules.len() == 1));
+    assert!(parsed.rules[3..].iter().all(|rule| rule.as_style().is_some()));
+}
+
+#[test]
+fn load_imports_relative_to_each_file() {
+    let dir = std::env::temp_dir().join(format!("robinson-imports-{}", std::process::id()));
+    fs::create_dir_all(dir.join("parts")).unwrap();
+    let write = |name: &str, source: &str| fs::write(dir.join(name), source).unwrap();
+    write("main.css", "@import url(parts/a.css); @import 'parts/print.css' print; p { margin: 3px; }");
+    write("parts/a.css", "@import 'b.css'; p { margin: 1px; }");
+    write("parts/b.css", "@import '../main.css'; @import 'missing.css'; p { margin: 2px; padding: 2px; }");
+    write("parts/print.css", "p { padding: 4px; }");
+
+    let stylesheet = load(&dir.join("main.css"), Origin::User).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let declarations = |device: &Device| -> Vec<String> {
+        stylesheet.style_rules(device).iter()
+            .flat_map(|rule| &rule.declarations)
+            .map(|d| format!("{}: {:?}", d.name, d.value))
+            .collect()
+    };
+    // The cyclic import of main.css and the missing file are skipped.
+    assert_eq!(vec!["margin: Length(2.0, Px)", "padding: Length(2.0, Px)", "margin: Length(1.0, Px)", "margin: Length(3.0, Px)"],
+               declarations(&Device::default()));
+    let print = Device { media_type: MediaType::Print, ..Device::default() };
+    assert_eq!(Some(&"padding: Length(4.0, Px)".to_string()), declarations(&print).get(3));
+    match stylesheet.rules[0] {
+        CssRule::Import(ImportRule { stylesheet: Some(ref imported), .. }) => assert_eq!(Origin::User, imported.origin),
+        _ => panic!("expected a loaded import"),
+    }
+    let rules = stylesheet.style_rules(&Device::default());
+    assert_eq!(Some(vec!["parts/a.css".to_string(), "b.css".to_string()]), stylesheet.import_chain(rules[0]));
+    assert_eq!(Some(Vec::new()), stylesheet.import_chain(rules[2]));
+}
+
+#[test]
+fn rule_source_locations() {
+    let stylesheet = parse("a { }\n  @media screen {\n    p, em { color: red; }\n  }\n\tb{}".to_string());
+    let locations: Vec<SourceLocation> = stylesheet.style_rules(&crate::media::Device::default()).iter()
+        .map(|rule| rule.location)
+        .collect();
+    let at = |line, column| SourceLocation { line, column };
+    assert_eq!(vec![at(1, 1), at(3, 5), at(5, 2)], locations);
+}
@@ -9,6 +9,20 @@ use crate::css::Stylesheet;
 pub struct Node {
     pub node_type: NodeType,
     pub children: Vec<Node>,
+    /// Changes since the node was last styled.
+    pub dirty: Dirty,
+}
+
+/// What changed about a node since it was last styled, so that `style::Restyler` can
+/// restyle only what the changes affect. Only changes made through the methods of
+/// `Node` are recorded.
+#[derive(Debug, Default, PartialEq)]
+pub struct Dirty {
+    /// The attributes of an element as they were before they first changed.
+    pub old_attributes: Option<AttrMap>,
+    /// For each child, its index before the children first changed, or `None` if it
+    /// was inserted since.
+    pub old_children: Option<Vec<Option<usize>>>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -33,7 +47,7 @@ impl ElementData {
 
     pub fn classes(&self) -> HashSet<&str> {
         match self.attributes.get("class") {
-            Some(classList) => classList.split(' ').collect(),
+            Some(class_list) => class_list.split(' ').collect(),
             None => HashSet::new()
         }
     }
@@ -49,26 +63,71 @@ impl ElementData {
     }
 }
 
+impl Node {
+    /// Set an attribute of an element, such as `class`.
+    pub fn set_attribute(&mut self, name: &str, value: String) {
+        self.attributes_mut().insert(name.to_string(), value);
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        self.attributes_mut().remove(name)
+    }
+
+    pub fn insert_child(&mut self, index: usize, child: Node) {
+        self.old_children_mut().insert(index, None);
+        self.children.insert(index, child);
+    }
+
+    pub fn remove_child(&mut self, index: usize) -> Node {
+        self.old_children_mut().remove(index);
+        self.children.remove(index)
+    }
+
+    /// Forget the changes to this node and its descendants, once they have been styled.
+    pub fn clear_dirty(&mut self) {
+        self.dirty = Dirty::default();
+        for child in &mut self.children {
+            child.clear_dirty();
+        }
+    }
+
+    fn attributes_mut(&mut self) -> &mut AttrMap {
+        let NodeType::Element(ref mut data) = self.node_type else {
+            panic!("Only elements have attributes")
+        };
+        self.dirty.old_attributes.get_or_insert_with(|| data.attributes.clone());
+        &mut data.attributes
+    }
+
+    fn old_children_mut(&mut self) -> &mut Vec<Option<usize>> {
+        let len = self.children.len();
+        self.dirty.old_children.get_or_insert_with(|| (0..len).map(Some).collect())
+    }
+}
+
 pub fn text(data: String) -> Node {
     Node {
         children: Vec::new(),
-        node_type: NodeType::Text(data)
+        node_type: NodeType::Text(data),
+        dirty: Dirty::default(),
     }
 }
 
 pub fn comment(data: String) -> Node {
     Node {
         children: Vec::new(),
-        node_type: NodeType::Comment(data)
+        node_type: NodeType::Comment(data),
+        dirty: Dirty::default(),
     }
 }
 
 pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
     Node {
-        children: children,
+        children,
         node_type: NodeType::Element(ElementData {
             tag_name: name,
             attributes: attrs,
-        })
+        }),
+        dirty: Dirty::default(),
     }
 }
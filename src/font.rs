@@ -0,0 +1,177 @@
+use std::io;
+use std::path::Path;
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+use ttf_parser::{name_id, Face, GlyphId};
+use crate::computed::{FontStyle, FontValues};
+
+/// A TrueType or OpenType font face, with the properties `select` matches against.
+pub struct Font {
+    face: Face<'static>,
+    /// The family name from the font's `name` table, in lowercase.
+    family: String,
+    weight: u16,
+    italic: bool,
+}
+
+/// The vertical metrics of a font at some size, in px.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontMetrics {
+    /// How far the font reaches above the baseline.
+    pub ascent: f32,
+    /// How far the font reaches below the baseline, as a positive length.
+    pub descent: f32,
+    /// The space the font recommends between the descent of a line and the ascent of the next.
+    pub line_gap: f32,
+    /// The height of lowercase letters, from the font's OS/2 table or its "x" glyph, or
+    /// half the font size if it has neither.
+    pub x_height: f32,
+}
+
+/// A glyph of shaped text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    pub id: u16,
+    /// How far the glyph moves the pen, in px, including kerning with the next glyph.
+    pub advance: f32,
+}
+
+lazy_static! {
+    /// DejaVu Sans, used for generic families and for families no registered font provides.
+    static ref DEFAULT_FONT: Font = Font::from_data(include_bytes!("../fonts/DejaVuSans.ttf"))
+        .expect("the bundled font is valid");
+    static ref FONTS: RwLock<Vec<&'static Font>> = RwLock::new(Vec::new());
+}
+
+impl Font {
+    /// Parse the first face of a TTF or OTF file.
+    pub fn from_data(data: &'static [u8]) -> Option<Font> {
+        let face = Face::parse(data, 0).ok()?;
+        let family_name = |id: u16| face.names().into_iter()
+            .find(|name| name.name_id == id && name.is_unicode())
+            .and_then(|name| name.to_string());
+        let family = family_name(name_id::TYPOGRAPHIC_FAMILY).or_else(|| family_name(name_id::FAMILY))?;
+        Some(Font {
+            family: family.to_lowercase(),
+            weight: face.weight().to_number(),
+            italic: face.is_italic() || face.is_oblique(),
+            face,
+        })
+    }
+
+    /// Load a font file. Fonts are kept for the rest of the program, so its data is never freed.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Font> {
+        let data: &'static [u8] = Box::leak(std::fs::read(path)?.into_boxed_slice());
+        Font::from_data(data).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a TrueType or OpenType font"))
+    }
+
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    pub fn metrics(&self, size: f32) -> FontMetrics {
+        let scale = self.scale(size);
+        FontMetrics {
+            ascent: self.face.ascender() as f32 * scale,
+            descent: -self.face.descender() as f32 * scale,
+            line_gap: self.face.line_gap() as f32 * scale,
+            x_height: self.face.x_height()
+                .or_else(|| self.face.glyph_bounding_box(self.face.glyph_index('x')?).map(|rect| rect.y_max))
+                .map_or(size / 2.0, |x_height| x_height as f32 * scale),
+        }
+    }
+
+    /// The advance of "0", which is the size of one `ch`, or half the font size if the
+    /// font has no glyph for it.
+    pub fn zero_advance(&self, size: f32) -> f32 {
+        match self.face.glyph_index('0') {
+            Some(_) => self.text_width("0", size),
+            None => size / 2.0,
+        }
+    }
+
+    /// Map the characters of `text` to glyphs, one for each character, and find their
+    /// advances, applying the kerning in the font's `kern` table. Characters the font
+    /// has no glyph for get its `.notdef` glyph.
+    pub fn shape(&self, text: &str, size: f32) -> Vec<Glyph> {
+        let scale = self.scale(size);
+        let ids: Vec<GlyphId> = text.chars()
+            .map(|c| self.face.glyph_index(c).unwrap_or(GlyphId(0)))
+            .collect();
+        ids.iter().enumerate().map(|(i, &id)| {
+            let advance = self.face.glyph_hor_advance(id).unwrap_or(0) as f32;
+            let kerning = ids.get(i + 1).map_or(0, |&next| self.kerning(id, next)) as f32;
+            Glyph { id: id.0, advance: (advance + kerning) * scale }
+        }).collect()
+    }
+
+    /// The advance width of `text`, in px.
+    pub fn text_width(&self, text: &str, size: f32) -> f32 {
+        self.shape(text, size).iter().map(|glyph| glyph.advance).sum()
+    }
+
+    fn kerning(&self, left: GlyphId, right: GlyphId) -> i16 {
+        let Some(kern) = self.face.tables().kern else { return 0 };
+        kern.subtables.into_iter()
+            .filter(|subtable| subtable.horizontal && !subtable.has_cross_stream && !subtable.variable)
+            .filter_map(|subtable| subtable.glyphs_kerning(left, right))
+            .sum()
+    }
+
+    /// Font units to px.
+    fn scale(&self, size: f32) -> f32 {
+        size / self.face.units_per_em() as f32
+    }
+}
+
+/// Make a font available to `select`.
+pub fn register(font: Font) {
+    FONTS.write().unwrap().push(Box::leak(Box::new(font)));
+}
+
+/// The font to use for `font`: the first family in its list that a registered font
+/// provides, in the style closest to the requested one, or else the default font.
+pub fn select(font: &FontValues) -> &'static Font {
+    find(&FONTS.read().unwrap(), font).unwrap_or(&DEFAULT_FONT)
+}
+
+/// The font among `fonts` for the first family in `font`'s list that any of them provides.
+fn find<'a>(fonts: &[&'a Font], font: &FontValues) -> Option<&'a Font> {
+    let italic = font.style != FontStyle::Normal;
+    font.family.iter()
+        .find_map(|family| fonts.iter()
+            .filter(|candidate| candidate.family.eq_ignore_ascii_case(family))
+            .min_by_key(|candidate| (candidate.italic != italic, candidate.weight.abs_diff(font.weight))))
+        .copied()
+}
+
+#[test]
+fn default_font() {
+    let values = |family: &str| FontValues {
+        size: 16.0,
+        weight: 400,
+        style: FontStyle::Normal,
+        family: vec!["No Such Font".to_string(), family.to_string()],
+        line_height: crate::computed::LineHeight::Normal,
+    };
+    let font = select(&values("serif"));
+    assert!(std::ptr::eq(font, &*DEFAULT_FONT));
+    assert_eq!("dejavu sans", font.family());
+
+    // Fonts are found by family name, in any case.
+    let dejavu = Font::from_data(include_bytes!("../fonts/DejaVuSans.ttf")).unwrap();
+    assert!(std::ptr::eq(find(&[&dejavu], &values("DejaVu SANS")).unwrap(), &dejavu));
+    assert!(find(&[&dejavu], &values("serif")).is_none());
+
+    // DejaVu Sans has 2048 units per em, an ascender of 1901 and a descender of -483.
+    let metrics = font.metrics(2048.0);
+    assert_eq!((1901.0, 483.0), (metrics.ascent, metrics.descent));
+
+    // Advances scale with the font size, and kerning pulls some pairs together.
+    let (a, v) = (font.text_width("A", 16.0), font.text_width("V", 16.0));
+    assert_eq!(2.0 * a, font.text_width("A", 32.0));
+    let h = font.text_width("H", 16.0);
+    assert_eq!(2.0 * h, font.text_width("HH", 16.0));
+    assert!(font.text_width("AV", 16.0) < a + v);
+    assert_eq!(vec![0], font.shape("\u{E000}", 16.0).iter().map(|glyph| glyph.id).collect::<Vec<_>>());
+}
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+use crate::css::{Combinator, PseudoClass, Selector, SimpleSelector};
+use crate::dom::AttrMap;
+
+/// The elements whose style may depend on some feature of an element, relative to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reach {
+    /// The element itself.
+    Element,
+    /// Its descendants, as for `.a p`.
+    Descendants,
+    /// Its following siblings and their descendants, as for `.a + p`.
+    LaterSiblings,
+    /// All of its siblings and their descendants, as for `:nth-child(2 of .a)`.
+    Siblings,
+    /// Any element, as for `:has(.a)`.
+    Document,
+}
+
+/// Invalidation sets: for each class and id that selectors test, which elements a
+/// change to it may affect, relative to the element that changed.
+#[derive(Default)]
+pub struct InvalidationMap {
+    classes: HashMap<String, HashSet<Reach>>,
+    ids: HashMap<String, HashSet<Reach>>,
+    /// Which elements inserting or removing children may affect, relative to the parent.
+    /// Children that are inserted are always styled.
+    structural: HashSet<Reach>,
+}
+
+impl InvalidationMap {
+    pub fn new<'s>(selectors: impl IntoIterator<Item=&'s Selector>) -> InvalidationMap {
+        let mut map = InvalidationMap::default();
+        for selector in selectors {
+            map.add_selector(selector, Reach::Element);
+        }
+        map
+    }
+
+    /// Which elements changing an element's attributes from `old` to `new` may affect.
+    /// Selectors only test classes and ids; the `style` attribute only affects the
+    /// element itself.
+    pub fn attribute_changes(&self, old: &AttrMap, new: &AttrMap) -> HashSet<Reach> {
+        let mut reaches = HashSet::new();
+        let classes = |attributes: &AttrMap| -> HashSet<String> {
+            attributes.get("class").map_or_else(HashSet::new, |list| list.split(' ').map(str::to_string).collect())
+        };
+        let (old_classes, new_classes) = (classes(old), classes(new));
+        for class in old_classes.symmetric_difference(&new_classes) {
+            reaches.extend(self.classes.get(class).into_iter().flatten());
+        }
+        if old.get("id") != new.get("id") {
+            for id in old.get("id").into_iter().chain(new.get("id")) {
+                reaches.extend(self.ids.get(id).into_iter().flatten());
+            }
+        }
+        if old.get("style") != new.get("style") {
+            reaches.insert(Reach::Element);
+        }
+        reaches
+    }
+
+    /// Which elements inserting or removing children may affect, relative to the parent.
+    pub fn structural_changes(&self) -> &HashSet<Reach> {
+        &self.structural
+    }
+
+    /// Record the features of `selector`, whose subject's style depends on them
+    /// with `reach`.
+    fn add_selector(&mut self, selector: &Selector, reach: Reach) {
+        match *selector {
+            Selector::Simple(ref compound) => self.add_compound(compound, reach),
+            Selector::Complex(ref left, combinator, ref right) => {
+                self.add_compound(right, reach);
+                // Everything to the right of a compound is within its subtree, or that of
+                // one of its siblings, whatever combinators follow.
+                let left_reach = match combinator {
+                    Combinator::Descendant | Combinator::Child => Reach::Descendants,
+                    Combinator::NextSibling | Combinator::SubsequentSibling => {
+                        self.structural.insert(Reach::Descendants);
+                        Reach::LaterSiblings
+                    }
+                };
+                self.add_selector(left, compose(left_reach, reach));
+            }
+        }
+    }
+
+    fn add_compound(&mut self, compound: &SimpleSelector, reach: Reach) {
+        for class in &compound.class {
+            self.classes.entry(class.clone()).or_default().insert(reach);
+        }
+        if let Some(ref id) = compound.id {
+            self.ids.entry(id.clone()).or_default().insert(reach);
+        }
+        for pseudo_class in &compound.pseudo_classes {
+            match *pseudo_class {
+                PseudoClass::Root => {}
+                PseudoClass::Empty => { self.structural.insert(reach); }
+                PseudoClass::FirstChild | PseudoClass::LastChild | PseudoClass::OnlyChild |
+                PseudoClass::FirstOfType | PseudoClass::LastOfType | PseudoClass::OnlyOfType |
+                PseudoClass::NthOfType(_) | PseudoClass::NthLastOfType(_) => {
+                    self.structural.insert(Reach::Descendants);
+                }
+                PseudoClass::NthChild(_, ref of) | PseudoClass::NthLastChild(_, ref of) => {
+                    self.structural.insert(Reach::Descendants);
+                    // Whether a sibling matches `of` changes the element's index.
+                    for selector in of {
+                        self.add_selector(selector, compose(Reach::Siblings, reach));
+                    }
+                }
+                PseudoClass::Not(ref list) | PseudoClass::Is(ref list) | PseudoClass::Where(ref list) => {
+                    for selector in list {
+                        self.add_selector(selector, reach);
+                    }
+                }
+                PseudoClass::Has(ref list) => {
+                    self.structural.insert(Reach::Document);
+                    for relative in list {
+                        self.add_selector(&relative.selector, Reach::Document);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The reach of a feature `inner` that affects an element which in turn affects the
+/// subject with `outer`. Only the relation nearest the changed element matters: the
+/// descendants of an element's descendants are its descendants, and so on.
+fn compose(inner: Reach, outer: Reach) -> Reach {
+    match (inner, outer) {
+        (Reach::Document, _) | (_, Reach::Document) => Reach::Document,
+        (Reach::Element, outer) => outer,
+        (inner, _) => inner,
+    }
+}
+
+#[test]
+fn reach_of_class_changes() {
+    let stylesheet = crate::css::parse("
+        .a { } .b p { } .c + p span { } :is(.d .e) + p { } li:nth-child(odd of .f) { } div:has(.g) { } #h > p { }
+    ".to_string());
+    let selectors = stylesheet.rules.iter().filter_map(|r| r.as_style()).flat_map(|r| &r.selectors);
+    let map = InvalidationMap::new(selectors);
+    let reaches = |class: &str| -> Vec<Reach> {
+        let mut reaches: Vec<Reach> = map.classes[class].iter().copied().collect();
+        reaches.sort_by_key(|r| *r as u8);
+        reaches
+    };
+    assert_eq!(vec![Reach::Element], reaches("a"));
+    assert_eq!(vec![Reach::Descendants], reaches("b"));
+    assert_eq!(vec![Reach::LaterSiblings], reaches("c"));
+    assert_eq!(vec![Reach::Descendants], reaches("d"));
+    assert_eq!(vec![Reach::LaterSiblings], reaches("e"));
+    assert_eq!(vec![Reach::Siblings], reaches("f"));
+    assert_eq!(vec![Reach::Document], reaches("g"));
+    assert!(map.ids["h"].contains(&Reach::Descendants));
+
+    let attributes = |class: &str| -> AttrMap { [("class".to_string(), class.to_string())].into_iter().collect() };
+    let changes = map.attribute_changes(&attributes("a x"), &attributes("x b"));
+    assert_eq!([Reach::Element, Reach::Descendants].into_iter().collect::<HashSet<_>>(), changes);
+    assert!(map.attribute_changes(&attributes("x"), &attributes("y")).is_empty());
+}
@@ -2,13 +2,10 @@ use crate::style::{
     Display,
     StyledNode,
 };
-use crate::css::{
-    Unit::Px,
-    Value::{
-        Keyword,
-        Length,
-    },
-};
+use std::ops::Range;
+use crate::computed::{ComputedStyle, DisplayInside, DisplayOutside, FontValues, LengthPercentageOrAuto, LineHeight, TextAlign, WhiteSpace};
+use crate::css::LengthContext;
+use crate::font;
 
 #[derive(Clone, Copy, Default)]
 pub struct Dimensions {
@@ -36,48 +33,90 @@ pub struct EdgeSizes {
     pub bottom: f32,
 }
 
+#[derive(Clone)]
 pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
 }
 
+#[derive(Clone)]
 pub enum BoxType<'a> {
     BlockNode(&'a StyledNode<'a>),
+    /// An inline box. Once laid out, it is split into one fragment for each line it spans.
     InlineNode(&'a StyledNode<'a>),
+    /// An inline-level box that lays out its contents like a block, as for `inline-block`.
+    InlineBlockNode(&'a StyledNode<'a>),
     AnonymousBlock,
+    /// The text of a text node, or the content generated for a pseudo-element, which is
+    /// the run's only child.
+    TextRun(&'a StyledNode<'a>),
+    /// One line of an anonymous block's inline content, created by layout.
+    LineBox,
+    /// The part of a text node, or of a pseudo-element's content, on one line, after
+    /// white space processing.
+    TextFragment(&'a StyledNode<'a>, String),
 }
 
+/// Lay out a style tree. The initial containing block is also the viewport that
+/// viewport-relative lengths refer to.
 pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions) -> LayoutBox<'a> {
+    let viewport = containing_block.content;
     containing_block.content.height = 0.0;
 
     let mut root_box = build_layout_tree(node);
-    root_box.layout(containing_block);
+    root_box.layout(containing_block, viewport);
     root_box
 }
 
 /// Build the tree of LayoutBoxes, but don't perform any layout calculations yet.
 fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+    if style_node.pseudo_element().is_none() && style_node.text().is_some() {
+        return LayoutBox::new(BoxType::TextRun(style_node));
+    }
     let mut root = LayoutBox::new(match style_node.display() {
-        Display::Block => BoxType::BlockNode(style_node),
-        Display::Inline => BoxType::InlineNode(style_node),
-        Display::None => panic!("Root node has display: none.")
+        Display::Box { outside: DisplayOutside::Block, .. } => BoxType::BlockNode(style_node),
+        Display::Box { inside: DisplayInside::FlowRoot, .. } => BoxType::InlineBlockNode(style_node),
+        Display::Box { inside: DisplayInside::Flow, .. } => BoxType::InlineNode(style_node),
+        display => panic!("Root node has display: {display:?}.")
     });
 
-    for child in &style_node.children {
+    // A list item's marker is inline content at the start of the item.
+    if let Some(marker) = style_node.marker.as_deref() {
+        let mut marker_box = LayoutBox::new(BoxType::InlineNode(marker));
+        marker_box.children.push(LayoutBox::new(BoxType::TextRun(marker)));
+        root.get_inline_container().children.push(marker_box);
+    }
+    // The content of a pseudo-element is a run of text inside its box.
+    if style_node.text().is_some() {
+        root.get_inline_container().children.push(LayoutBox::new(BoxType::TextRun(style_node)));
+    }
+    add_child_boxes(&mut root, style_node);
+    root
+}
+
+/// Add the boxes generated by the children of `style_node` to `parent`. Generated
+/// `::before` and `::after` boxes are the first and last children, and the children
+/// of `display: contents` elements take the place of the element.
+fn add_child_boxes<'a>(parent: &mut LayoutBox<'a>, style_node: &'a StyledNode<'a>) {
+    let children = style_node.before.as_deref().into_iter()
+        .chain(&style_node.children)
+        .chain(style_node.after.as_deref());
+    for child in children {
         match child.display() {
-            Display::Block => root.children.push(build_layout_tree(child)),
-            Display::Inline => root.get_inline_container().children.push(build_layout_tree(child)),
+            Display::Box { outside: DisplayOutside::Block, .. } => parent.children.push(build_layout_tree(child)),
+            Display::Box { outside: DisplayOutside::Inline, .. } =>
+                parent.get_inline_container().children.push(build_layout_tree(child)),
+            Display::Contents => add_child_boxes(parent, child),
             Display::None => {} // Skip nodes with `display: none;`
         }
     }
-    root
 }
 
 impl<'a> LayoutBox<'a> {
     fn new(box_type: BoxType) -> LayoutBox {
         LayoutBox {
-            box_type: box_type,
+            box_type,
             dimensions: Default::default(), // initially set all fields to 0.0
             children: Vec::new(),
         }
@@ -85,15 +124,17 @@ impl<'a> LayoutBox<'a> {
 
     fn get_style_node(&self) -> &'a StyledNode<'a> {
         match self.box_type {
-            BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
-            BoxType::AnonymousBlock => panic!("Anonymous block box has no style node")
+            BoxType::BlockNode(node) | BoxType::InlineNode(node) | BoxType::InlineBlockNode(node) |
+            BoxType::TextRun(node) | BoxType::TextFragment(node, _) => node,
+            BoxType::AnonymousBlock | BoxType::LineBox => panic!("Anonymous box has no style node")
         }
     }
 
     fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
-            BoxType::BlockNode(_) => {
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock | BoxType::TextRun(_) | BoxType::LineBox |
+            BoxType::TextFragment(..) => self,
+            BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) => {
                 match self.children.last() {
                     Some(&LayoutBox { box_type: BoxType::AnonymousBlock, .. }) => {}
                     _ => self.children.push(LayoutBox::new(BoxType::AnonymousBlock))
@@ -103,140 +144,663 @@ impl<'a> LayoutBox<'a> {
         }
     }
 
-    fn layout(&mut self, containing_block: Dimensions) {
-        match self.box_type {
-            BoxType::BlockNode(_) => self.layout_block(containing_block),
-            BoxType::InlineNode(_) => {} // TODO
-            BoxType::AnonymousBlock => {} // TODO
-        }
+    /// Lay out this box and its descendants in three passes: widths flow down from
+    /// containing blocks, heights flow up from children, and positions flow down again.
+    /// With the `parallel` feature, siblings are visited in parallel in each pass.
+    fn layout(&mut self, containing_block: Dimensions, viewport: Rect) {
+        self.assign_widths(containing_block.content.width, viewport);
+        self.assign_heights(containing_block.content.width, viewport);
+        self.assign_positions(containing_block);
     }
 
-    fn layout_block(&mut self, containing_block: Dimensions) {
-        // Child width can depend on parent width,
-        // so we need to calculate this box's width
-        // before laying out its children
-        self.calculate_block_width(containing_block);
-
-        // Determine where the box is located within its container
-        self.calculate_block_position(containing_block);
+    /// Child width can depend on parent width, so this box's width is calculated
+    /// before its children's.
+    fn assign_widths(&mut self, containing_width: f32, viewport: Rect) {
+        // Percentages in margins, padding and width all refer to the containing block's width.
+        let lengths = length_context(containing_width, viewport);
+        let width = match self.box_type {
+            BoxType::BlockNode(_) => {
+                self.calculate_block_width(containing_width, &lengths);
+                self.calculate_vertical_edges(&lengths);
+                self.dimensions.content.width
+            }
+            BoxType::InlineBlockNode(_) => {
+                self.calculate_inline_block_width(containing_width, viewport);
+                self.calculate_vertical_edges(&lengths);
+                self.dimensions.content.width
+            }
+            BoxType::AnonymousBlock => {
+                self.dimensions.content.width = containing_width;
+                containing_width
+            }
+            // Inline boxes are sized by line layout, and their contents share their containing block.
+            BoxType::InlineNode(_) => containing_width,
+            BoxType::TextRun(_) | BoxType::LineBox | BoxType::TextFragment(..) => return,
+        };
+        for_each_child(&mut self.children, |_, child| child.assign_widths(width, viewport));
+    }
 
-        // Recursively lay out the children of this box
-        self.layout_block_children();
+    /// Parent height can depend on child height, so children's heights are calculated first.
+    fn assign_heights(&mut self, containing_width: f32, viewport: Rect) {
+        let style_node = match self.box_type {
+            BoxType::BlockNode(node) | BoxType::InlineBlockNode(node) => node,
+            BoxType::AnonymousBlock | BoxType::InlineNode(_) => {
+                // Inline-blocks in inline content are sized before it is broken into lines.
+                let width = match self.box_type {
+                    BoxType::AnonymousBlock => self.dimensions.content.width,
+                    _ => containing_width,
+                };
+                for_each_child(&mut self.children, |_, child| child.assign_heights(width, viewport));
+                return;
+            }
+            BoxType::TextRun(_) | BoxType::LineBox | BoxType::TextFragment(..) => return,
+        };
+        let width = self.dimensions.content.width;
+        for_each_child(&mut self.children, |_, child| {
+            child.assign_heights(width, viewport);
+            if let BoxType::AnonymousBlock = child.box_type {
+                child.layout_lines(style_node, viewport);
+            }
+        });
 
-        // Parent height can depend on child height,
-        // so calculate_height() must be called after the children are laid out
-        self.calculate_block_height();
+        // Children are stacked vertically, each below the previous one.
+        self.dimensions.content.height = sum(self.children.iter().map(|child| child.dimensions.margin_box().height));
+        self.calculate_block_height(&length_context(containing_width, viewport));
     }
 
-    fn calculate_block_width(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
+    /// Place this box within its container, then its children within it.
+    fn assign_positions(&mut self, containing_block: Dimensions) {
+        match self.box_type {
+            BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) => {}
+            // Line layout places inline content relative to the anonymous block.
+            BoxType::AnonymousBlock => {
+                self.calculate_block_position(containing_block);
+                let Rect { x, y, .. } = self.dimensions.content;
+                for_each_child(&mut self.children, |_, child| child.translate(x, y));
+                return;
+            }
+            _ => return,
+        }
+        self.calculate_block_position(containing_block);
+
+        // Each child's containing block has the height of the siblings above it, which
+        // is where the child goes.
+        let mut d = self.dimensions;
+        d.content.height = 0.0;
+        let containing_blocks: Vec<Dimensions> = self.children.iter().map(|child| {
+            let block = d;
+            d.content.height += child.dimensions.margin_box().height;
+            block
+        }).collect();
+        for_each_child(&mut self.children, |i, child| child.assign_positions(containing_blocks[i]));
+    }
 
-        let auto = Keyword("auto".to_string());
-        let mut width = style.value("width").unwrap_or(auto.clone());
+    fn calculate_block_width(&mut self, containing_width: f32, lengths: &LengthContext) {
+        let style = self.get_style_node().style();
 
-        let zero = Length(0.0, Px);
+        // `None` stands for `auto`.
+        let width = style.box_model.width.to_px(lengths);
 
-        let mut margin_left = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right = style.lookup("margin-right", "margin", &zero);
+        let mut margin_left = style.box_model.margin.left.to_px(lengths);
+        let mut margin_right = style.box_model.margin.right.to_px(lengths);
 
-        let border_left = style.lookup("border-left-width", "border-width", &zero);
-        let border_right = style.lookup("border-right-width", "border-width", &zero);
+        let border_left = style.border.width.left.to_px(lengths);
+        let border_right = style.border.width.right.to_px(lengths);
 
-        let padding_left = style.lookup("padding-left", "padding", &zero);
-        let padding_right = style.lookup("padding-right", "padding", &zero);
+        let padding_left = style.box_model.padding.left.to_px(lengths);
+        let padding_right = style.box_model.padding.right.to_px(lengths);
 
-        let total = sum([&margin_left, &margin_right, &border_left, &border_right,
-                        &padding_left, &padding_right, &width].iter().map(|v| v.to_px()));
+        let total = sum([margin_left, margin_right, width].iter().map(|v| v.unwrap_or(0.0))) +
+                    border_left + border_right + padding_left + padding_right;
 
         // If width isn't auto and the total is wider than the container,
         // treat auto margins as 0
-        if width != auto && total > containing_block.content.width {
-            if margin_left == auto {
-                margin_left = Length(0.0, Px);
-            }
-            if margin_right == auto {
-                margin_right = Length(0.0, Px);
-            }
+        if width.is_some() && total > containing_width {
+            margin_left.get_or_insert(0.0);
+            margin_right.get_or_insert(0.0);
         }
 
         // Adjust used values so that the above sum equals `containing_block.width`.
         // Each arm of the match should increase the total width by exactly `underflow`,
         // and afterward all values should be absolute length in px.
-        let underflow = containing_block.content.width - total;
+        let underflow = containing_width - total;
 
-        match (width == auto, margin_left == auto, margin_right == auto) {
+        let (width, margin_left, margin_right) = match (width, margin_left, margin_right) {
             // If the values are overconstrained, calculate margin_right.
-            (false, false, false) => {
-                margin_right = Length(margin_right.to_px() + underflow, Px);
-            }
+            (Some(width), Some(left), Some(right)) => (width, left, right + underflow),
             // If exactly one size is auto, its used value follows from the equality.
-            (false, false, true) => { margin_right = Length(underflow, Px); }
-            (false, true, false) => { margin_left = Length(underflow, Px); }
+            (Some(width), Some(left), None) => (width, left, underflow),
+            (Some(width), None, Some(right)) => (width, underflow, right),
             // If width is set to auto, any other auto values become 0.
-            (true, _, _) => {
-                if margin_left == auto { margin_left = Length(0.0, Px); }
-                if margin_right == auto { margin_right = Length(0.0, Px); }
+            (None, left, right) => {
+                let (left, right) = (left.unwrap_or(0.0), right.unwrap_or(0.0));
                 if underflow >= 0.0 {
                     // Expand width to fill the overflow.
-                    width = Length(underflow, Px);
+                    (underflow, left, right)
                 } else {
                     // Width can't be negative. Adjust the right margin instead.
-                    width = Length(0.0, Px);
-                    margin_right = Length(margin_right.to_px() + underflow, Px);
+                    (0.0, left, right + underflow)
                 }
             }
             // If margin-left and margin-right are both auto, their used values are equal.
-            (false, true, true) => {
-                margin_left = Length(underflow / 2.0, Px);
-                margin_right = Length(underflow / 2.0, Px);
-            }
-        }
+            (Some(width), None, None) => (width, underflow / 2.0, underflow / 2.0),
+        };
 
         let d = &mut self.dimensions;
-        d.content.width = width.to_px();
-        d.padding.left = padding_left.to_px();
-        d.padding.right = padding_right.to_px();
-        d.border.left = border_left.to_px();
-        d.border.right = border_right.to_px();
-        d.margin.left = margin_left.to_px();
-        d.margin.right = margin_right.to_px();
+        d.content.width = width;
+        d.padding.left = padding_left;
+        d.padding.right = padding_right;
+        d.border.left = border_left;
+        d.border.right = border_right;
+        d.margin.left = margin_left;
+        d.margin.right = margin_right;
     }
 
-    fn calculate_block_position(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
+    /// Inline-blocks shrink to fit their contents when their width is `auto`, and their
+    /// `auto` margins are 0.
+    fn calculate_inline_block_width(&mut self, containing_width: f32, viewport: Rect) {
+        let style = self.get_style_node().style();
+        let lengths = length_context(containing_width, viewport);
+        let edges = inline_edges(style, &lengths);
         let d = &mut self.dimensions;
+        (d.margin.left, d.margin.right) = (edges.margin.left, edges.margin.right);
+        (d.border.left, d.border.right) = (edges.border.left, edges.border.right);
+        (d.padding.left, d.padding.right) = (edges.padding.left, edges.padding.right);
+        let width = style.box_model.width.to_px(&lengths);
+        self.dimensions.content.width = width.unwrap_or_else(|| {
+            let d = self.dimensions;
+            let available = containing_width - d.margin_box().width;
+            let (min, max) = self.content_intrinsic_widths(viewport);
+            available.max(min).min(max)
+        });
+    }
 
-        let zero = Length(0.0, Px);
+    /// The narrowest width the margin box can have without its contents overflowing,
+    /// and the width it takes if its lines are never wrapped.
+    fn intrinsic_widths(&self, viewport: Rect) -> (f32, f32) {
+        match self.box_type {
+            BoxType::BlockNode(node) | BoxType::InlineBlockNode(node) => {
+                // Percentages refer to the containing block, whose width isn't known yet.
+                let lengths = length_context(0.0, viewport);
+                let style = node.style();
+                let edges = inline_edges(style, &lengths).margin_box().width;
+                let (min, max) = match style.box_model.width {
+                    LengthPercentageOrAuto::LengthPercentage(ref width) if !width.has_percentage() => {
+                        let width = width.to_px(&lengths);
+                        (width, width)
+                    }
+                    _ => self.content_intrinsic_widths(viewport),
+                };
+                (min + edges, max + edges)
+            }
+            BoxType::AnonymousBlock => inline_intrinsic_widths(&self.children, viewport),
+            _ => (0.0, 0.0),
+        }
+    }
 
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
-        d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
-        d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+    fn content_intrinsic_widths(&self, viewport: Rect) -> (f32, f32) {
+        self.children.iter()
+            .map(|child| child.intrinsic_widths(viewport))
+            .fold((0.0, 0.0), |(min, max), (child_min, child_max)| (min.max(child_min), max.max(child_max)))
+    }
 
+    /// Break the inline content of an anonymous block into line boxes that fit its
+    /// width, and give it the height of its lines. `container` is the block container
+    /// whose inline content this is, which decides alignment, indentation and the
+    /// minimum height of each line. Inline boxes that span several lines are split
+    /// into one fragment per line, and positions are relative to the anonymous block.
+    fn layout_lines(&mut self, container: &StyledNode, viewport: Rect) {
+        let style = container.style();
+        let available = self.dimensions.content.width;
+        let lengths = length_context(available, viewport);
+        let mut items = Vec::new();
+        collect_inline_items(&self.children, &mut items, &mut true);
+        let widths: Vec<f32> = items.iter()
+            .map(|item| item.width(&lengths, |atomic| atomic.dimensions.margin_box().width))
+            .collect();
+        let indent = style.text.text_indent.to_px(&lengths);
+
+        let mut lines = Vec::new();
+        let mut open = Vec::new();
+        let mut y = 0.0;
+        for (n, range) in break_lines(&items, &widths, available, indent).into_iter().enumerate() {
+            let indent = if n == 0 { indent } else { 0.0 };
+            let Some(mut line) = build_line(&items, &widths, range, &mut open, available - indent, style, &lengths) else {
+                continue;
+            };
+            line.translate(indent, 0.0);
+
+            // Boxes are aligned on their baselines, and the line is tall enough for
+            // each box's line height, and for the container's.
+            let (mut above, mut below) = half_leading_extent(style);
+            for child in &line.children {
+                line_extent(child, &mut above, &mut below);
+            }
+            for child in &mut line.children {
+                align_to_baseline(child, y + above);
+            }
+            line.dimensions.content = Rect { x: 0.0, y, width: available, height: above + below };
+            y += above + below;
+            lines.push(line);
+        }
+        self.children = lines;
+        self.dimensions.content.height = y;
+    }
+
+    /// Move this box and its descendants.
+    fn translate(&mut self, dx: f32, dy: f32) {
+        self.dimensions.content.x += dx;
+        self.dimensions.content.y += dy;
+        for child in &mut self.children {
+            child.translate(dx, dy);
+        }
+    }
+
+    fn calculate_vertical_edges(&mut self, lengths: &LengthContext) {
+        let style = self.get_style_node().style();
+        let d = &mut self.dimensions;
+
+        d.margin.top = style.box_model.margin.top.to_px(lengths).unwrap_or(0.0);
+        d.margin.bottom = style.box_model.margin.bottom.to_px(lengths).unwrap_or(0.0);
+        d.border.top = style.border.width.top.to_px(lengths);
+        d.border.bottom = style.border.width.bottom.to_px(lengths);
+        d.padding.top = style.box_model.padding.top.to_px(lengths);
+        d.padding.bottom = style.box_model.padding.bottom.to_px(lengths);
+    }
+
+    /// Determine where the box is located within its container.
+    fn calculate_block_position(&mut self, containing_block: Dimensions) {
+        let d = &mut self.dimensions;
         d.content.x = containing_block.content.x +
                 d.margin.left + d.border.left + d.padding.left;
         d.content.y = containing_block.content.height + containing_block.content.y +
                 d.margin.top + d.border.top + d.padding.top;
     }
 
-    fn layout_block_children(&mut self) {
-        let d = &mut self.dimensions;
-        for child in &mut self.children {
-            child.layout(*d);
-            // Increment the height so each child is laid out below the previous one.
-            d.content.height = d.content.height + child.dimensions.margin_box().height;
+    fn calculate_block_height(&mut self, lengths: &LengthContext) {
+        // The containing block's height isn't known here, so percentage heights behave as `auto`.
+        match self.get_style_node().style().box_model.height {
+            LengthPercentageOrAuto::LengthPercentage(ref height) if !height.has_percentage() =>
+                self.dimensions.content.height = height.to_px(lengths),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+fn for_each_child<'a>(children: &mut [LayoutBox<'a>], f: impl Fn(usize, &mut LayoutBox<'a>) + Sync) {
+    children.iter_mut().enumerate().for_each(|(i, child)| f(i, child))
+}
+
+#[cfg(feature = "parallel")]
+fn for_each_child<'a>(children: &mut [LayoutBox<'a>], f: impl Fn(usize, &mut LayoutBox<'a>) + Sync) {
+    use rayon::prelude::*;
+    children.par_iter_mut().enumerate().for_each(|(i, child)| f(i, child))
+}
+
+/// A piece of inline content, in document order.
+enum InlineItem<'b, 'a> {
+    /// The start of an inline box, where its left margin, border and padding go.
+    Start(&'b LayoutBox<'a>),
+    /// The end of an inline box, where its right margin, border and padding go.
+    End(&'b LayoutBox<'a>),
+    /// A word or a space, after white space processing. Collapsible spaces are removed
+    /// at the start and end of a line.
+    Text { node: &'a StyledNode<'a>, text: String, collapsible: bool, break_after: bool },
+    /// An inline-block, or a block inside an inline box, placed as a whole.
+    Atomic(&'b LayoutBox<'a>),
+    /// A preserved newline.
+    LineBreak,
+}
+
+impl InlineItem<'_, '_> {
+    fn width(&self, lengths: &LengthContext, atomic_width: impl Fn(&LayoutBox) -> f32) -> f32 {
+        match *self {
+            InlineItem::Start(inline) => {
+                let d = inline_edges(inline.get_style_node().style(), lengths);
+                d.margin.left + d.border.left + d.padding.left
+            }
+            InlineItem::End(inline) => {
+                let d = inline_edges(inline.get_style_node().style(), lengths);
+                d.margin.right + d.border.right + d.padding.right
+            }
+            InlineItem::Text { node, ref text, .. } => text_width(text, &node.style().font),
+            InlineItem::Atomic(atomic) => atomic_width(atomic),
+            InlineItem::LineBreak => 0.0,
         }
     }
 
-    fn calculate_block_height(&mut self) {
-        if let Some(Length(h, Px)) = self.get_style_node().value("height") {
-            self.dimensions.content.height = h;
+    /// Words and atomic boxes: what a line needs at least one of not to be empty.
+    fn is_content(&self) -> bool {
+        matches!(*self, InlineItem::Text { collapsible: false, .. } | InlineItem::Atomic(_))
+    }
+
+    /// A block inside an inline box goes on a line of its own.
+    fn is_block(&self) -> bool {
+        matches!(*self, InlineItem::Atomic(LayoutBox { box_type: BoxType::BlockNode(_), .. }))
+    }
+}
+
+/// Flatten the inline boxes in `boxes` into items. `after_space` tells whether the
+/// content so far ends in a collapsible space, so that spaces collapse across boxes.
+fn collect_inline_items<'b, 'a>(boxes: &'b [LayoutBox<'a>], items: &mut Vec<InlineItem<'b, 'a>>, after_space: &mut bool) {
+    for layout_box in boxes {
+        match layout_box.box_type {
+            BoxType::InlineNode(_) => {
+                items.push(InlineItem::Start(layout_box));
+                collect_inline_items(&layout_box.children, items, after_space);
+                items.push(InlineItem::End(layout_box));
+            }
+            BoxType::TextRun(node) => collect_text(node, node.text().unwrap_or_default(), items, after_space),
+            BoxType::InlineBlockNode(_) | BoxType::BlockNode(_) => {
+                items.push(InlineItem::Atomic(layout_box));
+                *after_space = false;
+            }
+            _ => {}
         }
     }
 }
 
+/// Split text into words and spaces following `white-space`: whether spaces collapse,
+/// whether newlines are preserved, and whether lines may wrap at spaces.
+fn collect_text<'b, 'a>(node: &'a StyledNode<'a>, text: &str, items: &mut Vec<InlineItem<'b, 'a>>, after_space: &mut bool) {
+    let white_space = node.style().text.white_space;
+    let collapse = matches!(white_space, WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine);
+    let preserve_newlines = matches!(white_space, WhiteSpace::Pre | WhiteSpace::PreWrap | WhiteSpace::PreLine);
+    let wrap = matches!(white_space, WhiteSpace::Normal | WhiteSpace::PreWrap | WhiteSpace::PreLine);
+    let mut word = String::new();
+    let push_word = |word: &mut String, items: &mut Vec<InlineItem<'b, 'a>>| if !word.is_empty() {
+        items.push(InlineItem::Text { node, text: std::mem::take(word), collapsible: false, break_after: false });
+    };
+    for c in text.chars() {
+        if c == '\n' && preserve_newlines {
+            push_word(&mut word, items);
+            items.push(InlineItem::LineBreak);
+            *after_space = true;
+        } else if c.is_ascii_whitespace() {
+            push_word(&mut word, items);
+            if !collapse || !*after_space {
+                items.push(InlineItem::Text { node, text: " ".to_string(), collapsible: collapse, break_after: wrap });
+            }
+            *after_space = collapse;
+        } else {
+            word.push(c);
+            *after_space = false;
+        }
+    }
+    push_word(&mut word, items);
+}
+
+/// Whether a line may end after `items[i]`: after spaces that allow wrapping, and
+/// on either side of an atomic box. The ends of inline boxes stay on the line before.
+fn break_after(items: &[InlineItem], i: usize) -> bool {
+    if matches!(items.get(i + 1), Some(InlineItem::End(_))) {
+        return false;
+    }
+    let after = match items[i] {
+        InlineItem::Text { break_after, .. } => break_after,
+        InlineItem::Atomic(_) => true,
+        InlineItem::End(_) => items[..i].iter().rev()
+            .find(|item| !matches!(item, InlineItem::End(_)))
+            .is_some_and(|item| matches!(*item, InlineItem::Text { break_after: true, .. } | InlineItem::Atomic(_))),
+        _ => false,
+    };
+    after || matches!(items.get(i + 1), Some(InlineItem::Atomic(_)))
+}
+
+/// Choose where lines end, filling each line with as many items as fit in
+/// `available` and breaking at the last opportunity before the first that doesn't.
+/// An item too wide for an empty line overflows it.
+fn break_lines(items: &[InlineItem], widths: &[f32], available: f32, indent: f32) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let (mut start, mut width, mut opportunity) = (0, indent, None);
+    let mut i = 0;
+    while i < items.len() {
+        if matches!(items[i], InlineItem::LineBreak) || items[i].is_block() {
+            if items[i].is_block() {
+                lines.push(start..i);
+                start = i;
+            }
+            lines.push(start..i + 1);
+            (start, width, opportunity) = (i + 1, 0.0, None);
+            i += 1;
+            continue;
+        }
+        if items[i].is_content() && width + widths[i] > available && items[start..i].iter().any(InlineItem::is_content) {
+            if let Some(end) = opportunity {
+                lines.push(start..end + 1);
+                start = end + 1;
+                width = widths[start..i].iter().sum();
+                opportunity = (start..i).rev().find(|&j| break_after(items, j));
+                continue;
+            }
+        }
+        width += widths[i];
+        if break_after(items, i) {
+            opportunity = Some(i);
+        }
+        i += 1;
+    }
+    lines.push(start..items.len());
+    lines
+}
+
+/// Build the line box for `items[range]`, or `None` if the line has no content.
+/// `open` holds the inline boxes that started on earlier lines and haven't ended, and
+/// is updated for the next line. Boxes are placed horizontally, at y = 0.
+fn build_line<'b, 'a>(items: &[InlineItem<'b, 'a>], widths: &[f32], range: Range<usize>, open: &mut Vec<&'b LayoutBox<'a>>,
+                      available: f32, container: &ComputedStyle, lengths: &LengthContext) -> Option<LayoutBox<'a>> {
+    let mut content = range.clone().filter(|&i| items[i].is_content());
+    let (first, last) = match content.next() {
+        Some(first) => (first, content.next_back().unwrap_or(first)),
+        None => {
+            for item in &items[range] {
+                match *item {
+                    InlineItem::Start(inline) => open.push(inline),
+                    InlineItem::End(_) => { open.pop(); }
+                    _ => {}
+                }
+            }
+            return None;
+        }
+    };
+    let kept = |i: usize| !matches!(items[i], InlineItem::Text { collapsible: true, .. }) || (first..=last).contains(&i);
+    let width: f32 = range.clone().filter(|&i| kept(i)).map(|i| widths[i]).sum();
+    let mut x = match container.text.text_align {
+        TextAlign::Right | TextAlign::End => available - width,
+        TextAlign::Center => (available - width) / 2.0,
+        TextAlign::Left | TextAlign::Start | TextAlign::Justify => 0.0,
+    };
+
+    let mut line = LayoutBox::new(BoxType::LineBox);
+    // Boxes that continue from the previous line start without their left edges.
+    let mut fragments: Vec<LayoutBox> = open.iter().map(|inline| inline_fragment(inline, x, lengths)).collect();
+    for i in range.filter(|&i| kept(i)) {
+        match items[i] {
+            InlineItem::Start(inline) => {
+                let mut fragment = inline_fragment(inline, x, lengths);
+                let d = &mut fragment.dimensions;
+                let edges = inline_edges(inline.get_style_node().style(), lengths);
+                (d.margin.left, d.border.left, d.padding.left) = (edges.margin.left, edges.border.left, edges.padding.left);
+                x += widths[i];
+                d.content.x = x;
+                fragments.push(fragment);
+                open.push(inline);
+            }
+            InlineItem::End(inline) => {
+                let mut fragment = fragments.pop().unwrap();
+                let d = &mut fragment.dimensions;
+                let edges = inline_edges(inline.get_style_node().style(), lengths);
+                (d.margin.right, d.border.right, d.padding.right) = (edges.margin.right, edges.border.right, edges.padding.right);
+                d.content.width = x - d.content.x;
+                x += widths[i];
+                fragments.last_mut().unwrap_or(&mut line).children.push(fragment);
+                open.pop();
+            }
+            InlineItem::Text { node, ref text, .. } => {
+                let parent = fragments.last_mut().unwrap_or(&mut line);
+                // Consecutive words of the same text make up one fragment.
+                match parent.children.last_mut() {
+                    Some(LayoutBox { box_type: BoxType::TextFragment(fragment_node, ref mut fragment_text), dimensions, .. })
+                        if std::ptr::eq(*fragment_node, node) => {
+                        fragment_text.push_str(text);
+                        dimensions.content.width += widths[i];
+                    }
+                    _ => {
+                        let mut fragment = LayoutBox::new(BoxType::TextFragment(node, text.clone()));
+                        fragment.dimensions.content.x = x;
+                        fragment.dimensions.content.width = widths[i];
+                        parent.children.push(fragment);
+                    }
+                }
+                x += widths[i];
+            }
+            InlineItem::Atomic(atomic) => {
+                let mut atomic = atomic.clone();
+                let mut containing_block = Dimensions::default();
+                containing_block.content.x = x;
+                atomic.assign_positions(containing_block);
+                x += widths[i];
+                fragments.last_mut().unwrap_or(&mut line).children.push(atomic);
+            }
+            InlineItem::LineBreak => {}
+        }
+    }
+    // Boxes that continue on the next line end without their right edges.
+    while let Some(mut fragment) = fragments.pop() {
+        fragment.dimensions.content.width = x - fragment.dimensions.content.x;
+        fragments.last_mut().unwrap_or(&mut line).children.push(fragment);
+    }
+    Some(line)
+}
+
+/// A fragment of an inline box starting at `x`, with its vertical borders and padding.
+fn inline_fragment<'a>(inline: &LayoutBox<'a>, x: f32, lengths: &LengthContext) -> LayoutBox<'a> {
+    let mut fragment = LayoutBox::new(inline.box_type.clone());
+    let edges = inline_edges(inline.get_style_node().style(), lengths);
+    let d = &mut fragment.dimensions;
+    d.content.x = x;
+    (d.border.top, d.border.bottom) = (edges.border.top, edges.border.bottom);
+    (d.padding.top, d.padding.bottom) = (edges.padding.top, edges.padding.bottom);
+    fragment
+}
+
+/// The margins, borders and padding of an inline-level box, with `auto` margins as 0.
+fn inline_edges(style: &ComputedStyle, lengths: &LengthContext) -> Dimensions {
+    let margin = |side: &LengthPercentageOrAuto| side.to_px(lengths).unwrap_or(0.0);
+    let (m, b, p) = (&style.box_model.margin, &style.border.width, &style.box_model.padding);
+    Dimensions {
+        content: Rect::default(),
+        margin: EdgeSizes { left: margin(&m.left), right: margin(&m.right), top: margin(&m.top), bottom: margin(&m.bottom) },
+        border: EdgeSizes { left: b.left.to_px(lengths), right: b.right.to_px(lengths), top: b.top.to_px(lengths), bottom: b.bottom.to_px(lengths) },
+        padding: EdgeSizes { left: p.left.to_px(lengths), right: p.right.to_px(lengths), top: p.top.to_px(lengths), bottom: p.bottom.to_px(lengths) },
+    }
+}
+
+/// The narrowest and widest the inline content in `boxes` can be laid out.
+fn inline_intrinsic_widths(boxes: &[LayoutBox], viewport: Rect) -> (f32, f32) {
+    let lengths = length_context(0.0, viewport);
+    let mut items = Vec::new();
+    collect_inline_items(boxes, &mut items, &mut true);
+    let (mut min, mut max) = (0.0f32, 0.0f32);
+    // The widths of the words between break opportunities, and of the current line.
+    let (mut word, mut line) = (0.0, 0.0);
+    for (i, item) in items.iter().enumerate() {
+        let (item_min, item_max) = match *item {
+            InlineItem::Atomic(atomic) => atomic.intrinsic_widths(viewport),
+            _ => {
+                let width = item.width(&lengths, |_| 0.0);
+                (width, width)
+            }
+        };
+        if matches!(*item, InlineItem::LineBreak) || item.is_block() {
+            (word, line) = (0.0, 0.0);
+        }
+        line += item_max;
+        max = max.max(line);
+        if !matches!(*item, InlineItem::Text { collapsible: true, .. }) {
+            word += item_min;
+            min = min.max(word);
+        }
+        if break_after(&items, i) || item.is_block() {
+            word = 0.0;
+        }
+        if item.is_block() {
+            line = 0.0;
+        }
+    }
+    (min, max)
+}
+
+/// Extend `above` and `below` the baseline to fit a box on a line and its descendants.
+fn line_extent(layout_box: &LayoutBox, above: &mut f32, below: &mut f32) {
+    match layout_box.box_type {
+        BoxType::InlineNode(node) | BoxType::TextFragment(node, _) => {
+            let (box_above, box_below) = half_leading_extent(node.style());
+            (*above, *below) = (above.max(box_above), below.max(box_below));
+            for child in &layout_box.children {
+                line_extent(child, above, below);
+            }
+        }
+        // Atomic boxes sit on the baseline.
+        _ => *above = above.max(layout_box.dimensions.margin_box().height),
+    }
+}
+
+/// How far the line height of a box reaches above and below its baseline: its font's
+/// ascent and descent, plus half the leading on either side. A `normal` line height is
+/// the one the font recommends.
+fn half_leading_extent(style: &ComputedStyle) -> (f32, f32) {
+    let metrics = font::select(&style.font).metrics(style.font.size);
+    let line_height = match style.font.line_height {
+        LineHeight::Normal => metrics.ascent + metrics.descent + metrics.line_gap,
+        _ => style.font.used_line_height(),
+    };
+    let half_leading = (line_height - (metrics.ascent + metrics.descent)) / 2.0;
+    (metrics.ascent + half_leading, metrics.descent + half_leading)
+}
+
+/// Place a box on a line, and its descendants, with their baselines at `baseline`.
+/// The content area of an inline box is as tall as its font.
+fn align_to_baseline(layout_box: &mut LayoutBox, baseline: f32) {
+    match layout_box.box_type {
+        BoxType::InlineNode(node) | BoxType::TextFragment(node, _) => {
+            let (ascent, descent) = font_metrics(&node.style().font);
+            layout_box.dimensions.content.y = baseline - ascent;
+            layout_box.dimensions.content.height = ascent + descent;
+            for child in &mut layout_box.children {
+                align_to_baseline(child, baseline);
+            }
+        }
+        _ => {
+            let height = layout_box.dimensions.margin_box().height;
+            layout_box.translate(0.0, baseline - height);
+        }
+    }
+}
+
+/// The ascent and descent of the font used for `font`, in px.
+fn font_metrics(font: &FontValues) -> (f32, f32) {
+    let metrics = font::select(font).metrics(font.size);
+    (metrics.ascent, metrics.descent)
+}
+
+/// The advance width of `text` in the font used for `font`, in px.
+fn text_width(text: &str, font: &FontValues) -> f32 {
+    font::select(font).text_width(text, font.size)
+}
+
+/// Font-relative lengths are already absolute in computed values, so only
+/// percentages and viewport units are left to resolve during layout.
+fn length_context(percentage_base: f32, viewport: Rect) -> LengthContext {
+    LengthContext {
+        percentage_base,
+        viewport_width: viewport.width,
+        viewport_height: viewport.height,
+        ..Default::default()
+    }
+}
+
 impl Rect {
     fn expanded_by(self, edge: EdgeSizes) -> Rect {
         Rect {
@@ -264,3 +828,163 @@ fn sum<I>(iter: I) -> f32
     where I: Iterator<Item=f32> {
     iter.fold(0., |a, b| a + b)
 }
+
+#[test]
+fn generated_boxes_are_first_and_last_children() {
+    let root = crate::parser::parse("<div><p></p></div>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        div, p { display: block; }
+        div::before { content: "a"; display: block; }
+        div::after { content: "b"; display: block; }
+    "#.to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let layout_root = build_layout_tree(&styled);
+    let pseudo: Vec<_> = layout_root.children.iter()
+        .map(|child| child.get_style_node().pseudo_element())
+        .collect();
+    use crate::css::PseudoElement::{After, Before};
+    assert_eq!(vec![Some(Before), None, Some(After)], pseudo);
+}
+
+#[test]
+fn auto_margins_from_shorthand() {
+    let root = crate::parser::parse("<div><p></p></div>".to_string());
+    let stylesheet = crate::css::parse("p { width: 100px; margin: 0 auto; padding: 10px 20px; }".to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout_tree(&styled, viewport);
+    let p = &layout_root.children[0].dimensions;
+    assert_eq!((330.0, 330.0), (p.margin.left, p.margin.right));
+    assert_eq!((20.0, 10.0), (p.padding.left, p.padding.top));
+}
+
+#[test]
+fn percentage_and_viewport_lengths() {
+    let root = crate::parser::parse("<div><p></p></div>".to_string());
+    let stylesheet = crate::css::parse("div { width: 50%; } p { width: 50%; margin-left: 10%; height: 10vh; padding-top: 1em; }".to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+    let layout_root = layout_tree(&styled, viewport);
+    let p = &layout_root.children[0].dimensions;
+    assert_eq!((200.0, 40.0, 60.0, 16.0), (p.content.width, p.margin.left, p.content.height, p.padding.top));
+}
+
+#[test]
+fn math_functions_in_layout() {
+    let root = crate::parser::parse("<div><p></p></div>".to_string());
+    let stylesheet = crate::css::parse(
+        "p { width: calc(100% - 2 * 50px); height: clamp(10px, 10vh, 40px); margin-left: min(10%, 100px); }".to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+    let layout_root = layout_tree(&styled, viewport);
+    let p = &layout_root.children[0].dimensions;
+    assert_eq!((700.0, 40.0, 80.0), (p.content.width, p.content.height, p.margin.left));
+}
+
+#[test]
+fn boxes_for_display_values() {
+    let root = crate::parser::parse(
+        "<div><span class='contents'><p></p>x</span><b class='inline-block'><p></p></b><p class='flow-root'></p><ol><li>y</li></ol></div>".to_string());
+    let stylesheet = crate::css::parse("
+        .contents { display: contents; }
+        .contents::before { content: 'a'; }
+        .inline-block { display: inline-block; }
+        .flow-root { display: flow-root; }
+    ".to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let layout_root = build_layout_tree(&styled);
+    fn dump(layout_box: &LayoutBox, out: &mut String) {
+        out.push_str(match layout_box.box_type {
+            BoxType::BlockNode(_) => "block",
+            BoxType::TextRun(node) => node.text().unwrap(),
+            BoxType::InlineNode(_) => "inline",
+            BoxType::InlineBlockNode(_) => "inline-block",
+            BoxType::AnonymousBlock => "anonymous",
+            BoxType::LineBox | BoxType::TextFragment(..) => unreachable!(),
+        });
+        if !layout_box.children.is_empty() {
+            out.push('(');
+            for child in &layout_box.children {
+                dump(child, out);
+                out.push(' ');
+            }
+            out.push(')');
+        }
+    }
+    let mut boxes = String::new();
+    dump(&layout_root, &mut boxes);
+    // The `display: contents` span is replaced by its `::before`, paragraph and text;
+    // the list item's marker comes before its text.
+    assert_eq!("block(anonymous(inline(a ) ) block anonymous(x inline-block(block ) ) block block(block(anonymous(inline(1.  ) y ) ) ) )", boxes);
+}
+
+#[test]
+fn inline_layout() {
+    let root = crate::parser::parse(concat!(
+        "<div>",
+        "<p id='wrap'>aaaa bb <span>ccc dd </span>e</p>",
+        "<p id='center'>  <b>xx yyy</b></p>",
+        "<p id='pre'>a\nbb</p>",
+        "</div>",
+    ).to_string());
+    let stylesheet = crate::css::parse("
+        p { display: block; font-size: 10px; line-height: 20px; }
+        #wrap { width: 44px; }
+        span { padding-left: 2px; padding-right: 3px; }
+        #center { width: 100px; text-align: center; }
+        b { display: inline-block; padding: 1px; }
+        #pre { white-space: pre; }
+    ".to_string());
+    let styled = crate::style::style_tree(&root, &[&stylesheet]);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout_tree(&styled, viewport);
+    let text = |line: &LayoutBox| -> String {
+        fn collect(layout_box: &LayoutBox, out: &mut String) {
+            if let BoxType::TextFragment(_, ref text) = layout_box.box_type {
+                out.push_str(text);
+            }
+            layout_box.children.iter().for_each(|child| collect(child, out));
+        }
+        let mut out = String::new();
+        collect(line, &mut out);
+        out
+    };
+
+    let font = &styled.children[0].style().font;
+    let width = |text: &str| text_width(text, font);
+    let (ascent, descent) = font_metrics(font);
+    let half_leading = (20.0 - (ascent + descent)) / 2.0;
+
+    // Spaces at the ends of lines are removed, and the span is split across lines, with
+    // its left padding on the first fragment and its right padding on the last.
+    let wrap = &layout_root.children[0];
+    let lines = &wrap.children[0].children;
+    assert_eq!(vec!["aaaa bb", "ccc dd", "e"], lines.iter().map(text).collect::<Vec<_>>());
+    assert_eq!(60.0, wrap.dimensions.content.height);
+    let span = &lines[1].children[0].dimensions;
+    let span_width = width("ccc") + width(" ") + width("dd");
+    assert_eq!((2.0, span_width, 3.0), (span.content.x, span.content.width, span.padding.right));
+    // Text is as tall as its font, with half the leading above it.
+    let e = &lines[2].children[0].dimensions;
+    assert_eq!((0.0, wrap.dimensions.content.y + 40.0 + half_leading, ascent + descent),
+               (e.content.x, e.content.y, e.content.height));
+
+    // The inline-block shrinks to fit its text, and sits on the baseline in the middle.
+    let center = &layout_root.children[1];
+    let line = &center.children[0].children[0];
+    let b = &line.children[0].dimensions;
+    let b_width = width("xx") + width(" ") + width("yyy");
+    assert_eq!((b_width, (100.0 - b_width - 2.0) / 2.0 + 1.0, 22.0), (b.content.width, b.content.x, b.margin_box().height));
+    assert_eq!(22.0 + descent + half_leading, line.dimensions.content.height);
+    assert_eq!(center.dimensions.content.y + 1.0, b.content.y);
+
+    // Preserved newlines end lines.
+    let pre = &layout_root.children[2].children[0].children;
+    assert_eq!(vec!["a", "bb"], pre.iter().map(text).collect::<Vec<_>>());
+}
@@ -1,7 +1,19 @@
 
+pub mod color;
+pub mod computed;
+pub mod content;
 pub mod css;
 pub mod dom;
+pub mod invalidation;
 pub mod parser;
+pub mod properties;
+pub mod rule_index;
+pub mod serialize;
+pub mod sharing;
 pub mod style;
+pub mod supports;
+pub mod variables;
+pub mod media;
+pub mod font;
 pub mod layout;
 pub mod painting;
@@ -7,12 +7,16 @@ use std::path::Path;
 use std::fs::File;
 use std::env;
 
-mod css;
-mod dom;
-mod parser;
-mod style;
-mod layout;
-mod painting;
+use robinson::{
+    css,
+    dom,
+    font,
+    media,
+    parser,
+    style,
+    layout,
+    painting,
+};
 
 fn main() {
     println!("Hello, world!");
@@ -29,8 +33,7 @@ fn main() {
         "data-theme".to_string(),
         "dark".to_string()
     );
-    let mut children = Vec::new();
-    children.push(text);
+    let children = vec![text];
     let html = dom::elem("html".to_string(), attrs, children);
     println!("{:?}", html);
 
@@ -43,7 +46,14 @@ fn main() {
     let mut opts = Options::new();
     opts.optopt("h", "html", "HTML document", "FILENAME");
     opts.optopt("c", "css", "CSS stylesheet", "FILENAME");
+    opts.optopt("u", "user-css", "User stylesheet", "FILENAME");
     opts.optopt("o", "output", "Output file", "FILENAME");
+    opts.optopt("", "width", "Viewport width in px (default 800)", "PX");
+    opts.optopt("", "height", "Viewport height in px (default 600)", "PX");
+    opts.optopt("", "media", "Media type: screen or print (default screen)", "TYPE");
+    opts.optopt("", "color-scheme", "Preferred color scheme: light or dark (default light)", "SCHEME");
+    opts.optflag("", "style-stats", "Print style sharing cache statistics");
+    opts.optmulti("", "font", "TrueType or OpenType font to make available to `font-family`", "FILENAME");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => panic!("{}", f.to_string())
@@ -53,23 +63,43 @@ fn main() {
         std::fs::read_to_string(path).unwrap()
     };
     let html = read_source(&matches.opt_str("h").unwrap_or("examples/test.html".to_string()));
-    let css = read_source(&matches.opt_str("c").unwrap_or("examples/test.css".to_string()));
+    let viewport_size = |name: &str, default: f32| matches.opt_str(name)
+        .map_or(default, |size| size.parse().expect("viewport size must be a number"));
+    let (width, height) = (viewport_size("width", 800.0), viewport_size("height", 600.0));
+    let mut device = media::Device::screen(width, height);
+    if matches.opt_str("media").as_deref() == Some("print") {
+        device.media_type = media::MediaType::Print;
+    }
+    if matches.opt_str("color-scheme").as_deref() == Some("dark") {
+        device.color_scheme = media::ColorScheme::Dark;
+    }
     let initial_containing_block = layout::Dimensions {
-        content: layout::Rect { x: 0.0, y: 0.0, width: 800.0, height: 600.0 },
+        content: layout::Rect { x: 0.0, y: 0.0, width, height },
         padding: Default::default(),
         border: Default::default(),
         margin: Default::default(),
     };
 
+    for path in matches.opt_strs("font") {
+        font::register(font::Font::load(&path).unwrap());
+    }
+
     let root_node = parser::parse(html);
-    let stylesheet = css::parse(css);
-    let style_root = style::style_tree(&root_node, &stylesheet);
+    // Stylesheets are loaded from files so that their `@import`s can be resolved.
+    let load_stylesheet = |path: &str, origin| css::load(Path::new(path), origin).unwrap();
+    let user_stylesheet = matches.opt_str("u").map(|path| load_stylesheet(&path, css::Origin::User));
+    let stylesheet = load_stylesheet(&matches.opt_str("c").unwrap_or("examples/test.css".to_string()), css::Origin::Author);
+    let stylesheets: Vec<&css::Stylesheet> = user_stylesheet.iter().chain([&stylesheet]).collect();
+    let (style_root, sharing) = style::style_tree_with_stats(&root_node, &stylesheets, &device);
+    if matches.opt_present("style-stats") {
+        println!("style sharing: {} hits, {} misses", sharing.hits, sharing.misses);
+    }
     let layout_root = layout::layout_tree(&style_root, initial_containing_block);
 
     let canvas = painting::paint(&layout_root, initial_containing_block.content);
 
     let filename = matches.opt_str("o").unwrap_or("output.png".to_string());
-    let mut file = File::create(&Path::new(&filename)).unwrap();
+    let mut file = File::create(Path::new(&filename)).unwrap();
     let (w, h) = (canvas.width as u32, canvas.height as u32);
     let buffer: Vec<image::Rgba<u8>> = unsafe { std::mem::transmute(canvas.pixels) };
     let img = image::ImageBuffer::from_fn(w, h, Box::new(|x: u32, y: u32| buffer[(y * w + x) as usize]));
@@ -0,0 +1,249 @@
+use crate::css::{LengthContext, Value};
+
+/// The output device that media queries are evaluated against.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    pub media_type: MediaType,
+    /// Viewport size, in px.
+    pub width: f32,
+    pub height: f32,
+    pub color_scheme: ColorScheme,
+}
+
+impl Device {
+    /// A screen with a viewport of the given size, in px.
+    pub fn screen(width: f32, height: f32) -> Device {
+        Device { media_type: MediaType::Screen, width, height, color_scheme: ColorScheme::Light }
+    }
+}
+
+impl Default for Device {
+    fn default() -> Device {
+        Device::screen(800.0, 600.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaType {
+    All,
+    Screen,
+    Print,
+    /// A media type we don't render to, such as `tv`. It never matches.
+    Unknown(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+/// A comma-separated list of media queries. It matches if any query matches,
+/// and an empty list matches everything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaList(pub Vec<MediaQuery>);
+
+/// `[not | only]? <media-type> [and <condition>]?`, or a bare `<condition>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaQuery {
+    pub negated: bool,
+    pub media_type: MediaType,
+    pub condition: Option<MediaCondition>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaCondition {
+    Feature(MediaFeature),
+    Not(Box<MediaCondition>),
+    And(Vec<MediaCondition>),
+    Or(Vec<MediaCondition>),
+    /// Anything else in parentheses, such as a feature with a value we can't parse, kept
+    /// as source text. It never matches.
+    Unknown(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaFeature {
+    /// A range feature compared against one or two values, e.g. `(min-width: 600px)`
+    /// or `(400px < width <= 700px)`. Each comparison reads `<feature> <op> <value>`.
+    Range(String, Vec<(Comparison, Value)>),
+    /// A discrete feature such as `(orientation: landscape)`, or a boolean one such as `(color)`.
+    Discrete(String, Option<Value>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    /// The comparison with its operands swapped: `a < b` is `b > a`.
+    pub fn flip(self) -> Comparison {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Equal => Comparison::Equal,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Greater => Comparison::Less,
+        }
+    }
+
+    fn test(self, a: f32, b: f32) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Equal => a == b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Greater => a > b,
+        }
+    }
+}
+
+/// Whether `name` is a feature that can be used in a range context.
+pub fn is_range_feature(name: &str) -> bool {
+    ["width", "height"].contains(&name)
+}
+
+impl MediaList {
+    pub fn matches(&self, device: &Device) -> bool {
+        self.0.is_empty() || self.0.iter().any(|query| query.matches(device))
+    }
+}
+
+impl MediaQuery {
+    pub fn matches(&self, device: &Device) -> bool {
+        let type_matches = match self.media_type {
+            MediaType::All => true,
+            MediaType::Unknown(_) => false,
+            ref media_type => *media_type == device.media_type,
+        };
+        let matches = type_matches && self.condition.as_ref().is_none_or(|c| c.matches(device));
+        matches != self.negated
+    }
+}
+
+impl MediaCondition {
+    pub fn matches(&self, device: &Device) -> bool {
+        match *self {
+            MediaCondition::Feature(ref feature) => feature.matches(device),
+            MediaCondition::Not(ref condition) => !condition.matches(device),
+            MediaCondition::And(ref conditions) => conditions.iter().all(|c| c.matches(device)),
+            MediaCondition::Or(ref conditions) => conditions.iter().any(|c| c.matches(device)),
+            MediaCondition::Unknown(_) => false,
+        }
+    }
+}
+
+impl MediaFeature {
+    /// Unknown features and values never match.
+    pub fn matches(&self, device: &Device) -> bool {
+        let lengths = LengthContext {
+            font_size: 16.0,
+            root_font_size: 16.0,
+            percentage_base: 0.0,
+            viewport_width: device.width,
+            viewport_height: device.height,
+            ..Default::default()
+        };
+        match *self {
+            MediaFeature::Range(ref name, ref comparisons) => {
+                let actual = match &**name {
+                    "width" => device.width,
+                    "height" => device.height,
+                    _ => return false,
+                };
+                comparisons.iter().all(|(comparison, value)| match *value {
+                    Value::Length(..) | Value::Calc(_) => comparison.test(actual, value.to_px(&lengths)),
+                    // Only zero may be written without a unit.
+                    Value::Number(n) => n == 0.0 && comparison.test(actual, 0.0),
+                    _ => false,
+                })
+            }
+            MediaFeature::Discrete(ref name, ref value) => {
+                let keyword = match *value {
+                    Some(Value::Keyword(ref k)) => Some(k.to_ascii_lowercase()),
+                    Some(_) => return false,
+                    None => None,
+                };
+                match (&**name, keyword.as_deref()) {
+                    ("orientation", Some("portrait")) => device.height >= device.width,
+                    ("orientation", Some("landscape")) => device.width > device.height,
+                    ("orientation", None) => true,
+                    ("prefers-color-scheme", Some("light")) => device.color_scheme == ColorScheme::Light,
+                    ("prefers-color-scheme", Some("dark")) => device.color_scheme == ColorScheme::Dark,
+                    ("prefers-color-scheme", None) => true,
+                    ("color", None) => device.media_type != MediaType::Print,
+                    ("width", None) => device.width != 0.0,
+                    ("height", None) => device.height != 0.0,
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn media_queries() {
+    use crate::css::{parse, CssRule};
+    let queries = [
+        "screen", "print", "not print", "only screen and (min-width: 600px)", "(max-width: 599px)",
+        "(400px < width <= 800px)", "(width >= 50em)", "(orientation: landscape)", "not (orientation: landscape)",
+        "print, (prefers-color-scheme: light)", "(width < 700px) or (height < 700px)", "tv", "(hover)",
+    ];
+    let source: String = queries.iter().map(|q| format!("@media {q} {{ p {{ color: red }} }}\n")).collect();
+    let stylesheet = parse(source);
+    let evaluate = |device: &Device| -> Vec<bool> {
+        stylesheet.rules.iter().map(|rule| match *rule {
+            CssRule::Media(ref media, _) => media.matches(device),
+            _ => panic!("expected @media"),
+        }).collect()
+    };
+    assert_eq!(vec![true, false, true, true, false, true, true, true, false, true, true, false, false],
+               evaluate(&Device::screen(800.0, 600.0)));
+    let mut phone = Device::screen(400.0, 700.0);
+    phone.color_scheme = ColorScheme::Dark;
+    assert_eq!(vec![true, false, true, false, true, false, false, false, true, false, true, false, false],
+               evaluate(&phone));
+}
+
+#[test]
+fn unknown_media_features() {
+    use crate::css::{parse, CssRule};
+    // Features we can't parse are kept as source text, and never match.
+    let stylesheet = parse("
+        @media (min-aspect-ratio: 16/9), (width foo), (800px < width <), not (width foo), (width) { p { color: red } }
+    ".to_string());
+    let CssRule::Media(ref media, _) = stylesheet.rules[0] else { panic!("expected @media") };
+    let conditions: Vec<_> = media.0.iter().map(|query| query.condition.clone().unwrap()).collect();
+    assert_eq!(vec![
+        MediaCondition::Unknown("(min-aspect-ratio: 16/9)".to_string()),
+        MediaCondition::Unknown("(width foo)".to_string()),
+        MediaCondition::Unknown("(800px < width <)".to_string()),
+        MediaCondition::Not(Box::new(MediaCondition::Unknown("(width foo)".to_string()))),
+    ], conditions[..4]);
+    let matches: Vec<_> = media.0.iter().map(|query| query.matches(&Device::default())).collect();
+    assert_eq!(vec![false, false, false, true, true], matches);
+}
+
+#[test]
+fn invalid_media_queries() {
+    use crate::css::parse;
+    // A condition mixing `and` and `or`, a missing `)` or `(`, and anything else that
+    // isn't a media query drops its rule.
+    let stylesheet = parse("
+        @media (color) and (hover) or (width) { p { color: red } }
+        @media (color) or (hover) and (width) { p { color: red } }
+        @media screen and { p { color: red } }
+        @media screen print { p { color: red } }
+        @media .screen { p { color: red } }
+        @media ((color) { p { color: red } }
+        p { color: blue }
+        @media (min-width: 1px { p { color: red } }
+    ".to_string());
+    assert_eq!(1, stylesheet.rules.len());
+    assert!(stylesheet.rules[0].as_style().is_some());
+}
@@ -1,13 +1,10 @@
-use crate::css::{
-    Color,
-    Value,
-};
+use crate::computed::ComputedStyle;
+use crate::css::Color;
 use crate::layout::{
     BoxType::*,
     LayoutBox,
     Rect,
 };
-use std::iter::repeat;
 
 type DisplayList = Vec<DisplayCommand>;
 
@@ -18,7 +15,7 @@ enum DisplayCommand {
 fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
     let mut list = Vec::new();
     render_layout_box(&mut list, layout_root);
-    return list;
+    list
 }
 
 fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
@@ -32,52 +29,49 @@ fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
 }
 
 fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color|
-        list.push(DisplayCommand::SolidColor(color, layout_box.dimensions.border_box())));
+    if let Some(style) = get_style(layout_box) {
+        list.push(DisplayCommand::SolidColor(style.background.color, layout_box.dimensions.border_box()));
+    }
 }
 
-fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+fn get_style<'a>(layout_box: &LayoutBox<'a>) -> Option<&'a ComputedStyle> {
     match layout_box.box_type {
-        BlockNode(style) | InlineNode(style) => match style.value(name) {
-            Some(Value::ColorValue(color)) => Some(color),
-            _ => None
-        },
-        AnonymousBlock => None
+        BlockNode(style) | InlineNode(style) | InlineBlockNode(style) => Some(style.style()),
+        AnonymousBlock | TextRun(_) | LineBox | TextFragment(..) => None
     }
 }
 
 fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
-    let color = match get_color(layout_box, "border-color") {
-        Some(color) => color,
-        _ => return // bail out if no border color is specifies
+    let colors = match get_style(layout_box) {
+        Some(style) => &style.border.color,
+        None => return,
     };
-
     let d = &layout_box.dimensions;
     let border_box = d.border_box();
 
     // left border
-    list.push(DisplayCommand::SolidColor(color, Rect {
+    list.push(DisplayCommand::SolidColor(colors.left, Rect {
         x: border_box.x,
         y: border_box.y,
         width: d.border.left,
         height: border_box.height,
     }));
     // right border
-    list.push(DisplayCommand::SolidColor(color, Rect {
+    list.push(DisplayCommand::SolidColor(colors.right, Rect {
         x: border_box.x + border_box.width - d.border.right,
         y: border_box.y,
         width: d.border.right,
         height: border_box.height,
     }));
     // Top border
-    list.push(DisplayCommand::SolidColor(color, Rect {
+    list.push(DisplayCommand::SolidColor(colors.top, Rect {
         x: border_box.x,
         y: border_box.y,
         width: border_box.width,
         height: d.border.top,
     }));
     // bottom border
-    list.push(DisplayCommand::SolidColor(color, Rect {
+    list.push(DisplayCommand::SolidColor(colors.bottom, Rect {
         x: border_box.x,
         y: border_box.y + border_box.height - d.border.bottom,
         width: border_box.width,
@@ -95,7 +89,7 @@ impl Canvas {
     fn new(width: usize, height: usize) -> Canvas {
         let white = Color { r: 255, g: 255, b: 255, a: 255 };
         Canvas {
-            pixels: repeat(white).take(width * height).collect(),
+            pixels: vec![white; width * height],
             width,
             height,
         }
@@ -109,10 +103,13 @@ impl Canvas {
                 let x1 = (rect.x + rect.width).clamp(0.0, self.width as f32) as usize;
                 let y1 = (rect.y + rect.height).clamp(0.0, self.height as f32) as usize;
 
+                if color.a == 0 {
+                    return;
+                }
                 for y in y0 .. y1 {
                     for x in x0 .. x1 {
-                        // TODO: alpha compositing with existing pixel
-                        self.pixels[x + y * self.width] = color;
+                        let pixel = &mut self.pixels[x + y * self.width];
+                        *pixel = blend(color, *pixel);
                     }
                 }
             }
@@ -120,6 +117,23 @@ impl Canvas {
     }
 }
 
+/// Composite `source` over `backdrop` ("source-over").
+fn blend(source: Color, backdrop: Color) -> Color {
+    if source.a == 255 {
+        return source;
+    }
+    let alpha = source.a as f32 / 255.0;
+    let backdrop_alpha = backdrop.a as f32 / 255.0 * (1.0 - alpha);
+    let out_alpha = alpha + backdrop_alpha;
+    let channel = |s: u8, b: u8| ((s as f32 * alpha + b as f32 * backdrop_alpha) / out_alpha).round() as u8;
+    Color {
+        r: channel(source.r, backdrop.r),
+        g: channel(source.g, backdrop.g),
+        b: channel(source.b, backdrop.b),
+        a: (out_alpha * 255.0).round() as u8,
+    }
+}
+
 pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
     let display_list = build_display_list(layout_root);
     let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
@@ -128,3 +142,11 @@ pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
     }
     canvas
 }
+
+#[test]
+fn blend_source_over() {
+    let white = Color { r: 255, g: 255, b: 255, a: 255 };
+    let half_red = Color { r: 255, g: 0, b: 0, a: 128 };
+    assert_eq!(Color { r: 255, g: 127, b: 127, a: 255 }, blend(half_red, white));
+    assert_eq!(white, blend(white, half_red));
+}
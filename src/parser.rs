@@ -26,7 +26,7 @@ impl Parser {
         let (_, cur_char) = iter.next().unwrap();
         let (next_pos, _) = iter.next().unwrap_or((1, ' '));
         self.pos += next_pos;
-        return cur_char;
+        cur_char
     }
 
     /// Consume and discard zero or more whitespace characters.
@@ -61,9 +61,8 @@ impl Parser {
     /// Parse a single element
     fn parse_element(&mut self) -> dom::Node {
         assert!(self.consume_char() == '<');
-        match self.next_char() {
-            '!' => return self.parse_comment(),
-            _ => (),
+        if self.next_char() == '!' {
+            return self.parse_comment();
         }
         let tag_name = self.parse_tag_name();
         let attrs = self.parse_attributes();
@@ -123,10 +122,7 @@ impl Parser {
 
     /// Parse a tag or attribute name.
     fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' => true,
-            _ => false
-        })
+        self.consume_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-'))
     }
 
     fn consume_while<F>(&mut self, test: F) -> String
@@ -135,7 +131,7 @@ impl Parser {
         while !self.eof() && test(self.next_char()) {
             result.push(self.consume_char());
         }
-        return result;
+        result
     }
 
     fn next_char(&self) -> char {
@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+use crate::computed::Display;
+use crate::css::{
+    Color,
+    Unit::Px,
+    Value::{
+        self,
+        ColorValue,
+        Keyword,
+        Length,
+        Number,
+    },
+};
+
+/// What the style system needs to know about a CSS property.
+pub struct Property {
+    /// Whether the property takes its parent's computed value when not specified.
+    pub inherited: bool,
+    /// The value used by `initial`, and for inherited properties on the root element.
+    pub initial: Value,
+}
+
+lazy_static! {
+    static ref PROPERTIES: HashMap<&'static str, Property> = {
+        let keyword = |k: &str| Keyword(k.to_string());
+        let zero = Length(0.0, Px);
+        let black = ColorValue(Color { r: 0, g: 0, b: 0, a: 255 });
+
+        let mut map = HashMap::new();
+        let mut register = |name: &'static str, inherited: bool, initial: Value| {
+            map.insert(name, Property { inherited, initial });
+        };
+
+        // Box model
+        register("display", false, keyword("inline"));
+        register("width", false, keyword("auto"));
+        register("height", false, keyword("auto"));
+        for name in MARGIN.iter().chain(&PADDING).chain(&BORDER_WIDTH) {
+            register(name, false, zero.clone());
+        }
+        for name in BORDER_STYLE {
+            register(name, false, keyword("none"));
+        }
+        for name in BORDER_COLOR {
+            register(name, false, keyword("currentcolor"));
+        }
+        for name in INSET {
+            register(name, false, keyword("auto"));
+        }
+
+        // Colors and backgrounds
+        register("color", true, black);
+        register("background-color", false, ColorValue(Color { r: 0, g: 0, b: 0, a: 0 }));
+        register("background-image", false, keyword("none"));
+        register("background-repeat", false, keyword("repeat"));
+        register("background-attachment", false, keyword("scroll"));
+        register("background-position", false, Value::List(vec![keyword("left"), keyword("top")]));
+
+        // Fonts and text
+        register("font-family", true, keyword("serif"));
+        register("font-size", true, keyword("medium"));
+        register("font-style", true, keyword("normal"));
+        register("font-variant", true, keyword("normal"));
+        register("font-weight", true, keyword("normal"));
+        register("font-stretch", true, keyword("normal"));
+        register("line-height", true, keyword("normal"));
+        register("letter-spacing", true, keyword("normal"));
+        register("word-spacing", true, keyword("normal"));
+        register("text-align", true, keyword("start"));
+        register("text-indent", true, zero.clone());
+        register("text-transform", true, keyword("none"));
+        register("white-space", true, keyword("normal"));
+        register("visibility", true, keyword("visible"));
+
+        // Lists and generated content
+        register("list-style-type", true, keyword("disc"));
+        register("content", false, keyword("normal"));
+        register("counter-reset", false, keyword("none"));
+        register("counter-increment", false, keyword("none"));
+        register("counter-set", false, keyword("none"));
+
+        // Flexbox
+        register("flex-grow", false, Number(0.0));
+        register("flex-shrink", false, Number(1.0));
+        register("flex-basis", false, keyword("auto"));
+
+        register("opacity", false, Number(1.0));
+
+        map
+    };
+}
+
+pub(crate) const MARGIN: [&str; 4] = ["margin-top", "margin-right", "margin-bottom", "margin-left"];
+pub(crate) const PADDING: [&str; 4] = ["padding-top", "padding-right", "padding-bottom", "padding-left"];
+pub(crate) const BORDER_WIDTH: [&str; 4] = ["border-top-width", "border-right-width", "border-bottom-width", "border-left-width"];
+pub(crate) const BORDER_STYLE: [&str; 4] = ["border-top-style", "border-right-style", "border-bottom-style", "border-left-style"];
+pub(crate) const BORDER_COLOR: [&str; 4] = ["border-top-color", "border-right-color", "border-bottom-color", "border-left-color"];
+const INSET: [&str; 4] = ["top", "right", "bottom", "left"];
+const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+const BORDER_STYLES: [&str; 10] = ["none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset"];
+pub const CSS_WIDE_KEYWORDS: [&str; 4] = ["inherit", "initial", "unset", "revert"];
+
+pub fn lookup(name: &str) -> Option<&'static Property> {
+    PROPERTIES.get(name)
+}
+
+/// Custom properties are always inherited.
+pub fn is_inherited(name: &str) -> bool {
+    name.starts_with("--") || lookup(name).is_some_and(|p| p.inherited)
+}
+
+pub fn initial_value(name: &str) -> Option<Value> {
+    lookup(name).map(|p| p.initial.clone())
+}
+
+/// All inherited properties.
+pub fn inherited_properties() -> impl Iterator<Item=(&'static str, &'static Property)> {
+    PROPERTIES.iter()
+        .filter(|(_, p)| p.inherited)
+        .map(|(&name, p)| (name, p))
+}
+
+/// The longhands set by a shorthand property, or `None` if `name` is not a shorthand.
+pub fn longhands(name: &str) -> Option<Vec<&'static str>> {
+    let side_longhands = |side: &str| {
+        let i = SIDES.iter().position(|s| *s == side).unwrap();
+        vec![BORDER_WIDTH[i], BORDER_STYLE[i], BORDER_COLOR[i]]
+    };
+    Some(match name {
+        "margin" => MARGIN.to_vec(),
+        "padding" => PADDING.to_vec(),
+        "border-width" => BORDER_WIDTH.to_vec(),
+        "border-style" => BORDER_STYLE.to_vec(),
+        "border-color" => BORDER_COLOR.to_vec(),
+        "inset" => INSET.to_vec(),
+        "border" => BORDER_WIDTH.iter().chain(&BORDER_STYLE).chain(&BORDER_COLOR).copied().collect(),
+        "border-top" => side_longhands("top"),
+        "border-right" => side_longhands("right"),
+        "border-bottom" => side_longhands("bottom"),
+        "border-left" => side_longhands("left"),
+        "background" => vec!["background-color", "background-image", "background-repeat",
+                             "background-attachment", "background-position"],
+        "font" => vec!["font-style", "font-variant", "font-weight", "font-stretch",
+                       "font-size", "line-height", "font-family"],
+        "flex" => vec!["flex-grow", "flex-shrink", "flex-basis"],
+        _ => return None,
+    })
+}
+
+/// Expand a declaration into longhand declarations. Longhands are returned as they are;
+/// a shorthand whose value doesn't parse expands to nothing, as invalid declarations are ignored.
+pub fn expand(name: &str, value: &Value) -> Vec<(String, Value)> {
+    let longhands = match longhands(name) {
+        Some(longhands) => longhands,
+        None => return vec![(name.to_string(), value.clone())],
+    };
+    // A CSS-wide keyword, or a value waiting for `var()` substitution, applies to every longhand.
+    let applies_to_all = match *value {
+        Keyword(ref k) => CSS_WIDE_KEYWORDS.contains(&&**k),
+        Value::Pending(..) => true,
+        _ => false,
+    };
+    if applies_to_all {
+        return longhands.iter().map(|l| (l.to_string(), value.clone())).collect();
+    }
+    let expanded = match name {
+        "margin" | "padding" | "border-width" | "border-style" | "border-color" | "inset" =>
+            expand_box(&longhands, value),
+        "border" => expand_border(value).map(|[width, style, color]| {
+            let mut result = Vec::new();
+            for (longhand, value) in [(BORDER_WIDTH, width), (BORDER_STYLE, style), (BORDER_COLOR, color)] {
+                result.extend(longhand.iter().map(|l| (l.to_string(), value.clone())));
+            }
+            result
+        }),
+        "border-top" | "border-right" | "border-bottom" | "border-left" =>
+            expand_border(value).map(|values| longhands.iter().map(|l| l.to_string()).zip(values).collect()),
+        "background" => expand_background(value),
+        "font" => expand_font(value),
+        "flex" => expand_flex(value),
+        _ => None,
+    };
+    expanded.unwrap_or_default()
+}
+
+/// Whether the declaration `name: value` is valid: a shorthand must expand, and every
+/// longhand it sets must accept its value.
+pub fn is_valid(name: &str, value: &Value) -> bool {
+    let longhands = expand(name, value);
+    !longhands.is_empty() && longhands.iter().all(|(name, value)| accepts(name, value))
+}
+
+/// Whether `value` is a value of the longhand `name` that this engine implements.
+/// Properties whose values aren't checked accept anything but unknown units.
+pub fn accepts(name: &str, value: &Value) -> bool {
+    let length = |value: &Value| match *value {
+        Length(..) => true,
+        Value::Calc(ref calc) => calc.any_unit(&|_| true),
+        Number(n) => n == 0.0,
+        _ => false,
+    };
+    let number = |value: &Value| match *value {
+        Number(_) => true,
+        Value::Calc(ref calc) => !calc.any_unit(&|_| true),
+        _ => false,
+    };
+    let keyword = |value: &Value, keywords: &[&str]| matches!(*value, Keyword(ref k) if keywords.contains(&&**k));
+    match *value {
+        Keyword(ref k) if CSS_WIDE_KEYWORDS.contains(&&**k) => return true,
+        Value::Pending(..) => return true,
+        _ => {}
+    }
+    match name {
+        "display" => Display::parse(value).is_some(),
+        "width" | "height" | "flex-basis" => length(value) || keyword(value, &["auto"]),
+        _ if MARGIN.contains(&name) || INSET.contains(&name) => length(value) || keyword(value, &["auto"]),
+        "text-indent" => length(value),
+        _ if PADDING.contains(&name) => length(value),
+        _ if BORDER_WIDTH.contains(&name) => length(value) || keyword(value, &["thin", "medium", "thick"]),
+        _ if BORDER_STYLE.contains(&name) => keyword(value, &BORDER_STYLES),
+        _ if BORDER_COLOR.contains(&name) || name == "color" || name == "background-color" =>
+            matches!(*value, ColorValue(_)) || keyword(value, &["currentcolor"]),
+        "opacity" | "flex-grow" | "flex-shrink" => number(value),
+        _ => !has_unknown_unit(value),
+    }
+}
+
+fn has_unknown_unit(value: &Value) -> bool {
+    match *value {
+        Value::Dimension(..) => true,
+        Value::List(ref items) | Value::CommaList(ref items) | Value::Function(_, ref items) =>
+            items.iter().any(has_unknown_unit),
+        _ => false,
+    }
+}
+
+fn components(value: &Value) -> &[Value] {
+    match *value {
+        Value::List(ref items) => items,
+        ref value => std::slice::from_ref(value),
+    }
+}
+
+fn initial(name: &str) -> Value {
+    initial_value(name).unwrap_or_else(|| Keyword("initial".to_string()))
+}
+
+/// One to four values for top, right, bottom and left, following the usual CSS box rules.
+fn expand_box(longhands: &[&str], value: &Value) -> Option<Vec<(String, Value)>> {
+    let values = match components(value) {
+        [all] => [all, all, all, all],
+        [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+        [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+        [top, right, bottom, left] => [top, right, bottom, left],
+        _ => return None,
+    };
+    Some(longhands.iter().zip(values).map(|(l, v)| (l.to_string(), v.clone())).collect())
+}
+
+/// `<line-width> || <line-style> || <color>`, in any order. Returns width, style and color.
+fn expand_border(value: &Value) -> Option<[Value; 3]> {
+    let (mut width, mut style, mut color) = (None, None, None);
+    for component in components(value) {
+        let slot = match *component {
+            Length(..) | Number(_) => &mut width,
+            Keyword(ref k) if ["thin", "medium", "thick"].contains(&&**k) => &mut width,
+            Keyword(ref k) if BORDER_STYLES.contains(&&**k) => &mut style,
+            ColorValue(_) | Keyword(_) => &mut color,
+            _ => return None,
+        };
+        if slot.replace(component.clone()).is_some() {
+            return None;
+        }
+    }
+    Some([
+        width.unwrap_or_else(|| initial("border-top-width")),
+        style.unwrap_or_else(|| initial("border-top-style")),
+        color.unwrap_or_else(|| initial("border-top-color")),
+    ])
+}
+
+/// A single background layer; with several comma-separated layers only the last one sets the color.
+fn expand_background(value: &Value) -> Option<Vec<(String, Value)>> {
+    let layers = match *value {
+        Value::CommaList(ref layers) => layers.as_slice(),
+        ref value => std::slice::from_ref(value),
+    };
+    let mut color = None;
+    let mut per_layer: [Vec<Value>; 4] = Default::default();
+    for (i, layer) in layers.iter().enumerate() {
+        let (mut image, mut repeat, mut attachment) = (None, None, None);
+        let mut position = Vec::new();
+        for component in components(layer) {
+            let slot = match *component {
+                ColorValue(_) if i == layers.len() - 1 => &mut color,
+                Keyword(ref k) if k == "currentcolor" => &mut color,
+                Keyword(ref k) if k == "none" => &mut image,
+                Value::Function(ref f, _) if f == "url" || f.ends_with("gradient") => &mut image,
+                Keyword(ref k) if ["repeat", "repeat-x", "repeat-y", "no-repeat", "space", "round"].contains(&&**k) =>
+                    &mut repeat,
+                Keyword(ref k) if ["scroll", "fixed", "local"].contains(&&**k) => &mut attachment,
+                Keyword(ref k) if ["left", "center", "right", "top", "bottom"].contains(&&**k) => {
+                    position.push(component.clone());
+                    continue;
+                }
+                Length(..) | Number(_) => {
+                    position.push(component.clone());
+                    continue;
+                }
+                _ => return None,
+            };
+            if slot.replace(component.clone()).is_some() {
+                return None;
+            }
+        }
+        let position = match position.len() {
+            0 => None,
+            1 => position.pop(),
+            _ => Some(Value::List(position)),
+        };
+        let longhands = ["background-image", "background-repeat", "background-attachment", "background-position"];
+        for ((values, value), longhand) in per_layer.iter_mut().zip([image, repeat, attachment, position]).zip(longhands) {
+            values.push(value.unwrap_or_else(|| initial(longhand)));
+        }
+    }
+    let mut result = vec![("background-color".to_string(), color.unwrap_or_else(|| initial("background-color")))];
+    let longhands = ["background-image", "background-repeat", "background-attachment", "background-position"];
+    for (longhand, mut values) in longhands.iter().zip(per_layer) {
+        let value = if values.len() == 1 { values.remove(0) } else { Value::CommaList(values) };
+        result.push((longhand.to_string(), value));
+    }
+    Some(result)
+}
+
+/// `[<style> || <variant> || <weight> || <stretch>]? <size> [/ <line-height>]? <family>#`
+fn expand_font(value: &Value) -> Option<Vec<(String, Value)>> {
+    let (first, other_families) = match *value {
+        Value::CommaList(ref items) => (&items[0], &items[1..]),
+        ref value => (value, &[][..]),
+    };
+    let components = components(first);
+    let (mut style, mut variant, mut weight, mut stretch) = (None, None, None, None);
+    let mut i = 0;
+    let size = loop {
+        let component = components.get(i)?;
+        i += 1;
+        let slot = match *component {
+            Length(..) => break component.clone(),
+            Keyword(ref k) if FONT_SIZE_KEYWORDS.contains(&&**k) => break component.clone(),
+            // `normal` resets whichever of the properties isn't otherwise set.
+            Keyword(ref k) if k == "normal" => continue,
+            Keyword(ref k) if ["italic", "oblique"].contains(&&**k) => &mut style,
+            Keyword(ref k) if k == "small-caps" => &mut variant,
+            Keyword(ref k) if ["bold", "bolder", "lighter"].contains(&&**k) => &mut weight,
+            Number(n) if (1.0..=1000.0).contains(&n) => &mut weight,
+            Keyword(ref k) if k.ends_with("condensed") || k.ends_with("expanded") => &mut stretch,
+            _ => return None,
+        };
+        if slot.replace(component.clone()).is_some() {
+            return None;
+        }
+    };
+    let line_height = match components.get(i) {
+        Some(Value::Slash) => {
+            i += 2;
+            Some(components.get(i - 1)?.clone())
+        }
+        _ => None,
+    };
+    let first_family = match &components[i..] {
+        [] => return None,
+        [family] => family.clone(),
+        words => Value::List(words.to_vec()),
+    };
+    let family = if other_families.is_empty() {
+        first_family
+    } else {
+        Value::CommaList(std::iter::once(first_family).chain(other_families.iter().cloned()).collect())
+    };
+    Some(vec![
+        ("font-style".to_string(), style.unwrap_or_else(|| initial("font-style"))),
+        ("font-variant".to_string(), variant.unwrap_or_else(|| initial("font-variant"))),
+        ("font-weight".to_string(), weight.unwrap_or_else(|| initial("font-weight"))),
+        ("font-stretch".to_string(), stretch.unwrap_or_else(|| initial("font-stretch"))),
+        ("font-size".to_string(), size),
+        ("line-height".to_string(), line_height.unwrap_or_else(|| initial("line-height"))),
+        ("font-family".to_string(), family),
+    ])
+}
+
+const FONT_SIZE_KEYWORDS: [&str; 10] = [
+    "xx-small", "x-small", "small", "medium", "large", "x-large", "xx-large", "xxx-large", "larger", "smaller",
+];
+
+/// `none | auto | <grow> <shrink>? || <basis>`
+fn expand_flex(value: &Value) -> Option<Vec<(String, Value)>> {
+    let (grow, shrink, basis) = match components(value) {
+        [Keyword(k)] if k == "none" => (Number(0.0), Number(0.0), Keyword("auto".to_string())),
+        [Keyword(k)] if k == "auto" => (Number(1.0), Number(1.0), Keyword("auto".to_string())),
+        components => {
+            let (mut numbers, mut basis) = (Vec::new(), None);
+            for component in components {
+                match *component {
+                    // A unitless zero after both factors is the basis.
+                    Number(n) if numbers.len() == 2 && n == 0.0 && basis.is_none() => basis = Some(Length(0.0, Px)),
+                    Number(n) => numbers.push(n),
+                    Length(..) | Keyword(_) if basis.is_none() => basis = Some(component.clone()),
+                    _ => return None,
+                }
+            }
+            let (grow, shrink) = match numbers[..] {
+                [grow] => (grow, 1.0),
+                [grow, shrink] => (grow, shrink),
+                [] => (1.0, 1.0),
+                _ => return None,
+            };
+            // When omitted from the shorthand, flex-basis is 0 rather than its initial `auto`.
+            (Number(grow), Number(shrink), basis.unwrap_or(Length(0.0, Px)))
+        }
+    };
+    Some(vec![
+        ("flex-grow".to_string(), grow),
+        ("flex-shrink".to_string(), shrink),
+        ("flex-basis".to_string(), basis),
+    ])
+}
+
+#[test]
+fn expand_box_shorthands() {
+    let auto = Keyword("auto".to_string());
+    let zero = Number(0.0);
+    let parsed = crate::css::parse("p { margin: 0 auto; inset: 1px 2px 3px; }".to_string());
+    let declarations = &parsed.rules[0].as_style().unwrap().declarations;
+    assert_eq!(vec![
+        ("margin-top".to_string(), zero.clone()),
+        ("margin-right".to_string(), auto.clone()),
+        ("margin-bottom".to_string(), zero),
+        ("margin-left".to_string(), auto),
+    ], expand(&declarations[0].name, &declarations[0].value));
+    let left = expand(&declarations[1].name, &declarations[1].value).pop().unwrap();
+    assert_eq!(("left".to_string(), Length(2.0, Px)), left);
+}
+
+#[test]
+fn expand_other_shorthands() {
+    let parsed = crate::css::parse(r#"p {
+        border-top: red 2px dashed;
+        font: bold 12px/1.5 Georgia, serif;
+        flex: 2;
+        background: #ff0000 none no-repeat;
+        border: inherit;
+    }"#.to_string());
+    let expanded: Vec<_> = parsed.rules[0].as_style().unwrap().declarations.iter().flat_map(|d| expand(&d.name, &d.value)).collect();
+    let get = |name: &str| expanded.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone()).unwrap();
+    let red = ColorValue(Color { r: 255, g: 0, b: 0, a: 255 });
+    assert_eq!(Length(2.0, Px), get("border-top-width"));
+    assert_eq!(Keyword("dashed".to_string()), get("border-top-style"));
+    assert_eq!(red, get("border-top-color"));
+    assert_eq!(Keyword("bold".to_string()), get("font-weight"));
+    assert_eq!(Keyword("normal".to_string()), get("font-style"));
+    assert_eq!(Number(1.5), get("line-height"));
+    assert_eq!(Value::CommaList(vec![Keyword("Georgia".to_string()), Keyword("serif".to_string())]), get("font-family"));
+    assert_eq!((Number(2.0), Number(1.0), Length(0.0, Px)), (get("flex-grow"), get("flex-shrink"), get("flex-basis")));
+    assert_eq!(red, get("background-color"));
+    assert_eq!(Keyword("no-repeat".to_string()), get("background-repeat"));
+    assert_eq!(Keyword("inherit".to_string()), get("border-left-color"));
+}
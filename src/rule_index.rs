@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use crate::css::{
+    Combinator,
+    Rule,
+    Selector,
+    SimpleSelector,
+};
+use crate::dom::ElementData;
+
+/// The selectors of a set of style rules, bucketed by the rightmost compound selector's
+/// id, else one of its classes, else its tag name, so that an element only needs to be
+/// tested against the selectors that could possibly match it.
+#[derive(Default)]
+pub struct RuleIndex<'a> {
+    by_id: HashMap<&'a str, Vec<IndexedSelector<'a>>>,
+    by_class: HashMap<&'a str, Vec<IndexedSelector<'a>>>,
+    by_tag: HashMap<&'a str, Vec<IndexedSelector<'a>>>,
+    universal: Vec<IndexedSelector<'a>>,
+}
+
+pub struct IndexedSelector<'a> {
+    pub selector: &'a Selector,
+    /// The stylesheet, the rule within it, and the selector within the rule.
+    pub position: (usize, usize, usize),
+    /// Hashes of the ids, classes and tag names that some ancestor of a matching
+    /// element must have, for the `AncestorFilter`.
+    ancestor_hashes: Vec<u32>,
+    /// Whether matching depends on more than the tag names, ids and attributes of the
+    /// element and its ancestors, such as its position among its siblings. Elements may
+    /// only share a style if they agree on all such selectors.
+    pub needs_revalidation: bool,
+}
+
+impl<'a> RuleIndex<'a> {
+    /// Index `rules`, each given with its stylesheet and rule position.
+    pub fn new(rules: impl IntoIterator<Item=((usize, usize), &'a Rule)>) -> RuleIndex<'a> {
+        let mut index = RuleIndex::default();
+        for ((sheet, rule_index), rule) in rules {
+            for (i, selector) in rule.selectors.iter().enumerate() {
+                let indexed = IndexedSelector {
+                    selector,
+                    position: (sheet, rule_index, i),
+                    ancestor_hashes: ancestor_hashes(selector),
+                    needs_revalidation: needs_revalidation(selector),
+                };
+                let subject = selector.subject();
+                let bucket = if let Some(ref id) = subject.id {
+                    index.by_id.entry(id).or_default()
+                } else if let Some(class) = subject.class.first() {
+                    index.by_class.entry(class).or_default()
+                } else if let Some(ref tag_name) = subject.tag_name {
+                    index.by_tag.entry(tag_name).or_default()
+                } else {
+                    &mut index.universal
+                };
+                bucket.push(indexed);
+            }
+        }
+        index
+    }
+
+    /// The selectors that may match an element, in no particular order.
+    pub fn candidates<'i>(&'i self, elem: &'i ElementData) -> impl Iterator<Item=&'i IndexedSelector<'a>> + 'i {
+        let id = elem.id().and_then(|id| self.by_id.get(&**id));
+        let classes = elem.classes().into_iter().filter_map(|class| self.by_class.get(class));
+        let tag = self.by_tag.get(&*elem.tag_name);
+        id.into_iter().chain(classes).chain(tag).flatten().chain(&self.universal)
+    }
+}
+
+/// The tag names, ids and classes required of ancestors: those of every compound
+/// selector followed by a descendant or child combinator.
+fn ancestor_hashes(selector: &Selector) -> Vec<u32> {
+    let mut hashes = Vec::new();
+    let mut current = selector;
+    while let Selector::Complex(ref left, combinator, _) = *current {
+        if let Combinator::Descendant | Combinator::Child = combinator {
+            compound_hashes(left.subject(), &mut hashes);
+        }
+        current = left;
+    }
+    hashes
+}
+
+/// Selectors with pseudo-classes or sibling combinators.
+fn needs_revalidation(selector: &Selector) -> bool {
+    match *selector {
+        Selector::Simple(ref simple) => !simple.pseudo_classes.is_empty(),
+        Selector::Complex(ref left, combinator, ref right) => !right.pseudo_classes.is_empty() ||
+            matches!(combinator, Combinator::NextSibling | Combinator::SubsequentSibling) ||
+            needs_revalidation(left),
+    }
+}
+
+fn compound_hashes(selector: &SimpleSelector, hashes: &mut Vec<u32>) {
+    hashes.extend(selector.tag_name.iter().map(|name| hash(b't', name)));
+    hashes.extend(selector.id.iter().map(|id| hash(b'#', id)));
+    hashes.extend(selector.class.iter().map(|class| hash(b'.', class)));
+}
+
+/// FNV-1a, with a prefix so that a class and a tag with the same name hash differently.
+fn hash(kind: u8, name: &str) -> u32 {
+    std::iter::once(kind).chain(name.bytes())
+        .fold(0x811c9dc5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+const FILTER_BITS: u32 = 12;
+const FILTER_SIZE: usize = 1 << FILTER_BITS;
+
+/// A counting Bloom filter of the tag names, ids and classes of the ancestors of the
+/// element being styled. A selector that requires an ancestor feature the filter has
+/// never seen can't match, which rejects most descendant selectors without walking
+/// up the tree.
+#[derive(Clone)]
+pub struct AncestorFilter {
+    counts: Vec<u8>,
+}
+
+impl Default for AncestorFilter {
+    fn default() -> AncestorFilter {
+        AncestorFilter { counts: vec![0; FILTER_SIZE] }
+    }
+}
+
+impl AncestorFilter {
+    /// Add an element before styling its descendants.
+    pub fn push(&mut self, elem: &ElementData) {
+        self.for_each_slot(elem, |count| *count = count.saturating_add(1));
+    }
+
+    /// Remove an element pushed earlier, once its descendants have been styled.
+    pub fn pop(&mut self, elem: &ElementData) {
+        // A saturated count no longer knows how many elements it stands for, so it stays put.
+        self.for_each_slot(elem, |count| if *count != u8::MAX { *count -= 1 });
+    }
+
+    /// False if some element on the stack is certainly missing for `selector` to match.
+    pub fn may_match(&self, selector: &IndexedSelector) -> bool {
+        selector.ancestor_hashes.iter().all(|&hash| self.contains(hash))
+    }
+
+    fn contains(&self, hash: u32) -> bool {
+        let (a, b) = slots(hash);
+        self.counts[a] != 0 && self.counts[b] != 0
+    }
+
+    fn for_each_slot(&mut self, elem: &ElementData, mut f: impl FnMut(&mut u8)) {
+        let mut hashes = vec![hash(b't', &elem.tag_name)];
+        hashes.extend(elem.id().map(|id| hash(b'#', id)));
+        hashes.extend(elem.classes().into_iter().map(|class| hash(b'.', class)));
+        for hash in hashes {
+            let (a, b) = slots(hash);
+            f(&mut self.counts[a]);
+            f(&mut self.counts[b]);
+        }
+    }
+}
+
+/// Two filter slots taken from different bits of the hash.
+fn slots(hash: u32) -> (usize, usize) {
+    let mask = FILTER_SIZE as u32 - 1;
+    ((hash & mask) as usize, ((hash >> FILTER_BITS) & mask) as usize)
+}
+
+#[test]
+fn candidates_come_from_matching_buckets() {
+    let stylesheet = crate::css::parse(
+        "#main { } .note { } p.x.note { } div { } * { } .other { } span#main { } :is(p) { }".to_string());
+    let rules = stylesheet.rules.iter().enumerate().filter_map(|(i, r)| r.as_style().map(|r| ((0, i), r)));
+    let index = RuleIndex::new(rules);
+    let root = crate::parser::parse("<p id='main' class='note x'></p>".to_string());
+    let crate::dom::NodeType::Element(ref data) = root.node_type else { unreachable!() };
+    let mut rules: Vec<usize> = index.candidates(data).map(|s| s.position.1).collect();
+    rules.sort();
+    // `div` and `.other` can't match; `span#main` is only ruled out by matching.
+    assert_eq!(vec![0, 1, 2, 4, 6, 7], rules);
+}
+
+#[test]
+fn ancestor_filter_rejects_missing_ancestors() {
+    let stylesheet = crate::css::parse("
+        .a p { } .b > p { } .a + .b p { } .c ~ p { } section .a em { }
+    ".to_string());
+    let rules = stylesheet.rules.iter().enumerate().filter_map(|(i, r)| r.as_style().map(|r| ((0, i), r)));
+    let index = RuleIndex::new(rules);
+    let root = crate::parser::parse("<div id='x' class='a'><em></em></div>".to_string());
+    let crate::dom::NodeType::Element(ref data) = root.node_type else { unreachable!() };
+    let mut filter = AncestorFilter::default();
+    filter.push(data);
+    let mut selectors: Vec<&IndexedSelector> = index.universal.iter().chain(index.by_tag.values().flatten()).collect();
+    selectors.sort_by_key(|s| s.position);
+    let may_match: Vec<bool> = selectors.iter().map(|s| filter.may_match(s)).collect();
+    assert_eq!(vec![true, false, false, true, false], may_match);
+    filter.pop(data);
+    assert!(!filter.may_match(selectors[0]));
+}
@@ -0,0 +1,528 @@
+use crate::color;
+use crate::css::{
+    Calc,
+    Color,
+    Combinator,
+    CssRule,
+    Declaration,
+    ImportRule,
+    LayerName,
+    Nth,
+    PseudoClass,
+    PseudoElement,
+    Rule,
+    Selector,
+    SimpleSelector,
+    Stylesheet,
+    Value,
+};
+use crate::media::{Comparison, MediaCondition, MediaFeature, MediaList, MediaQuery, MediaType};
+use crate::supports::SupportsCondition;
+
+/// Serialize a stylesheet following the CSSOM rules: one rule per line, with the
+/// rules of grouping rules indented, and values in their canonical form.
+pub fn serialize(stylesheet: &Stylesheet) -> String {
+    Serializer { minify: false }.rules(&stylesheet.rules, 0)
+}
+
+/// Serialize a stylesheet as compactly as possible. Empty rules and rules repeated
+/// later on are dropped, and adjacent rules with the same selectors or the same
+/// declarations are merged. The result cascades exactly like the original.
+pub fn minify(stylesheet: &Stylesheet) -> String {
+    Serializer { minify: true }.rules(&stylesheet.rules, 0)
+}
+
+pub fn serialize_value(value: &Value) -> String {
+    Serializer { minify: false }.value(value)
+}
+
+pub fn serialize_selector(selector: &Selector) -> String {
+    Serializer { minify: false }.selector(selector)
+}
+
+/// Adjacent style rules that minify into a single rule.
+struct MergedRule<'a> {
+    selectors: Vec<&'a Selector>,
+    declarations: Vec<&'a Declaration>,
+}
+
+enum Item<'a> {
+    Style(MergedRule<'a>),
+    Other(&'a CssRule),
+}
+
+struct Serializer {
+    minify: bool,
+}
+
+impl Serializer {
+    fn rules(&self, rules: &[CssRule], depth: usize) -> String {
+        let items: Vec<Item> = if self.minify {
+            merge_rules(rules)
+        } else {
+            rules.iter().map(|rule| match *rule {
+                CssRule::Style(ref rule) => Item::Style(MergedRule {
+                    selectors: rule.selectors.iter().collect(),
+                    declarations: rule.declarations.iter().collect(),
+                }),
+                ref rule => Item::Other(rule),
+            }).collect()
+        };
+        let indent = "  ".repeat(depth);
+        let serialized: Vec<String> = items.iter().map(|item| match *item {
+            Item::Style(ref rule) => self.style_rule(rule),
+            Item::Other(rule) => self.at_rule(rule, depth),
+        }).collect();
+        match self.minify {
+            true => serialized.concat(),
+            false => serialized.iter().map(|rule| format!("{indent}{rule}")).collect::<Vec<_>>().join("\n"),
+        }
+    }
+
+    fn style_rule(&self, rule: &MergedRule) -> String {
+        let selectors: Vec<String> = rule.selectors.iter().map(|s| self.selector(s)).collect();
+        let declarations: Vec<String> = rule.declarations.iter().map(|d| self.declaration(d)).collect();
+        match self.minify {
+            true => format!("{}{{{}}}", selectors.join(","), declarations.join(";")),
+            false if declarations.is_empty() => format!("{} {{ }}", selectors.join(", ")),
+            false => format!("{} {{ {}; }}", selectors.join(", "), declarations.join("; ")),
+        }
+    }
+
+    fn declaration(&self, declaration: &Declaration) -> String {
+        let important = match (declaration.important, self.minify) {
+            (false, _) => "",
+            (true, true) => "!important",
+            (true, false) => " !important",
+        };
+        let separator = if self.minify { ":" } else { ": " };
+        format!("{}{separator}{}{important}", declaration.name, self.value(&declaration.value))
+    }
+
+    fn at_rule(&self, rule: &CssRule, depth: usize) -> String {
+        let space = if self.minify { "" } else { " " };
+        let (prelude, rules) = match *rule {
+            CssRule::Style(_) => unreachable!(),
+            CssRule::Media(ref media, ref rules) => (format!("@media {}", self.media_list(media)), rules),
+            CssRule::Supports(ref condition, ref rules) => (format!("@supports {}", self.supports(condition)), rules),
+            CssRule::Layer(Some(ref name), ref rules) => (format!("@layer {}", layer_name(name)), rules),
+            CssRule::Layer(None, ref rules) => ("@layer".to_string(), rules),
+            CssRule::LayerStatement(ref names) => {
+                let names: Vec<String> = names.iter().map(layer_name).collect();
+                return format!("@layer {};", names.join(&format!(",{space}")));
+            }
+            CssRule::Import(ImportRule { ref url, ref media, .. }) => {
+                let media = match media.0.is_empty() {
+                    true => String::new(),
+                    false => format!(" {}", self.media_list(media)),
+                };
+                return format!("@import url({}){media};", self.string(url));
+            }
+        };
+        if self.minify {
+            return format!("{prelude}{{{}}}", self.rules(rules, 0));
+        }
+        match rules.is_empty() {
+            true => format!("{prelude} {{\n{}}}", "  ".repeat(depth)),
+            false => format!("{prelude} {{\n{}\n{}}}", self.rules(rules, depth + 1), "  ".repeat(depth)),
+        }
+    }
+
+    fn media_list(&self, media: &MediaList) -> String {
+        let separator = if self.minify { "," } else { ", " };
+        media.0.iter().map(|q| self.media_query(q)).collect::<Vec<_>>().join(separator)
+    }
+
+    fn media_query(&self, query: &MediaQuery) -> String {
+        let media_type = match query.media_type {
+            MediaType::All => "all",
+            MediaType::Screen => "screen",
+            MediaType::Print => "print",
+            MediaType::Unknown(ref name) => name,
+        };
+        let not = if query.negated { "not " } else { "" };
+        match query.condition {
+            None => format!("{not}{media_type}"),
+            Some(ref condition) if !query.negated && query.media_type == MediaType::All =>
+                self.media_condition(condition),
+            // Only a condition without `or` may follow a media type.
+            Some(ref condition @ MediaCondition::Or(_)) =>
+                format!("{not}{media_type} and {}", self.media_in_parens(condition)),
+            Some(ref condition) => format!("{not}{media_type} and {}", self.media_condition(condition)),
+        }
+    }
+
+    fn media_condition(&self, condition: &MediaCondition) -> String {
+        match *condition {
+            MediaCondition::Feature(_) => self.media_in_parens(condition),
+            MediaCondition::Not(ref condition) => format!("not {}", self.media_in_parens(condition)),
+            MediaCondition::And(ref conditions) => self.join_media(conditions, " and "),
+            MediaCondition::Or(ref conditions) => self.join_media(conditions, " or "),
+            MediaCondition::Unknown(ref source) => source.clone(),
+        }
+    }
+
+    fn join_media(&self, conditions: &[MediaCondition], operator: &str) -> String {
+        conditions.iter().map(|c| self.media_in_parens(c)).collect::<Vec<_>>().join(operator)
+    }
+
+    fn media_in_parens(&self, condition: &MediaCondition) -> String {
+        let feature = match *condition {
+            MediaCondition::Feature(ref feature) => feature,
+            MediaCondition::Unknown(ref source) => return source.clone(),
+            _ => return format!("({})", self.media_condition(condition)),
+        };
+        let space = if self.minify { "" } else { " " };
+        match *feature {
+            MediaFeature::Discrete(ref name, None) => format!("({name})"),
+            MediaFeature::Discrete(ref name, Some(ref value)) => format!("({name}:{space}{})", self.value(value)),
+            MediaFeature::Range(ref name, ref comparisons) => match comparisons[..] {
+                [(comparison, ref value)] =>
+                    format!("({name}{space}{}{space}{})", comparison_operator(comparison), self.value(value)),
+                [(low_comparison, ref low), (high_comparison, ref high)] => format!(
+                    "({}{space}{}{space}{name}{space}{}{space}{})",
+                    self.value(low), comparison_operator(low_comparison.flip()),
+                    comparison_operator(high_comparison), self.value(high)),
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    fn supports(&self, condition: &SupportsCondition) -> String {
+        let join = |conditions: &[SupportsCondition], operator| {
+            conditions.iter().map(|c| self.supports_in_parens(c)).collect::<Vec<_>>().join(operator)
+        };
+        match *condition {
+            SupportsCondition::Not(ref condition) => format!("not {}", self.supports_in_parens(condition)),
+            SupportsCondition::And(ref conditions) => join(conditions, " and "),
+            SupportsCondition::Or(ref conditions) => join(conditions, " or "),
+            _ => self.supports_in_parens(condition),
+        }
+    }
+
+    fn supports_in_parens(&self, condition: &SupportsCondition) -> String {
+        let space = if self.minify { "" } else { " " };
+        match *condition {
+            SupportsCondition::Declaration(ref name, ref value) => format!("({name}:{space}{value})"),
+            SupportsCondition::Unknown(ref source) => source.clone(),
+            _ => format!("({})", self.supports(condition)),
+        }
+    }
+
+    fn selector(&self, selector: &Selector) -> String {
+        match *selector {
+            Selector::Simple(ref simple) => self.simple_selector(simple),
+            Selector::Complex(ref left, combinator, ref right) => format!(
+                "{}{}{}", self.selector(left), self.combinator(combinator), self.simple_selector(right)),
+        }
+    }
+
+    fn combinator(&self, combinator: Combinator) -> &'static str {
+        match (combinator, self.minify) {
+            (Combinator::Descendant, _) => " ",
+            (Combinator::Child, true) => ">",
+            (Combinator::NextSibling, true) => "+",
+            (Combinator::SubsequentSibling, true) => "~",
+            (Combinator::Child, false) => " > ",
+            (Combinator::NextSibling, false) => " + ",
+            (Combinator::SubsequentSibling, false) => " ~ ",
+        }
+    }
+
+    fn simple_selector(&self, selector: &SimpleSelector) -> String {
+        let mut result = String::new();
+        if let Some(ref tag_name) = selector.tag_name {
+            result.push_str(tag_name);
+        }
+        if let Some(ref id) = selector.id {
+            result.push('#');
+            result.push_str(id);
+        }
+        for class in &selector.class {
+            result.push('.');
+            result.push_str(class);
+        }
+        for pseudo_class in &selector.pseudo_classes {
+            result.push(':');
+            result.push_str(&self.pseudo_class(pseudo_class));
+        }
+        match selector.pseudo_element {
+            Some(PseudoElement::Before) => result.push_str("::before"),
+            Some(PseudoElement::After) => result.push_str("::after"),
+            Some(PseudoElement::Marker) => result.push_str("::marker"),
+            None => {}
+        }
+        if result.is_empty() {
+            result.push('*');
+        }
+        result
+    }
+
+    fn pseudo_class(&self, pseudo_class: &PseudoClass) -> String {
+        let list = |selectors: &[Selector]| {
+            let separator = if self.minify { "," } else { ", " };
+            selectors.iter().map(|s| self.selector(s)).collect::<Vec<_>>().join(separator)
+        };
+        let nth_of = |nth: &Nth, of: &[Selector]| match of.is_empty() {
+            true => nth_serialization(nth),
+            false => format!("{} of {}", nth_serialization(nth), list(of)),
+        };
+        match *pseudo_class {
+            PseudoClass::Root => "root".to_string(),
+            PseudoClass::Empty => "empty".to_string(),
+            PseudoClass::FirstChild => "first-child".to_string(),
+            PseudoClass::LastChild => "last-child".to_string(),
+            PseudoClass::OnlyChild => "only-child".to_string(),
+            PseudoClass::FirstOfType => "first-of-type".to_string(),
+            PseudoClass::LastOfType => "last-of-type".to_string(),
+            PseudoClass::OnlyOfType => "only-of-type".to_string(),
+            PseudoClass::NthChild(ref nth, ref of) => format!("nth-child({})", nth_of(nth, of)),
+            PseudoClass::NthLastChild(ref nth, ref of) => format!("nth-last-child({})", nth_of(nth, of)),
+            PseudoClass::NthOfType(ref nth) => format!("nth-of-type({})", nth_serialization(nth)),
+            PseudoClass::NthLastOfType(ref nth) => format!("nth-last-of-type({})", nth_serialization(nth)),
+            PseudoClass::Not(ref selectors) => format!("not({})", list(selectors)),
+            PseudoClass::Is(ref selectors) => format!("is({})", list(selectors)),
+            PseudoClass::Where(ref selectors) => format!("where({})", list(selectors)),
+            PseudoClass::Has(ref relative) => {
+                let separator = if self.minify { "," } else { ", " };
+                let selectors: Vec<String> = relative.iter().map(|r| match r.combinator {
+                    Combinator::Descendant => self.selector(&r.selector),
+                    combinator => format!("{}{}", self.combinator(combinator).trim_start(), self.selector(&r.selector)),
+                }).collect();
+                format!("has({})", selectors.join(separator))
+            }
+        }
+    }
+
+    fn value(&self, value: &Value) -> String {
+        match *value {
+            Value::Keyword(ref keyword) => keyword.clone(),
+            Value::Length(v, unit) => format!("{}{}", self.number(v), unit.name()),
+            Value::Number(n) => self.number(n),
+            Value::Dimension(v, ref unit) => format!("{}{unit}", self.number(v)),
+            Value::ColorValue(color) => self.color(color),
+            Value::Str(ref s) => self.string(s),
+            Value::Function(ref name, ref args) if name == "url" => match args[..] {
+                [Value::Str(ref url)] => format!("url({})", self.string(url)),
+                _ => unreachable!(),
+            },
+            Value::Function(ref name, ref args) => {
+                let separator = if self.minify { "," } else { ", " };
+                let args: Vec<String> = args.iter().map(|a| self.value(a)).collect();
+                format!("{name}({})", args.join(separator))
+            }
+            Value::List(ref items) => {
+                let mut result = String::new();
+                for (i, item) in items.iter().enumerate() {
+                    let around_slash = matches!(item, Value::Slash) || matches!(items.get(i.wrapping_sub(1)), Some(Value::Slash));
+                    if i > 0 && !(self.minify && around_slash) {
+                        result.push(' ');
+                    }
+                    result.push_str(&self.value(item));
+                }
+                result
+            }
+            Value::CommaList(ref items) => {
+                let separator = if self.minify { "," } else { ", " };
+                items.iter().map(|v| self.value(v)).collect::<Vec<_>>().join(separator)
+            }
+            Value::Slash => "/".to_string(),
+            Value::Calc(ref calc) => match **calc {
+                Calc::Min(_) | Calc::Max(_) | Calc::Clamp(..) => self.calc(calc),
+                ref calc => format!("calc({})", self.calc(calc)),
+            },
+            Value::Tokens(ref tokens) | Value::Pending(_, ref tokens) => tokens.clone(),
+        }
+    }
+
+    fn calc(&self, calc: &Calc) -> String {
+        let separator = if self.minify { "," } else { ", " };
+        let args = |args: &[&Calc]| args.iter().map(|a| self.calc(a)).collect::<Vec<_>>().join(separator);
+        // Sums need parentheses inside products, and so do the right operands of products.
+        let operand = |calc: &Calc, right: bool| match *calc {
+            Calc::Sum(_) => format!("({})", self.calc(calc)),
+            Calc::Product(..) | Calc::Quotient(..) if right => format!("({})", self.calc(calc)),
+            _ => self.calc(calc),
+        };
+        let operator = |op: &str| if self.minify { op.to_string() } else { format!(" {op} ") };
+        match *calc {
+            Calc::Number(n) => self.number(n),
+            Calc::Length(v, unit) => format!("{}{}", self.number(v), unit.name()),
+            Calc::Sum(ref terms) => {
+                let mut result = String::new();
+                for (i, term) in terms.iter().enumerate() {
+                    // `+` and `-` must always be surrounded by whitespace.
+                    match *term {
+                        Calc::Negate(ref term) if i > 0 => {
+                            result.push_str(" - ");
+                            result.push_str(&operand(term, false));
+                        }
+                        Calc::Negate(ref term) => result.push_str(&format!("-1{}{}", operator("*"), operand(term, true))),
+                        ref term => {
+                            if i > 0 {
+                                result.push_str(" + ");
+                            }
+                            result.push_str(&operand(term, false));
+                        }
+                    }
+                }
+                result
+            }
+            Calc::Negate(ref term) => format!("-1{}{}", operator("*"), operand(term, true)),
+            Calc::Product(ref a, ref b) => format!("{}{}{}", operand(a, false), operator("*"), operand(b, true)),
+            Calc::Quotient(ref a, ref b) => format!("{}{}{}", operand(a, false), operator("/"), operand(b, true)),
+            Calc::Min(ref values) => format!("min({})", args(&values.iter().collect::<Vec<_>>())),
+            Calc::Max(ref values) => format!("max({})", args(&values.iter().collect::<Vec<_>>())),
+            Calc::Clamp(ref min, ref value, ref max) => format!("clamp({})", args(&[min, value, max])),
+        }
+    }
+
+    fn number(&self, n: f32) -> String {
+        let s = n.to_string();
+        match self.minify {
+            true => s.replacen("0.", ".", usize::from(s.starts_with("0.") || s.starts_with("-0."))),
+            false => s,
+        }
+    }
+
+    /// Colors serialize as `rgb()` or `rgba()`; when minifying, as the shortest
+    /// of their name and hex notations.
+    fn color(&self, color: Color) -> String {
+        let Color { r, g, b, a } = color;
+        if !self.minify {
+            return match a {
+                255 => format!("rgb({r}, {g}, {b})"),
+                _ => format!("rgba({r}, {g}, {b}, {})", alpha(a)),
+            };
+        }
+        let channels: Vec<u8> = if a == 255 { vec![r, g, b] } else { vec![r, g, b, a] };
+        let hex = match channels.iter().all(|c| c % 17 == 0) {
+            true => channels.iter().map(|c| format!("{:x}", c / 17)).collect::<String>(),
+            false => channels.iter().map(|c| format!("{c:02x}")).collect::<String>(),
+        };
+        match color::color_name(color) {
+            Some(name) if name.len() <= hex.len() + 1 => name.to_string(),
+            _ => format!("#{hex}"),
+        }
+    }
+
+    fn string(&self, s: &str) -> String {
+        let mut result = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '"' | '\\' => {
+                    result.push('\\');
+                    result.push(c);
+                }
+                c if c.is_control() => result.push_str(&format!("\\{:x} ", c as u32)),
+                c => result.push(c),
+            }
+        }
+        result.push('"');
+        result
+    }
+}
+
+/// An alpha channel as the shortest decimal that maps back to the same byte.
+fn alpha(a: u8) -> String {
+    (2..=3)
+        .map(|precision| format!("{:.*}", precision, a as f32 / 255.0))
+        .map(|s| s.trim_end_matches('0').trim_end_matches('.').to_string())
+        .find(|s| (s.parse::<f32>().unwrap() * 255.0).round() as u8 == a)
+        .unwrap()
+}
+
+fn nth_serialization(nth: &Nth) -> String {
+    let a = match nth.a {
+        0 => return nth.b.to_string(),
+        1 => "n".to_string(),
+        -1 => "-n".to_string(),
+        a => format!("{a}n"),
+    };
+    match nth.b {
+        0 => a,
+        b if b > 0 => format!("{a}+{b}"),
+        b => format!("{a}{b}"),
+    }
+}
+
+fn comparison_operator(comparison: Comparison) -> &'static str {
+    match comparison {
+        Comparison::Less => "<",
+        Comparison::LessOrEqual => "<=",
+        Comparison::Equal => "=",
+        Comparison::GreaterOrEqual => ">=",
+        Comparison::Greater => ">",
+    }
+}
+
+fn layer_name(name: &LayerName) -> String {
+    name.join(".")
+}
+
+/// Drop empty style rules and those repeated later in the same list, which can't affect
+/// the cascade, then merge adjacent rules that share their selectors or declarations.
+fn merge_rules(rules: &[CssRule]) -> Vec<Item<'_>> {
+    let mut items: Vec<Item> = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let style: &Rule = match *rule {
+            CssRule::Style(ref style) => style,
+            ref rule => {
+                items.push(Item::Other(rule));
+                continue;
+            }
+        };
+        if style.declarations.is_empty() || rules[i + 1..].contains(rule) {
+            continue;
+        }
+        if let Some(Item::Style(ref mut last)) = items.last_mut() {
+            if last.selectors.iter().copied().eq(style.selectors.iter()) {
+                last.declarations.extend(&style.declarations);
+                continue;
+            }
+            if last.declarations.iter().copied().eq(style.declarations.iter()) {
+                last.selectors.extend(&style.selectors);
+                continue;
+            }
+        }
+        items.push(Item::Style(MergedRule {
+            selectors: style.selectors.iter().collect(),
+            declarations: style.declarations.iter().collect(),
+        }));
+    }
+    items
+}
+
+#[test]
+fn serialize_values_and_selectors() {
+    use crate::css::parse;
+    let stylesheet = parse(r#"
+        ul > li:nth-child(-n+3)::after, p:not(.a, #b) { color: #80808080; content: "a\"b" counter(x); width: calc(50% - (2px + 1em) / 2) }
+        @media screen and ((width >= 600px) or (color)) { p { height: max(1px, 2vh) } }
+    "#.to_string());
+    // Selector lists are sorted by specificity when parsed.
+    assert_eq!(serialize(&stylesheet), "\
+p:not(.a, #b), ul > li:nth-child(-n+3)::after { color: rgba(128, 128, 128, 0.5); content: \"a\\\"b\" counter(x); width: calc(50% - (2px + 1em) / 2); }
+@media screen and ((width >= 600px) or (color)) {
+  p { height: max(1px, 2vh); }
+}");
+}
+
+#[test]
+fn minify_merges_rules() {
+    use crate::css::parse;
+    let stylesheet = parse("
+        a { color: blue } p { } a { margin-left: 0.5px; margin-left: calc(1px + 2%) }
+        h1 { color: #ffffff } h2 { color: #ffffff } em { color: red } h1 { color: #ffffff }
+    ".to_string());
+    assert_eq!(minify(&stylesheet),
+               "a{color:blue;margin-left:.5px;margin-left:calc(1px + 2%)}h2{color:#fff}em{color:red}h1{color:#fff}");
+}
+
+#[test]
+fn alpha_uses_the_shortest_round_trip() {
+    assert_eq!("0.5", alpha(128));
+    assert_eq!("0.2", alpha(51));
+    assert_eq!("0.004", alpha(1));
+    assert_eq!("0", alpha(0));
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use crate::computed::ComputedStyle;
+use crate::dom::ElementData;
+use crate::style::PropertyMap;
+
+/// How many recently styled elements are kept as candidates for sharing.
+const CACHE_SIZE: usize = 16;
+
+/// Which revalidation selectors an element matches, by position in the rule index.
+pub type Revalidation = Vec<((usize, usize, usize), bool)>;
+
+/// A cache of recently computed styles, so that siblings and cousins that are certain to
+/// get the same style reuse it instead of going through selector matching and the cascade.
+///
+/// Two elements share a style when they have the same tag name and attributes, neither has
+/// an id or a `style` attribute, their parents have the very same style (which, for cousins,
+/// means the parents shared theirs), and they match the same revalidation selectors.
+#[derive(Clone, Default)]
+pub struct StyleSharingCache<'a> {
+    /// Most recently used first.
+    entries: Vec<CacheEntry<'a>>,
+}
+
+/// How often the style sharing cache found a style to reuse, among the elements
+/// that could share one. With the `parallel` feature, each thread has its own cache,
+/// so these numbers vary from run to run; the styles don't.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StyleSharingStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+#[derive(Clone)]
+struct CacheEntry<'a> {
+    element: &'a ElementData,
+    parent_values: Arc<PropertyMap>,
+    revalidation: Revalidation,
+    values: Arc<PropertyMap>,
+    style: Arc<ComputedStyle>,
+}
+
+/// Whether an element can share its style at all. Ids make matching unique to the element,
+/// and the declarations of a `style` attribute aren't part of any stylesheet.
+pub fn is_shareable(element: &ElementData) -> bool {
+    element.id().is_none() && !element.attributes.contains_key("style")
+}
+
+impl<'a> StyleSharingCache<'a> {
+    pub fn lookup(&mut self, element: &ElementData, parent_values: &Arc<PropertyMap>,
+                  revalidation: &Revalidation) -> Option<(Arc<PropertyMap>, Arc<ComputedStyle>)> {
+        let found = self.entries.iter().position(|entry| {
+            Arc::ptr_eq(&entry.parent_values, parent_values) &&
+                entry.element.tag_name == element.tag_name &&
+                entry.element.attributes == element.attributes &&
+                entry.revalidation == *revalidation
+        });
+        let entry = self.entries.remove(found?);
+        let shared = (entry.values.clone(), entry.style.clone());
+        self.entries.insert(0, entry);
+        Some(shared)
+    }
+
+    pub fn insert(&mut self, element: &'a ElementData, parent_values: Arc<PropertyMap>, revalidation: Revalidation,
+                  values: Arc<PropertyMap>, style: Arc<ComputedStyle>) {
+        self.entries.insert(0, CacheEntry { element, parent_values, revalidation, values, style });
+        self.entries.truncate(CACHE_SIZE);
+    }
+}
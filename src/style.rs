@@ -1,13 +1,56 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use crate::computed::{
+    self,
+    ComputedStyle,
+    FontValues,
+    MEDIUM_FONT_SIZE,
+};
+use crate::content::{
+    generate_content,
+    marker_content,
+    Counters,
+};
 use crate::css::{
+    Calc,
+    CssRule,
+    Combinator,
+    Declaration,
+    LayerName,
+    LengthContext,
+    PseudoClass,
+    PseudoElement,
+    RelativeSelector,
+    Origin,
     Rule,
     Selector,
-    Selector::Simple,
     SimpleSelector,
+    SourceLocation,
     Specificity,
     Stylesheet,
+    Unit,
     Value,
 };
+use crate::invalidation::{
+    InvalidationMap,
+    Reach,
+};
+use crate::media::Device;
+use crate::rule_index::{
+    AncestorFilter,
+    RuleIndex,
+};
+use crate::sharing::{
+    self,
+    Revalidation,
+    StyleSharingCache,
+    StyleSharingStats,
+};
+use crate::font;
+use crate::properties;
+use crate::variables;
 use crate::dom::{
     ElementData,
     Node,
@@ -18,129 +61,1528 @@ use crate::dom::{
     },
 };
 
+lazy_static! {
+    /// The built-in defaults that every document is styled with before any other stylesheet.
+    static ref USER_AGENT_STYLESHEET: Stylesheet = crate::css::parse_with_origin(
+        include_str!("user_agent.css").to_string(), Origin::UserAgent);
+}
+
+pub use crate::computed::Display;
+
 /// Map from CSS property names to values.
 pub type PropertyMap = HashMap<String, Value>;
 
 /// A node with associated style data.
 pub struct StyledNode<'a> {
+    /// The DOM node, or the originating element of a pseudo-element.
     node: &'a Node,
-    specified_values: PropertyMap,
+    /// Shared with other elements that are certain to have the same style.
+    computed_values: Arc<PropertyMap>,
+    /// The same computed values in typed form, for layout and painting. It is built once
+    /// for each map, and shared along with it.
+    style: Arc<ComputedStyle>,
     pub children: Vec<StyledNode<'a>>,
-}
-
-#[derive(Debug, PartialEq)]
-pub enum Display {
-    Inline,
-    Block,
-    None,
+    /// Boxes generated by `::before` and `::after`, if their `content` produces any.
+    pub before: Option<Box<StyledNode<'a>>>,
+    pub after: Option<Box<StyledNode<'a>>>,
+    /// The `::marker` box of a list item, unless its `list-style-type` is `none`.
+    pub marker: Option<Box<StyledNode<'a>>>,
+    pseudo_element: Option<PseudoElement>,
+    /// The text produced by the `content` property of a pseudo-element.
+    generated_content: Option<String>,
 }
 
 impl<'a> StyledNode<'a> {
+    fn with_shared_style(node: &'a Node, computed_values: Arc<PropertyMap>, style: Arc<ComputedStyle>) -> StyledNode<'a> {
+        StyledNode {
+            node,
+            style,
+            computed_values,
+            children: Vec::new(),
+            before: None,
+            after: None,
+            marker: None,
+            pseudo_element: None,
+            generated_content: None,
+        }
+    }
+
+    pub fn node(&self) -> &'a Node {
+        self.node
+    }
+
+    pub fn pseudo_element(&self) -> Option<PseudoElement> {
+        self.pseudo_element
+    }
+
+    /// The text of a text node, or the generated content of a pseudo-element.
+    pub fn text(&self) -> Option<&str> {
+        match (&self.generated_content, &self.node.node_type) {
+            (Some(content), _) => Some(content),
+            (None, Text(text)) if self.pseudo_element.is_none() => Some(text),
+            _ => None,
+        }
+    }
+
     pub fn value(&self, name: &str) -> Option<Value> {
-        self.specified_values.get(name).cloned()
+        self.computed_values.get(name).cloned()
     }
 
-    pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
-        self.value(name).unwrap_or_else(|| self.value(fallback_name)
-                        .unwrap_or_else(|| default.clone()))
+    pub fn style(&self) -> &ComputedStyle {
+        &self.style
     }
 
     pub fn display(&self) -> Display {
-        match self.value("display") {
-            Some(Value::Keyword(s)) => match &*s {
-                "block" => Display::Block,
-                "none" => Display::None,
-                _ => Display::Inline
-            },
-            _ => Display::Inline
+        self.style.display
+    }
+}
+
+/// A rule whose selector matched an element, with everything the cascade needs to order it.
+struct MatchedRule<'a> {
+    /// The most specific of the rule's selectors that match.
+    selector: &'a Selector,
+    specificity: Specificity,
+    origin: Origin,
+    /// Rank of the rule's cascade layer within its origin; unlayered rules rank last.
+    layer: usize,
+    /// Position of the stylesheet and of the rule within it.
+    source_order: (usize, usize),
+    rule: &'a Rule,
+}
+
+/// The origin and importance of a declaration, in increasing order of precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CascadeLevel {
+    UserAgentNormal,
+    UserNormal,
+    AuthorNormal,
+    AuthorImportant,
+    UserImportant,
+    UserAgentImportant,
+}
+
+impl CascadeLevel {
+    pub fn new(origin: Origin, important: bool) -> CascadeLevel {
+        match (origin, important) {
+            (Origin::UserAgent, false) => CascadeLevel::UserAgentNormal,
+            (Origin::User, false) => CascadeLevel::UserNormal,
+            (Origin::Author, false) => CascadeLevel::AuthorNormal,
+            (Origin::Author, true) => CascadeLevel::AuthorImportant,
+            (Origin::User, true) => CascadeLevel::UserImportant,
+            (Origin::UserAgent, true) => CascadeLevel::UserAgentImportant,
+        }
+    }
+}
+
+/// Sort key for a declaration: later entries win. The flag marks declarations from
+/// the `style` attribute, which beat any selector within the same cascade level.
+/// Then come cascade layers, specificity and source order.
+type CascadePriority = (CascadeLevel, bool, usize, Specificity, (usize, usize));
+
+/// An element together with its position in the tree, which structural
+/// pseudo-classes and combinators need in order to look at siblings and ancestors.
+#[derive(Clone, Copy)]
+pub struct ElementRef<'a, 'p> {
+    node: &'a Node,
+    parent: Option<&'p ElementRef<'a, 'p>>,
+    /// Index of `node` in its parent's `children`.
+    index: usize,
+}
+
+impl<'a, 'p> ElementRef<'a, 'p> {
+    pub fn root(node: &'a Node) -> ElementRef<'a, 'p> {
+        ElementRef { node, parent: None, index: 0 }
+    }
+
+    fn child(&'p self, index: usize) -> ElementRef<'a, 'p> {
+        ElementRef { node: &self.node.children[index], parent: Some(self), index }
+    }
+
+    fn data(&self) -> &'a ElementData {
+        match self.node.node_type {
+            Element(ref data) => data,
+            _ => panic!("ElementRef does not point to an element")
         }
     }
+
+    /// Element children, in document order.
+    fn children(&'p self) -> impl DoubleEndedIterator<Item=ElementRef<'a, 'p>> + 'p {
+        self.node.children.iter().enumerate()
+            .filter(|(_, c)| matches!(c.node_type, Element(_)))
+            .map(move |(i, _)| self.child(i))
+    }
+
+    /// Element siblings before this one, nearest first.
+    fn previous_siblings(&self) -> impl Iterator<Item=ElementRef<'a, 'p>> + '_ {
+        let index = self.index;
+        self.parent.into_iter()
+            .flat_map(move |p| p.children().rev().filter(move |s| s.index < index))
+    }
+
+    /// Element siblings after this one, nearest first.
+    fn following_siblings(&self) -> impl Iterator<Item=ElementRef<'a, 'p>> + '_ {
+        let index = self.index;
+        self.parent.into_iter()
+            .flat_map(move |p| p.children().filter(move |s| s.index > index))
+    }
+
+    fn ancestors(&self) -> impl Iterator<Item=&'p ElementRef<'a, 'p>> {
+        std::iter::successors(self.parent, |p| p.parent)
+    }
+
+    fn same_node(&self, other: &ElementRef) -> bool {
+        std::ptr::eq(self.node, other.node)
+    }
 }
 
-type MatchedRule<'a> = (Specificity, &'a Rule);
+/// The style rules of a stylesheet that apply on the device being styled for,
+/// each with the rank of its cascade layer.
+struct ActiveStylesheet<'a> {
+    stylesheet: &'a Stylesheet,
+    origin: Origin,
+    rules: Vec<(&'a Rule, usize)>,
+}
 
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
-    stylesheet.rules.iter()
-        .filter_map(|rule| match_rule(elem, rule))
+/// Collect the rules of every stylesheet that apply on `device`, and rank their cascade
+/// layers. Layers are per origin; each comes after the layers declared before it, and
+/// after its own nested layers. Rules outside any layer come last.
+fn active_stylesheets<'a>(stylesheets: &[&'a Stylesheet], device: &Device) -> Vec<ActiveStylesheet<'a>> {
+    let mut layers: HashMap<Origin, Vec<LayerName>> = HashMap::new();
+    let layered: Vec<_> = stylesheets.iter()
+        .map(|stylesheet| stylesheet.layered_style_rules(device, layers.entry(stylesheet.origin).or_default()))
+        .collect();
+
+    let ranks: HashMap<Origin, HashMap<LayerName, usize>> = layers.into_iter()
+        .map(|(origin, layers)| {
+            // Sorting by the position of each ancestor among its siblings, with a parent
+            // after its children, gives the layer order.
+            let mut keys: Vec<(Vec<usize>, LayerName)> = layers.iter().map(|name| {
+                let mut key: Vec<usize> = (1..=name.len())
+                    .map(|depth| layers.iter()
+                        .filter(|l| l.len() == depth && l[..depth - 1] == name[..depth - 1])
+                        .position(|l| l[..] == name[..depth])
+                        .unwrap())
+                    .collect();
+                key.push(usize::MAX);
+                (key, name.clone())
+            }).collect();
+            keys.sort();
+            let mut ranks: HashMap<LayerName, usize> = keys.into_iter().enumerate()
+                .map(|(rank, (_, name))| (name, rank))
+                .collect();
+            ranks.insert(Vec::new(), ranks.len());
+            (origin, ranks)
+        })
+        .collect();
+
+    stylesheets.iter().zip(layered)
+        .map(|(stylesheet, rules)| ActiveStylesheet {
+            stylesheet,
+            origin: stylesheet.origin,
+            rules: rules.into_iter().map(|r| (r.rule, ranks[&stylesheet.origin][&r.layer])).collect(),
+        })
+        .collect()
+}
+
+/// The rules with a selector that matches `elem`. Only the selectors indexed under the
+/// element's id, classes and tag name are tested, and those needing ancestors that
+/// the ancestor filter has never seen are skipped.
+fn matching_rules<'a>(elem: &ElementRef, pseudo: Option<PseudoElement>, context: &StyleContext<'a>,
+                      filter: &AncestorFilter) -> Vec<MatchedRule<'a>> {
+    let mut matched: Vec<(usize, usize, usize)> = context.index.candidates(elem.data())
+        .filter(|s| s.selector.pseudo_element() == pseudo && filter.may_match(s) && matches(elem, s.selector))
+        .map(|s| s.position)
+        .collect();
+    // Selectors are sorted by specificity, so the first matching one of a rule is the most specific.
+    matched.sort();
+    matched.dedup_by_key(|&mut (sheet, rule, _)| (sheet, rule));
+    matched.into_iter()
+        .map(|(sheet_index, rule_index, selector_index)| {
+            let stylesheet = &context.stylesheets[sheet_index];
+            let (rule, layer) = stylesheet.rules[rule_index];
+            let selector = &rule.selectors[selector_index];
+            MatchedRule {
+                selector,
+                specificity: selector.specificity(),
+                origin: stylesheet.origin,
+                layer,
+                source_order: (sheet_index, rule_index),
+                rule,
+            }
+        })
         .collect()
 }
 
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
-    rule.selectors.iter()
-        .find(|s| matches(elem, *s))
-        .map(|s| (s.specificity(), rule))
+pub fn matches(elem: &ElementRef, selector: &Selector) -> bool {
+    matches_anchored(elem, selector, None)
 }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+/// Match `selector` against `elem`. When `anchor` is given, the leftmost compound
+/// selector must additionally be related to the anchor element by the combinator,
+/// which is how the relative selectors inside `:has()` are evaluated.
+fn matches_anchored(elem: &ElementRef, selector: &Selector, anchor: Option<(&ElementRef, Combinator)>) -> bool {
     match *selector {
-        Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector)
+        Selector::Simple(ref simple_selector) => {
+            matches_simple_selector(elem, simple_selector) &&
+                anchor.is_none_or(|(anchor, combinator)| match combinator {
+                    Combinator::Descendant => elem.ancestors().any(|a| a.same_node(anchor)),
+                    Combinator::Child => elem.parent.is_some_and(|p| p.same_node(anchor)),
+                    Combinator::NextSibling => elem.previous_siblings().next().is_some_and(|s| s.same_node(anchor)),
+                    Combinator::SubsequentSibling => elem.previous_siblings().any(|s| s.same_node(anchor)),
+                })
+        }
+        Selector::Complex(ref left, combinator, ref right) => {
+            if !matches_simple_selector(elem, right) {
+                return false;
+            }
+            match combinator {
+                Combinator::Descendant => elem.ancestors().any(|a| matches_anchored(a, left, anchor)),
+                Combinator::Child => elem.parent.is_some_and(|p| matches_anchored(p, left, anchor)),
+                Combinator::NextSibling => elem.previous_siblings().next()
+                    .is_some_and(|s| matches_anchored(&s, left, anchor)),
+                Combinator::SubsequentSibling => elem.previous_siblings()
+                    .any(|s| matches_anchored(&s, left, anchor)),
+            }
+        }
     }
 }
 
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
-    if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
+fn matches_simple_selector(elem: &ElementRef, selector: &SimpleSelector) -> bool {
+    let data = elem.data();
+    if selector.tag_name.iter().any(|name| data.tag_name != *name) {
         return false;
     }
 
-    if selector.id.iter().any(|id| elem.id() != Some(id)) {
+    if selector.id.iter().any(|id| data.id() != Some(id)) {
         return false;
     }
 
-    let elem_classes = elem.classes();
+    let elem_classes = data.classes();
     if selector.class.iter().any(|class| !elem_classes.contains(&**class)) {
         return false;
     }
 
-    return true;
+    selector.pseudo_classes.iter().all(|pseudo_class| matches_pseudo_class(elem, pseudo_class))
 }
 
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
-    let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
+fn matches_pseudo_class(elem: &ElementRef, pseudo_class: &PseudoClass) -> bool {
+    let same_type = |s: &ElementRef| s.data().tag_name == elem.data().tag_name;
+    match *pseudo_class {
+        PseudoClass::Root => elem.parent.is_none(),
+        PseudoClass::Empty => elem.node.children.iter().all(|c| matches!(c.node_type, Comment(_))),
+        PseudoClass::FirstChild => elem.previous_siblings().next().is_none(),
+        PseudoClass::LastChild => elem.following_siblings().next().is_none(),
+        PseudoClass::OnlyChild =>
+            elem.previous_siblings().next().is_none() && elem.following_siblings().next().is_none(),
+        PseudoClass::FirstOfType => !elem.previous_siblings().any(|s| same_type(&s)),
+        PseudoClass::LastOfType => !elem.following_siblings().any(|s| same_type(&s)),
+        PseudoClass::OnlyOfType =>
+            !elem.previous_siblings().any(|s| same_type(&s)) && !elem.following_siblings().any(|s| same_type(&s)),
+        PseudoClass::NthChild(nth, ref of) => {
+            matches_any(elem, of) &&
+                nth.matches(1 + elem.previous_siblings().filter(|s| matches_any(s, of)).count() as i32)
+        }
+        PseudoClass::NthLastChild(nth, ref of) => {
+            matches_any(elem, of) &&
+                nth.matches(1 + elem.following_siblings().filter(|s| matches_any(s, of)).count() as i32)
+        }
+        PseudoClass::NthOfType(nth) =>
+            nth.matches(1 + elem.previous_siblings().filter(|s| same_type(s)).count() as i32),
+        PseudoClass::NthLastOfType(nth) =>
+            nth.matches(1 + elem.following_siblings().filter(|s| same_type(s)).count() as i32),
+        PseudoClass::Not(ref list) => !list.iter().any(|s| matches(elem, s)),
+        PseudoClass::Is(ref list) | PseudoClass::Where(ref list) => list.iter().any(|s| matches(elem, s)),
+        PseudoClass::Has(ref list) => list.iter().any(|relative| matches_relative(elem, relative)),
+    }
+}
 
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in rules {
-        for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
+/// Whether `elem` matches any selector in `list`. An empty list matches everything,
+/// which is the behaviour of `:nth-child()` without an `of S` clause.
+fn matches_any(elem: &ElementRef, list: &[Selector]) -> bool {
+    list.is_empty() || list.iter().any(|s| matches(elem, s))
+}
+
+/// Whether some element reachable from `anchor` matches the relative selector.
+fn matches_relative(anchor: &ElementRef, relative: &RelativeSelector) -> bool {
+    let test = |candidate: &ElementRef| matches_anchored(candidate, &relative.selector, Some((anchor, relative.combinator)));
+    match relative.combinator {
+        Combinator::Descendant | Combinator::Child => any_descendant(anchor, &test),
+        Combinator::NextSibling | Combinator::SubsequentSibling => anchor.following_siblings()
+            .any(|s| test(&s) || any_descendant(&s, &test)),
+    }
+}
+
+fn any_descendant(elem: &ElementRef, test: &dyn Fn(&ElementRef) -> bool) -> bool {
+    elem.children().any(|c| test(&c) || any_descendant(&c, test))
+}
+
+/// The cascaded value of every property declared for the element, which may still be
+/// a CSS-wide keyword such as `inherit`.
+fn specified_values(elem: &ElementRef, pseudo: Option<PseudoElement>, context: &StyleContext,
+                    filter: &AncestorFilter) -> PropertyMap {
+    let rules = matching_rules(elem, pseudo, context, filter);
+    let inline_style = match pseudo {
+        None => inline_style(elem.data()),
+        Some(_) => None,
+    };
+    cascade(&sorted_declarations(&rules, inline_style.as_ref())).0
+}
+
+/// The rule parsed from an element's `style` attribute, if it has one.
+fn inline_style(elem: &ElementData) -> Option<Rule> {
+    match elem.attributes.contains_key("style") {
+        true => elem.style().rules.into_iter().find_map(|rule| match rule {
+            CssRule::Style(rule) => Some(rule),
+            _ => None,
+        }),
+        false => None,
+    }
+}
+
+/// The declarations of the matched rules and of the inline style, from the lowest
+/// cascade priority to the highest.
+fn sorted_declarations<'r>(rules: &'r [MatchedRule], inline_style: Option<&'r Rule>)
+                           -> Vec<(CascadePriority, Origin, &'r Declaration)> {
+    let mut declarations: Vec<(CascadePriority, Origin, &Declaration)> = rules.iter()
+        .flat_map(|matched| matched.rule.declarations.iter().map(move |declaration| {
+            let level = CascadeLevel::new(matched.origin, declaration.important);
+            // Important declarations in earlier layers win, like earlier origins do.
+            let layer = if declaration.important { usize::MAX - matched.layer } else { matched.layer };
+            ((level, false, layer, matched.specificity, matched.source_order), matched.origin, declaration)
+        }))
+        .collect();
+
+    // Inline style declarations belong to the author origin.
+    declarations.extend(inline_style.iter()
+        .flat_map(|rule| &rule.declarations)
+        .map(|declaration| {
+            let level = CascadeLevel::new(Origin::Author, declaration.important);
+            ((level, true, 0, (0, 0, 0), (0, 0)), Origin::Author, declaration)
+        }));
+
+    // The sort is stable, so later declarations within a rule still win ties.
+    declarations.sort_by_key(|&(priority, _, _)| priority);
+    declarations
+}
+
+/// Cascade sorted declarations into the value of each property they declare. Also says,
+/// for each declaration, whether it decided the value of any property.
+fn cascade(declarations: &[(CascadePriority, Origin, &Declaration)]) -> (PropertyMap, Vec<bool>) {
+    // Walk from the highest priority down, so the first declaration seen for a property wins.
+    // Shorthands are expanded so that each longhand cascades on its own.
+    // `revert` discards the declarations of its own origin and above for that property.
+    let mut values = HashMap::new();
+    let mut reverted: HashMap<String, Origin> = HashMap::new();
+    let mut applied = vec![false; declarations.len()];
+    for (i, (_, origin, declaration)) in declarations.iter().enumerate().rev() {
+        for (name, value) in properties::expand(&declaration.name, &declaration.value) {
+            if values.contains_key(&name) || reverted.get(&name).is_some_and(|&cap| *origin >= cap) {
+                continue;
+            }
+            applied[i] = true;
+            match value {
+                Value::Keyword(ref k) if k == "revert" => { reverted.insert(name, *origin); }
+                value => { values.insert(name, value); }
+            }
         }
     }
+    // Reverting past the user-agent origin leaves the property as if it were unset.
+    for (name, _) in reverted {
+        values.entry(name).or_insert_with(|| Value::Keyword("unset".to_string()));
+    }
+
+    (values, applied)
+}
+
+/// Turn cascaded values into computed values: resolve `inherit`, `initial` and `unset`,
+/// fill in inherited properties from the parent (or their initial values at the root),
+/// and make font-relative and absolute lengths absolute.
+fn computed_values(specified: PropertyMap, parent: Option<&PropertyMap>, root_font_size: Option<f32>) -> PropertyMap {
+    let inherit = |name: &str| match parent {
+        Some(parent) => parent.get(name).cloned(),
+        None => properties::initial_value(name),
+    };
 
+    let mut values: PropertyMap = properties::inherited_properties()
+        .filter_map(|(name, _)| inherit(name).map(|value| (name.to_string(), value)))
+        .collect();
+    values.extend(parent.into_iter().flatten()
+        .filter(|(name, _)| variables::is_custom_property(name))
+        .map(|(name, value)| (name.clone(), value.clone())));
+
+    // Custom properties are computed first, so that other properties can substitute them.
+    let (custom, specified): (PropertyMap, PropertyMap) = specified.into_iter()
+        .partition(|(name, _)| variables::is_custom_property(name));
+    let custom_names: Vec<String> = custom.keys().cloned().collect();
+    for (name, value) in custom {
+        apply_specified_value(&mut values, name, value, &inherit);
+    }
+    variables::resolve_custom_properties(&mut values, custom_names.iter());
+
+    for (name, value) in specified {
+        // A value whose variables can't be substituted is invalid at computed-value time,
+        // and behaves as `unset`.
+        let value = match value {
+            Value::Pending(ref property, ref tokens) => variables::resolve_pending(&name, property, tokens, &values)
+                .unwrap_or_else(|| Value::Keyword("unset".to_string())),
+            value => value,
+        };
+        apply_specified_value(&mut values, name, value, &inherit);
+    }
+
+    if let Some(weight) = values.get_mut("font-weight") {
+        let parent_weight = match parent.and_then(|p| p.get("font-weight")) {
+            Some(&Value::Number(weight)) => weight,
+            _ => 400.0,
+        };
+        *weight = Value::Number(computed::font_weight(weight, parent_weight));
+    }
+
+    // `font-size` is relative to the parent's font; everything else to the element's own.
+    let parent_font_size = parent.map_or(MEDIUM_FONT_SIZE, font_size);
+    let mut lengths = LengthContext {
+        font_size: parent_font_size,
+        root_font_size: root_font_size.unwrap_or(MEDIUM_FONT_SIZE),
+        percentage_base: parent_font_size,
+        ..Default::default()
+    };
+    if let Some(parent) = parent {
+        set_font_metrics(&mut lengths, parent);
+    }
+    if let Some(size) = values.get_mut("font-size") {
+        *size = match computed::font_size_keyword(size, parent_font_size) {
+            Some(px) => Value::Length(px, Unit::Px),
+            None => resolve_lengths(size, &lengths, true),
+        };
+    }
+    lengths.font_size = font_size(&values);
+    lengths.root_font_size = root_font_size.unwrap_or(lengths.font_size);
+    lengths.percentage_base = lengths.font_size;
+    set_font_metrics(&mut lengths, &values);
+    for (name, value) in values.iter_mut() {
+        match &**name {
+            "font-size" => {}
+            // A percentage line height is of the element's own font size, and is inherited
+            // as the resulting length.
+            "line-height" => *value = resolve_lengths(value, &lengths, true),
+            _ => *value = resolve_lengths(value, &lengths, false),
+        }
+    }
     values
 }
 
-/// Apply a stylesheet to an entire DOM tree, returning a StyledNode tree.
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
-    StyledNode {
-        node: root,
-        specified_values: match root.node_type {
-            Element(ref elem) => specified_values(elem, stylesheet),
-            Text(_) => HashMap::new(),
-            Comment(_) => HashMap::new(),
+/// Store the value of `name`, resolving the CSS-wide keywords.
+fn apply_specified_value(values: &mut PropertyMap, name: String, value: Value,
+                         inherit: &dyn Fn(&str) -> Option<Value>) {
+    let computed = match value {
+        Value::Keyword(ref k) => match &**k {
+            "inherit" => inherit(&name),
+            "initial" => properties::initial_value(&name),
+            "unset" if properties::is_inherited(&name) => inherit(&name),
+            "unset" => properties::initial_value(&name),
+            _ => Some(value),
         },
-        children: root.children.iter().map(|c| style_tree(c, stylesheet)).collect(),
+        _ => Some(value),
+    };
+    match computed {
+        Some(computed) => { values.insert(name, computed); }
+        None => { values.remove(&name); }
+    }
+}
+
+/// Resolve `ex` and `ch` against the font that the computed values select, at the
+/// font size in `lengths`.
+fn set_font_metrics(lengths: &mut LengthContext, values: &PropertyMap) {
+    let font = FontValues::from_values(values, lengths.font_size);
+    let face = font::select(&font);
+    lengths.x_height = Some(face.metrics(font.size).x_height);
+    lengths.zero_advance = Some(face.zero_advance(font.size));
+}
+
+/// The computed font size in px of an element with the given computed values.
+fn font_size(values: &PropertyMap) -> f32 {
+    match values.get("font-size") {
+        Some(&Value::Length(size, Unit::Px)) => size,
+        _ => MEDIUM_FONT_SIZE,
     }
 }
 
+/// Convert font-relative and absolute lengths to px. Viewport units and percentages are
+/// left for layout, except that percentages are resolved when `percentages` is set.
+/// Math functions that no longer depend on anything are folded into a single length.
+fn resolve_lengths(value: &Value, lengths: &LengthContext, percentages: bool) -> Value {
+    let resolvable = |unit: Unit| unit.is_font_relative() || unit.absolute_px().is_some() ||
+                                  (percentages && unit == Unit::Percent);
+    match *value {
+        Value::Length(v, unit) if resolvable(unit) => Value::Length(unit.to_px(v, lengths), Unit::Px),
+        Value::Calc(ref calc) => {
+            let calc = calc.map_lengths(&|v, unit| match resolvable(unit) {
+                true => Calc::Length(unit.to_px(v, lengths), Unit::Px),
+                false => Calc::Length(v, unit),
+            });
+            if !calc.any_unit(&|_| true) {
+                // A calculation without lengths, such as `calc(1 + 0.5)`, is a number.
+                Value::Number(calc.to_px(lengths))
+            } else if calc.any_unit(&|unit| unit != Unit::Px) {
+                Value::Calc(Box::new(calc))
+            } else {
+                Value::Length(calc.to_px(lengths), Unit::Px)
+            }
+        }
+        Value::List(ref items) =>
+            Value::List(items.iter().map(|v| resolve_lengths(v, lengths, percentages)).collect()),
+        Value::CommaList(ref items) =>
+            Value::CommaList(items.iter().map(|v| resolve_lengths(v, lengths, percentages)).collect()),
+        ref value => value.clone(),
+    }
+}
+
+/// What styling needs to know about the document as a whole.
+struct StyleContext<'a> {
+    stylesheets: Vec<ActiveStylesheet<'a>>,
+    index: RuleIndex<'a>,
+    /// Which elements changes to the document affect.
+    invalidation: InvalidationMap,
+    /// The selectors of the rules added, removed or changed since the previous style pass.
+    changed_selectors: Vec<&'a Selector>,
+    sharing_hits: AtomicUsize,
+    sharing_misses: AtomicUsize,
+    /// Elements styled through sharing or the cascade, rather than kept from the previous pass.
+    restyled: AtomicUsize,
+}
+
+impl<'a> StyleContext<'a> {
+    fn new(stylesheets: Vec<ActiveStylesheet<'a>>, invalidation: InvalidationMap,
+           changed_selectors: Vec<&'a Selector>) -> StyleContext<'a> {
+        let index = RuleIndex::new(stylesheets.iter().enumerate()
+            .flat_map(|(sheet, stylesheet)| stylesheet.rules.iter().enumerate()
+                .map(move |(i, &(rule, _))| ((sheet, i), rule))));
+        StyleContext {
+            stylesheets,
+            index,
+            invalidation,
+            changed_selectors,
+            sharing_hits: AtomicUsize::new(0),
+            sharing_misses: AtomicUsize::new(0),
+            restyled: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// State threaded through the style tree traversal. With the `parallel` feature, each
+/// thread works on its own copy.
+#[derive(Clone, Default)]
+struct TraversalState<'a> {
+    /// The ancestors of the element being styled.
+    filter: AncestorFilter,
+    sharing: StyleSharingCache<'a>,
+    /// The computed font size of the root element, once it has been styled.
+    root_font_size: Option<f32>,
+}
+
+/// The styles computed for a node, its pseudo-elements and its descendants. Unlike a
+/// `StyledNode`, it doesn't borrow the document, so it can be kept across changes to it.
+///
+/// The cascade works on the map of computed values rather than on `ComputedStyle`:
+/// the map also holds the properties layout doesn't read, custom properties for `var()`,
+/// and the counter and `content` properties of generated content, and children inherit
+/// from it. `get_computed_style` reports it too.
+#[derive(Clone)]
+struct StyleData {
+    values: Arc<PropertyMap>,
+    style: Arc<ComputedStyle>,
+    /// The styles of `::before` and `::after`, whether or not they generate boxes.
+    before: Option<Box<StyleData>>,
+    after: Option<Box<StyleData>>,
+    /// The style of `::marker`, for list items only.
+    marker: Option<Box<StyleData>>,
+    children: Vec<StyleData>,
+}
+
+impl StyleData {
+    fn new(values: PropertyMap, parent: Option<&StyleData>) -> StyleData {
+        let style = ComputedStyle::from_values(&values, parent.map(|p| &*p.style));
+        StyleData::shared(Arc::new(values), Arc::new(style))
+    }
+
+    fn shared(values: Arc<PropertyMap>, style: Arc<ComputedStyle>) -> StyleData {
+        StyleData { values, style, before: None, after: None, marker: None, children: Vec::new() }
+    }
+
+    /// The styles of the node itself and its pseudo-elements, without its descendants.
+    fn without_children(&self) -> StyleData {
+        StyleData {
+            values: self.values.clone(),
+            style: self.style.clone(),
+            before: self.before.clone(),
+            after: self.after.clone(),
+            marker: self.marker.clone(),
+            children: Vec::new(),
+        }
+    }
+}
+
+/// How much of a node's previous styles can be kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Restyle {
+    /// Keep them, unless a change to the node affects them.
+    IfChanged,
+    /// Restyle the element, and its children if its style changed.
+    Element,
+    /// Restyle the element and all its descendants.
+    Subtree,
+}
+
+/// Apply stylesheets to an entire DOM tree, returning a StyledNode tree.
+///
+/// Stylesheets are given in source order; each one's `origin` decides where its
+/// declarations fall in the cascade. The user-agent stylesheet always comes first.
+/// Media queries are evaluated against the default device.
+pub fn style_tree<'a>(root: &'a Node, stylesheets: &[&'a Stylesheet]) -> StyledNode<'a> {
+    style_tree_for_device(root, stylesheets, &Device::default())
+}
+
+/// Like `style_tree`, evaluating media queries against `device`.
+pub fn style_tree_for_device<'a>(root: &'a Node, stylesheets: &[&'a Stylesheet], device: &Device) -> StyledNode<'a> {
+    style_tree_with_stats(root, stylesheets, device).0
+}
+
+/// Like `style_tree_for_device`, also reporting how often elements shared a style.
+///
+/// Elements are styled first, in parallel with the `parallel` feature. Counters and
+/// generated content depend on document order, so they follow in a second, sequential pass.
+pub fn style_tree_with_stats<'a>(root: &'a Node, stylesheets: &[&'a Stylesheet],
+                                 device: &Device) -> (StyledNode<'a>, StyleSharingStats) {
+    let stylesheets = with_user_agent_stylesheet(stylesheets);
+    let context = StyleContext::new(active_stylesheets(&stylesheets, device), InvalidationMap::default(), Vec::new());
+    let data = style_node(&ElementRef::root(root), None, None, Restyle::Subtree, &context, &mut TraversalState::default());
+    let styled = build_styled_tree(root, &data, &mut Counters::default());
+    let stats = StyleSharingStats {
+        hits: context.sharing_hits.into_inner(),
+        misses: context.sharing_misses.into_inner(),
+    };
+    (styled, stats)
+}
+
+fn with_user_agent_stylesheet<'s>(stylesheets: &[&'s Stylesheet]) -> Vec<&'s Stylesheet> {
+    std::iter::once(&*USER_AGENT_STYLESHEET)
+        .chain(stylesheets.iter().copied())
+        .collect()
+}
+
+/// Styles a document repeatedly, keeping the styles of each pass so that the next one only
+/// restyles the elements affected by changes to the document or its stylesheets.
+///
+/// Changes to the document are found through the dirty bits that the mutation methods of
+/// `Node` set. Changed stylesheets are compared rule by rule with those of the previous pass.
+#[derive(Default)]
+pub struct Restyler {
+    previous: Option<StyleData>,
+    /// The style rules that applied in the previous pass, with their origin and layer rank.
+    rules: Vec<(Origin, usize, Rule)>,
+    restyled: usize,
+}
+
+impl Restyler {
+    pub fn new() -> Restyler {
+        Restyler::default()
+    }
+
+    /// Style the document at `root`, which must be the same document each time, and clear
+    /// the changes recorded on its nodes.
+    pub fn style<'a>(&mut self, root: &'a mut Node, stylesheets: &[&Stylesheet], device: &Device) -> StyledNode<'a> {
+        let rules = {
+            let root: &Node = root;
+            let stylesheets = with_user_agent_stylesheet(stylesheets);
+            let stylesheets = active_stylesheets(&stylesheets, device);
+            let rules: Vec<(Origin, usize, &Rule)> = stylesheets.iter()
+                .flat_map(|s| s.rules.iter().map(move |&(rule, layer)| (s.origin, layer, rule)))
+                .collect();
+
+            // Rules between the longest common prefix and suffix of the old and new rule
+            // lists were added, removed, changed or moved.
+            let same = |(old, new): (&(Origin, usize, Rule), &(Origin, usize, &Rule))|
+                old.0 == new.0 && old.1 == new.1 && old.2 == *new.2;
+            let prefix = self.rules.iter().zip(&rules).take_while(|&pair| same(pair)).count();
+            let suffix = self.rules[prefix..].iter().rev().zip(rules[prefix..].iter().rev())
+                .take_while(|&pair| same(pair)).count();
+            let changed_rules = self.rules[prefix..self.rules.len() - suffix].iter().map(|(_, _, rule)| rule)
+                .chain(rules[prefix..rules.len() - suffix].iter().map(|&(_, _, rule)| rule));
+            let changed_selectors: Vec<&Selector> = changed_rules.flat_map(|rule| &rule.selectors).collect();
+
+            let invalidation = InvalidationMap::new(rules.iter().flat_map(|(_, _, rule)| &rule.selectors)
+                .chain(changed_selectors.iter().copied()));
+            let previous = self.previous.as_ref().filter(|_| !affects_document(root, &invalidation));
+            let context = StyleContext::new(stylesheets, invalidation, changed_selectors);
+            let data = style_node(&ElementRef::root(root), None, previous, Restyle::IfChanged,
+                                  &context, &mut TraversalState::default());
+            self.previous = Some(data);
+            self.restyled = context.restyled.into_inner();
+            rules.into_iter().map(|(origin, layer, rule)| (origin, layer, rule.clone())).collect()
+        };
+        self.rules = rules;
+        root.clear_dirty();
+        let root: &'a Node = root;
+        build_styled_tree(root, self.previous.as_ref().unwrap(), &mut Counters::default())
+    }
+
+    /// How many elements the last pass styled through sharing or the cascade, rather
+    /// than keeping their previous styles.
+    pub fn restyled_elements(&self) -> usize {
+        self.restyled
+    }
+}
+
+/// What the cascade made of a node, as returned by `get_computed_style`. It is what the
+/// "Styles" pane of a browser's developer tools shows.
+#[derive(Debug)]
+pub struct ComputedStyleInfo {
+    /// The final computed value of every property that has one.
+    pub computed_values: PropertyMap,
+    /// The rules that match the node and its `style` attribute, in cascade order: the
+    /// normal declarations of later rules win over those of earlier ones.
+    pub matched_rules: Vec<RuleMatch>,
+}
+
+/// A rule that matches an element.
+#[derive(Debug)]
+pub struct RuleMatch {
+    pub rule: Rule,
+    /// The most specific of the rule's selectors that match, or `None` for the `style` attribute.
+    pub selector: Option<Selector>,
+    pub specificity: Specificity,
+    pub origin: Origin,
+    pub source: RuleSource,
+    /// For each declaration of the rule, whether declarations with a higher priority
+    /// decide the value of every property it sets.
+    pub overridden: Vec<bool>,
+}
+
+/// Where a matched rule comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleSource {
+    UserAgent(SourceLocation),
+    /// The stylesheet at this index among those given, and the URLs of the `@import`
+    /// rules the rule was reached through from it, outermost first. The location of a
+    /// rule from an imported stylesheet is within the imported file.
+    Stylesheet(usize, Vec<String>, SourceLocation),
+    StyleAttribute,
+}
+
+/// The computed values of `node`, which is within the document at `root`, and the rules
+/// that produced them. Returns `None` if `node` isn't part of the document.
+pub fn get_computed_style(root: &Node, node: &Node, stylesheets: &[&Stylesheet],
+                          device: &Device) -> Option<ComputedStyleInfo> {
+    let path = path_to(root, node)?;
+    let stylesheets = with_user_agent_stylesheet(stylesheets);
+    let context = StyleContext::new(active_stylesheets(&stylesheets, device), InvalidationMap::default(), Vec::new());
+    Some(inspect(&ElementRef::root(root), &path, None, &context, &mut TraversalState::default()))
+}
+
+/// The indices of the children leading from `root` to `node`.
+fn path_to(root: &Node, node: &Node) -> Option<Vec<usize>> {
+    if std::ptr::eq(root, node) {
+        return Some(Vec::new());
+    }
+    root.children.iter().enumerate().find_map(|(i, child)| {
+        let mut path = path_to(child, node)?;
+        path.insert(0, i);
+        Some(path)
+    })
+}
+
+/// Style the ancestors along `path`, then inspect the node at its end.
+fn inspect(elem: &ElementRef, path: &[usize], parent: Option<&StyleData>, context: &StyleContext,
+           state: &mut TraversalState) -> ComputedStyleInfo {
+    if let Some((&i, path)) = path.split_first() {
+        let styled = compute_style(elem, parent, context, state);
+        state.root_font_size.get_or_insert(styled.style.font.size);
+        state.filter.push(elem.data());
+        return inspect(&elem.child(i), path, Some(&styled), context, state);
+    }
+
+    let parent_values = parent.map(|p| &*p.values);
+    let Element(ref data) = elem.node.node_type else {
+        return ComputedStyleInfo {
+            computed_values: computed_values(HashMap::new(), parent_values, state.root_font_size),
+            matched_rules: Vec::new(),
+        };
+    };
+    let rules = matching_rules(elem, None, context, &state.filter);
+    let inline_style = inline_style(data);
+    let declarations = sorted_declarations(&rules, inline_style.as_ref());
+    let (specified, applied) = cascade(&declarations);
+    let overridden = |rule: &Rule| -> Vec<bool> {
+        rule.declarations.iter()
+            .map(|declaration| !declarations.iter().zip(&applied)
+                .any(|(&(_, _, d), &applied)| applied && std::ptr::eq(d, declaration)))
+            .collect()
+    };
+
+    let mut matched_rules: Vec<(CascadePriority, RuleMatch)> = rules.iter().map(|matched| {
+        let location = matched.rule.location;
+        let source = match matched.source_order.0 {
+            0 => RuleSource::UserAgent(location),
+            sheet => {
+                let imports = context.stylesheets[sheet].stylesheet.import_chain(matched.rule).unwrap_or_default();
+                RuleSource::Stylesheet(sheet - 1, imports, location)
+            }
+        };
+        let level = CascadeLevel::new(matched.origin, false);
+        ((level, false, matched.layer, matched.specificity, matched.source_order), RuleMatch {
+            rule: matched.rule.clone(),
+            selector: Some(matched.selector.clone()),
+            specificity: matched.specificity,
+            origin: matched.origin,
+            source,
+            overridden: overridden(matched.rule),
+        })
+    }).collect();
+    if let Some(ref rule) = inline_style {
+        let level = CascadeLevel::new(Origin::Author, false);
+        matched_rules.push(((level, true, 0, (0, 0, 0), (0, 0)), RuleMatch {
+            rule: rule.clone(),
+            selector: None,
+            specificity: (0, 0, 0),
+            origin: Origin::Author,
+            source: RuleSource::StyleAttribute,
+            overridden: overridden(rule),
+        }));
+    }
+    matched_rules.sort_by_key(|&(priority, _)| priority);
+
+    ComputedStyleInfo {
+        computed_values: computed_values(specified, parent_values, state.root_font_size),
+        matched_rules: matched_rules.into_iter().map(|(_, rule)| rule).collect(),
+    }
+}
+
+/// Which elements the changes to an element's attributes may affect.
+fn attribute_changes(node: &Node, invalidation: &InvalidationMap) -> HashSet<Reach> {
+    match (&node.dirty.old_attributes, &node.node_type) {
+        (Some(old), Element(data)) => invalidation.attribute_changes(old, &data.attributes),
+        _ => HashSet::new(),
+    }
+}
+
+/// Whether some change anywhere in the document may affect any element, as with `:has()`.
+fn affects_document(node: &Node, invalidation: &InvalidationMap) -> bool {
+    attribute_changes(node, invalidation).contains(&Reach::Document) ||
+        (node.dirty.old_children.is_some() && invalidation.structural_changes().contains(&Reach::Document)) ||
+        node.children.iter().any(|child| affects_document(child, invalidation))
+}
+
+/// Style a node and its descendants, keeping what `restyle` allows of the `previous` styles.
+fn style_node<'a>(elem: &ElementRef<'a, '_>, parent: Option<&StyleData>, previous: Option<&StyleData>,
+                  restyle: Restyle, context: &StyleContext<'a>, state: &mut TraversalState<'a>) -> StyleData {
+    let restyle = if previous.is_some() { restyle } else { Restyle::Subtree };
+    let data = match elem.node.node_type {
+        Element(ref data) => data,
+        // Text inherits everything it can from its parent element.
+        Text(_) | Comment(_) => return match previous {
+            Some(previous) if restyle == Restyle::IfChanged => previous.without_children(),
+            _ => StyleData::new(computed_values(HashMap::new(), parent.map(|p| &*p.values), state.root_font_size), parent),
+        },
+    };
+
+    let dirty = &elem.node.dirty;
+    let own_changes = attribute_changes(elem.node, &context.invalidation);
+    let structural_changes = match dirty.old_children {
+        Some(_) => context.invalidation.structural_changes(),
+        None => &HashSet::new(),
+    };
+    let restyle = match restyle {
+        Restyle::IfChanged if own_changes.contains(&Reach::Element) ||
+            structural_changes.contains(&Reach::Element) ||
+            context.changed_selectors.iter().any(|selector| matches(elem, selector)) => Restyle::Element,
+        restyle => restyle,
+    };
+
+    let mut styled = match previous {
+        Some(previous) if restyle == Restyle::IfChanged => previous.without_children(),
+        _ => {
+            context.restyled.fetch_add(1, Ordering::Relaxed);
+            let mut styled = style_element(elem, data, parent, context, state);
+            styled.before = Some(style_pseudo_element(elem, PseudoElement::Before, &styled, context, state));
+            styled.after = Some(style_pseudo_element(elem, PseudoElement::After, &styled, context, state));
+            if styled.style.display.is_list_item() {
+                styled.marker = Some(style_pseudo_element(elem, PseudoElement::Marker, &styled, context, state));
+            }
+            styled
+        }
+    };
+    let root_font_size = *state.root_font_size.get_or_insert(styled.style.font.size);
+
+    // Children inherit from the element, and `rem` lengths everywhere depend on the root.
+    let changed = |previous: &StyleData| !Arc::ptr_eq(&previous.values, &styled.values) &&
+                                         previous.values != styled.values;
+    let root_font_size_changed = elem.parent.is_none() &&
+        previous.is_some_and(|previous| previous.style.font.size != root_font_size);
+    let children_restyle = if restyle == Restyle::Subtree || root_font_size_changed ||
+            own_changes.contains(&Reach::Descendants) || structural_changes.contains(&Reach::Descendants) {
+        Restyle::Subtree
+    } else if previous.is_none_or(changed) {
+        Restyle::Element
+    } else {
+        Restyle::IfChanged
+    };
+
+    // Changes to a child, or to the children of a child as `.a:empty + p` sees, may
+    // affect its siblings.
+    let sibling_changes: Vec<HashSet<Reach>> = elem.node.children.iter()
+        .map(|child| {
+            let mut reaches = attribute_changes(child, &context.invalidation);
+            if child.dirty.old_children.is_some() {
+                reaches.extend(context.invalidation.structural_changes());
+            }
+            reaches
+        })
+        .collect();
+    let all_siblings = sibling_changes.iter().any(|reaches| reaches.contains(&Reach::Siblings));
+    let mut later_siblings = false;
+    let children: Vec<(Option<&StyleData>, Restyle)> = sibling_changes.iter().enumerate().map(|(i, reaches)| {
+        let old_index = match dirty.old_children {
+            Some(ref old_children) => old_children[i],
+            None => Some(i),
+        };
+        let previous = previous.zip(old_index).and_then(|(previous, i)| previous.children.get(i));
+        let restyle = if all_siblings || later_siblings { Restyle::Subtree } else { children_restyle };
+        later_siblings |= reaches.contains(&Reach::LaterSiblings);
+        (previous, restyle)
+    }).collect();
+
+    state.filter.push(data);
+    styled.children = style_children(elem, &styled, &children, context, state);
+    state.filter.pop(data);
+    styled
+}
+
+#[cfg(not(feature = "parallel"))]
+fn style_children<'a>(elem: &ElementRef<'a, '_>, styled: &StyleData, children: &[(Option<&StyleData>, Restyle)],
+                      context: &StyleContext<'a>, state: &mut TraversalState<'a>) -> Vec<StyleData> {
+    children.iter().enumerate()
+        .map(|(i, &(previous, restyle))| style_node(&elem.child(i), Some(styled), previous, restyle, context, state))
+        .collect()
+}
+
+/// Each rayon job starts from a copy of the state, which holds the same ancestors.
+#[cfg(feature = "parallel")]
+fn style_children<'a>(elem: &ElementRef<'a, '_>, styled: &StyleData, children: &[(Option<&StyleData>, Restyle)],
+                      context: &StyleContext<'a>, state: &mut TraversalState<'a>) -> Vec<StyleData> {
+    use rayon::prelude::*;
+    children.par_iter().enumerate()
+        .map_with(state.clone(), |state, (i, &(previous, restyle))| {
+            style_node(&elem.child(i), Some(styled), previous, restyle, context, state)
+        })
+        .collect()
+}
+
+/// Style an element, sharing the style of a recently styled element where possible.
+fn style_element<'a>(elem: &ElementRef<'a, '_>, data: &'a ElementData, parent: Option<&StyleData>,
+                     context: &StyleContext<'a>, state: &mut TraversalState<'a>) -> StyleData {
+    let Some(parent) = parent.filter(|_| sharing::is_shareable(data)) else {
+        return compute_style(elem, parent, context, state);
+    };
+    let revalidation = revalidation_results(elem, context, &state.filter);
+    match state.sharing.lookup(data, &parent.values, &revalidation) {
+        Some((values, style)) => {
+            context.sharing_hits.fetch_add(1, Ordering::Relaxed);
+            StyleData::shared(values, style)
+        }
+        None => {
+            context.sharing_misses.fetch_add(1, Ordering::Relaxed);
+            let styled = compute_style(elem, Some(parent), context, state);
+            state.sharing.insert(data, parent.values.clone(), revalidation, styled.values.clone(), styled.style.clone());
+            styled
+        }
+    }
+}
+
+/// Style an element through selector matching and the cascade.
+fn compute_style(elem: &ElementRef, parent: Option<&StyleData>, context: &StyleContext,
+                 state: &TraversalState) -> StyleData {
+    let specified = specified_values(elem, None, context, &state.filter);
+    let values = computed_values(specified, parent.map(|p| &*p.values), state.root_font_size);
+    StyleData::new(values, parent)
+}
+
+/// Which of the selectors that could match `elem` and depend on more than its
+/// attributes and ancestors it actually matches.
+fn revalidation_results(elem: &ElementRef, context: &StyleContext, filter: &AncestorFilter) -> Revalidation {
+    let mut results: Revalidation = context.index.candidates(elem.data())
+        .filter(|s| s.needs_revalidation && s.selector.pseudo_element().is_none())
+        .map(|s| (s.position, filter.may_match(s) && matches(elem, s.selector)))
+        .collect();
+    results.sort();
+    results
+}
+
+/// Pseudo-elements inherit from their originating element. Whether they generate a
+/// box is only known once their content is generated.
+fn style_pseudo_element(elem: &ElementRef, pseudo: PseudoElement, parent: &StyleData,
+                        context: &StyleContext, state: &TraversalState) -> Box<StyleData> {
+    let specified = specified_values(elem, Some(pseudo), context, &state.filter);
+    let values = computed_values(specified, Some(&parent.values), state.root_font_size);
+    Box::new(StyleData::new(values, Some(parent)))
+}
+
+/// Build the styled tree of `node` from its styles, applying the counter properties in
+/// document order and generating the content of pseudo-elements. Those that don't
+/// generate any are dropped.
+fn build_styled_tree<'a>(node: &'a Node, data: &StyleData, counters: &mut Counters) -> StyledNode<'a> {
+    let mut styled = StyledNode::with_shared_style(node, data.values.clone(), data.style.clone());
+    let Element(ref elem) = node.node_type else { return styled };
+    counters.update(&data.values);
+    if data.style.display.is_list_item() {
+        counters.increment_list_item(&data.values);
+    }
+    let mark = counters.enter_children();
+    styled.marker = data.marker.as_deref()
+        .and_then(|marker| build_pseudo_element(node, PseudoElement::Marker, marker, elem, counters));
+    styled.before = data.before.as_deref()
+        .and_then(|before| build_pseudo_element(node, PseudoElement::Before, before, elem, counters));
+    styled.children = node.children.iter().zip(&data.children)
+        .map(|(child, data)| build_styled_tree(child, data, counters))
+        .collect();
+    styled.after = data.after.as_deref()
+        .and_then(|after| build_pseudo_element(node, PseudoElement::After, after, elem, counters));
+    counters.leave_children(mark);
+    styled
+}
+
+fn build_pseudo_element<'a>(node: &'a Node, pseudo: PseudoElement, data: &StyleData, elem: &ElementData,
+                            counters: &mut Counters) -> Option<Box<StyledNode<'a>>> {
+    let content = |counters: &Counters| match pseudo {
+        PseudoElement::Marker => marker_content(&data.values, elem, counters),
+        _ => generate_content(&data.values, elem, counters),
+    };
+    // The counter properties of a pseudo-element that generates no box don't apply.
+    content(counters)?;
+    counters.update(&data.values);
+    let mut styled = StyledNode::with_shared_style(node, data.values.clone(), data.style.clone());
+    styled.pseudo_element = Some(pseudo);
+    styled.generated_content = content(counters);
+    Some(Box::new(styled))
+}
+
 #[test]
 fn display_block() {
     let root = crate::parser::parse("<div>Hello World!</div>".to_string());
     let stylesheet = crate::css::parse("div { display: block; }".to_string());
-    let styled_node = style_tree(&root, &stylesheet);
-    assert_eq!(Display::Block, styled_node.display());
+    let styled_node = style_tree(&root, &[&stylesheet]);
+    assert_eq!(Display::BLOCK, styled_node.display());
 }
 
 #[test]
 fn display_inline() {
-    let root = crate::parser::parse("<div>Hello World!</div>".to_string());
-    let stylesheet = crate::css::parse("div { color: red; }".to_string());
-    let styled_node = style_tree(&root, &stylesheet);
-    assert_eq!(Display::Inline, styled_node.display());
+    let root = crate::parser::parse("<span>Hello World!</span>".to_string());
+    let stylesheet = crate::css::parse("span { color: red; }".to_string());
+    let styled_node = style_tree(&root, &[&stylesheet]);
+    assert_eq!(Display::INLINE, styled_node.display());
 }
 
 #[test]
 fn display_none() {
     let root = crate::parser::parse("<div>Hello World!</div>".to_string());
     let stylesheet = crate::css::parse("div { display: none; }".to_string());
-    let styled_node = style_tree(&root, &stylesheet);
+    let styled_node = style_tree(&root, &[&stylesheet]);
     assert_eq!(Display::None, styled_node.display());
 }
+
+/// Collect the ids (or tag names) of all elements in the styled tree that have `opacity: 0`.
+#[cfg(test)]
+fn matched_elements(styled_node: &StyledNode) -> Vec<String> {
+    let mut names = Vec::new();
+    if let (Element(ref elem), Some(Value::Number(0.0))) = (&styled_node.node().node_type, styled_node.value("opacity")) {
+        names.push(elem.id().cloned().unwrap_or_else(|| elem.tag_name.clone()));
+    }
+    for child in &styled_node.children {
+        names.extend(matched_elements(child));
+    }
+    names
+}
+
+#[test]
+fn structural_pseudo_classes() {
+    let root = crate::parser::parse("<ul><li id='a'></li><li id='b'>x</li><li id='c'></li><li id='d'></li></ul>".to_string());
+    let cases = [
+        ("ul:root", vec!["ul"]),
+        ("li:first-child", vec!["a"]),
+        ("li:last-child", vec!["d"]),
+        ("li:empty", vec!["a", "c", "d"]),
+        ("li:nth-child(odd)", vec!["a", "c"]),
+        ("li:nth-child(-n+2)", vec!["a", "b"]),
+        ("li:nth-last-child(2n)", vec!["a", "c"]),
+        ("li:nth-child(2 of :empty)", vec!["c"]),
+        ("li:nth-of-type(3)", vec!["c"]),
+        ("li:only-child", vec![]),
+    ];
+    for (selector, expected) in cases {
+        let stylesheet = crate::css::parse(format!("{selector} {{ opacity: 0; }}"));
+        assert_eq!(expected, matched_elements(&style_tree(&root, &[&stylesheet])), "{selector}");
+    }
+}
+
+#[test]
+fn logical_pseudo_classes_and_combinators() {
+    let root = crate::parser::parse(
+        "<div id='r'><p id='a' class='x'></p><p id='b'><em id='e'></em></p><span id='c'></span></div>".to_string());
+    let cases = [
+        ("p:not(.x)", vec!["b"]),
+        (":is(p, span).x", vec!["a"]),
+        (":where(#r) > span", vec!["c"]),
+        ("div em", vec!["e"]),
+        ("#a + p", vec!["b"]),
+        ("#a ~ span", vec!["c"]),
+        ("p:has(em)", vec!["b"]),
+        ("div:has(> em)", vec![]),
+        ("p:has(+ span)", vec!["b"]),
+        ("p:has(~ p em)", vec!["a"]),
+    ];
+    for (selector, expected) in cases {
+        let stylesheet = crate::css::parse(format!("{selector} {{ opacity: 0; }}"));
+        assert_eq!(expected, matched_elements(&style_tree(&root, &[&stylesheet])), "{selector}");
+    }
+}
+
+#[test]
+fn generated_content() {
+    let root = crate::parser::parse(
+        "<ol><li title='one'></li><li title='two'></li></ol>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        ol { counter-reset: item; }
+        li::before { counter-increment: item; content: counter(item, upper-roman) ". "; }
+        li::after { content: "(" attr(title) ")"; }
+        ol::after { content: none; }
+    "#.to_string());
+    let styled = style_tree(&root, &[&stylesheet]);
+    assert!(styled.after.is_none());
+    let generated: Vec<_> = styled.children.iter()
+        .map(|li| (li.before.as_ref().unwrap().text().unwrap(), li.after.as_ref().unwrap().text().unwrap()))
+        .collect();
+    assert_eq!(vec![("I. ", "(one)"), ("II. ", "(two)")], generated);
+    assert_eq!(Some(PseudoElement::Before), styled.children[0].before.as_ref().unwrap().pseudo_element());
+
+    // A pseudo-element without content generates no box, so its counter properties don't apply.
+    let root = crate::parser::parse("<div><p></p><p></p></div>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        div { counter-reset: c; }
+        p::before { counter-increment: c; }
+        div::after { content: counter(c); }
+    "#.to_string());
+    let styled = style_tree(&root, &[&stylesheet]);
+    assert!(styled.children[0].before.is_none());
+    assert_eq!(Some("0"), styled.after.as_ref().unwrap().text());
+}
+
+#[test]
+fn nested_counters() {
+    let root = crate::parser::parse(
+        "<ol><li><ol><li></li><li></li></ol></li><li></li></ol>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        ol { counter-reset: item; }
+        li { counter-increment: item; }
+        li::before { content: counters(item, "."); }
+    "#.to_string());
+    let styled = style_tree(&root, &[&stylesheet]);
+    let label = |node: &StyledNode| node.before.as_ref().unwrap().text().unwrap().to_string();
+    let outer = &styled.children;
+    let inner = &outer[0].children[0].children;
+    assert_eq!(vec!["1", "1.1", "1.2", "2"],
+               vec![label(&outer[0]), label(&inner[0]), label(&inner[1]), label(&outer[1])]);
+}
+
+#[test]
+fn cascade_origins_and_importance() {
+    use crate::css::parse_with_origin;
+    let root = crate::parser::parse("<p id='p'></p>".to_string());
+    let user_agent = parse_with_origin("p { display: block; color: red !important; }".to_string(), Origin::UserAgent);
+    let user = parse_with_origin("p { display: none !important; background: red; }".to_string(), Origin::User);
+    let author = crate::css::parse(r#"
+        #p { display: inline; color: blue !important; background: blue; }
+        p { background: #008000; }
+        p { background: #ffff00; }
+    "#.to_string());
+    let styled = style_tree(&root, &[&user_agent, &user, &author]);
+    assert_eq!(Display::None, styled.display());
+    assert_eq!(Some(Value::ColorValue(crate::css::Color { r: 255, g: 0, b: 0, a: 255 })), styled.value("color"));
+    assert_eq!(Some(Value::ColorValue(crate::css::Color { r: 0, g: 0, b: 255, a: 255 })), styled.value("background-color"));
+
+    // Equal specificity falls back to source order, across stylesheets too.
+    let first = crate::css::parse("p { background: #008000; }".to_string());
+    let second = crate::css::parse("p { background: #ffff00; }".to_string());
+    let styled = style_tree(&root, &[&first, &second]);
+    assert_eq!(Some(Value::ColorValue(crate::css::Color { r: 255, g: 255, b: 0, a: 255 })), styled.value("background-color"));
+
+    // Invalid declarations are dropped, so they don't override earlier valid ones.
+    let stylesheet = crate::css::parse("
+        p { width: 10px; margin-left: 7px; opacity: 0.5; }
+        p { width: red; margin: auto auto red; opacity: 1px; }
+    ".to_string());
+    assert!(stylesheet.rules[1].as_style().unwrap().declarations.is_empty());
+    let styled = style_tree(&root, &[&stylesheet]);
+    let px = |px| Some(Value::Length(px, crate::css::Unit::Px));
+    assert_eq!((px(10.0), px(7.0)), (styled.value("width"), styled.value("margin-left")));
+    assert_eq!(Some(Value::Number(0.5)), styled.value("opacity"));
+}
+
+#[test]
+fn inherited_and_initial_values() {
+    use crate::css::Color;
+    let root = crate::parser::parse(
+        "<body><div id='a'><p id='b'>text</p></div><div id='c'></div><div id='d'></div></body>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        body { color: #ff0000; background: #00ff00; }
+        #a { background: inherit; }
+        #b { color: initial; }
+        #c { color: unset; background: unset; }
+        #d { color: revert; }
+    "#.to_string());
+    let user_agent = crate::css::parse_with_origin("#d { color: #0000ff; }".to_string(), Origin::UserAgent);
+    let styled = style_tree(&root, &[&user_agent, &stylesheet]);
+
+    let red = Some(Value::ColorValue(Color { r: 255, g: 0, b: 0, a: 255 }));
+    let green = Some(Value::ColorValue(Color { r: 0, g: 255, b: 0, a: 255 }));
+    let black = Some(Value::ColorValue(Color { r: 0, g: 0, b: 0, a: 255 }));
+    let blue = Some(Value::ColorValue(Color { r: 0, g: 0, b: 255, a: 255 }));
+    let transparent = Some(Value::ColorValue(Color { r: 0, g: 0, b: 0, a: 0 }));
+
+    let (a, c, d) = (&styled.children[0], &styled.children[1], &styled.children[2]);
+    let b = &a.children[0];
+    assert_eq!((red.clone(), green), (a.value("color"), a.value("background-color")));
+    assert_eq!((black.clone(), None), (b.value("color"), b.value("background-color")));
+    assert_eq!(black, b.children[0].value("color"));
+    assert_eq!((red, transparent), (c.value("color"), c.value("background-color")));
+    assert_eq!(blue, d.value("color"));
+}
+
+#[test]
+fn user_agent_stylesheet() {
+    let root = crate::parser::parse(
+        "<html><head><style>p {}</style></head><body><h1>Title</h1><p>A <em>b</em></p><ul><li>c</li></ul></body></html>".to_string());
+    let styled = style_tree(&root, &[]);
+    let (head, body) = (&styled.children[0], &styled.children[1]);
+    assert_eq!((Display::BLOCK, Display::None, Display::BLOCK), (styled.display(), head.display(), body.display()));
+    let displays: Vec<_> = body.children.iter().map(StyledNode::display).collect();
+    assert_eq!(vec![Display::BLOCK, Display::BLOCK, Display::BLOCK], displays);
+    assert_eq!(Display::INLINE, body.children[1].children[1].display());
+    let li = &body.children[2].children[0];
+    assert_eq!(Display::LIST_ITEM, li.display());
+    assert_eq!(Some("\u{2022} "), li.marker.as_ref().unwrap().text());
+    assert_eq!(Some(Value::Length(32.0, crate::css::Unit::Px)), body.children[0].value("font-size"));
+    assert_eq!(Some(Value::Length(8.0, crate::css::Unit::Px)), body.value("margin-left"));
+
+    // Author styles override the defaults.
+    let author = crate::css::parse("h1 { display: inline; }".to_string());
+    let styled = style_tree(&root, &[&author]);
+    assert_eq!(Display::INLINE, styled.children[1].children[0].display());
+}
+
+#[test]
+fn font_relative_lengths() {
+    use crate::css::Unit::Px;
+    let root = crate::parser::parse("<html><div><p></p></div></html>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        html { font-size: 10px; }
+        div { font-size: 2em; margin: 1em 1rem 12pt 1in; }
+        p { font-size: 150%; line-height: 2ex; width: 50%; }
+    "#.to_string());
+    let styled = style_tree(&root, &[&stylesheet]);
+    let div = &styled.children[0];
+    let p = &div.children[0];
+    assert_eq!(Some(Value::Length(20.0, Px)), div.value("font-size"));
+    let margins: Vec<_> = ["margin-top", "margin-right", "margin-bottom", "margin-left"].iter()
+        .map(|m| div.value(m).unwrap())
+        .collect();
+    assert_eq!(vec![Value::Length(20.0, Px), Value::Length(10.0, Px), Value::Length(16.0, Px), Value::Length(96.0, Px)], margins);
+    assert_eq!(Some(Value::Length(30.0, Px)), p.value("font-size"));
+    // The default font's "x" is 1120 of its 2048 units per em tall.
+    assert_eq!(Some(Value::Length(2.0 * 30.0 * 1120.0 / 2048.0, Px)), p.value("line-height"));
+    assert_eq!(Some(Value::Length(50.0, Unit::Percent)), p.value("width"));
+}
+
+#[test]
+fn math_functions_in_computed_values() {
+    use crate::css::Unit::{Percent, Px};
+    let root = crate::parser::parse("<div></div>".to_string());
+    let stylesheet = crate::css::parse(
+        "div { font-size: calc(10px + 50%); margin-left: max(1em, 2px); width: calc(100% - 1em); }".to_string());
+    let styled = style_tree(&root, &[&stylesheet]);
+    assert_eq!(Some(Value::Length(18.0, Px)), styled.value("font-size"));
+    assert_eq!(Some(Value::Length(18.0, Px)), styled.value("margin-left"));
+    assert_eq!(Some(Value::Calc(Box::new(Calc::Sum(vec![
+        Calc::Length(100.0, Percent),
+        Calc::Negate(Box::new(Calc::Length(18.0, Px))),
+    ])))), styled.value("width"));
+
+    // Calculations without lengths are numbers.
+    let stylesheet = crate::css::parse("
+        div { line-height: calc(1 + 0.5); opacity: calc(0.5); flex-grow: max(1, 2 * 2); flex-shrink: clamp(1, 5, calc(6 / 2)); }
+    ".to_string());
+    let styled = style_tree(&root, &[&stylesheet]);
+    assert_eq!(Some(Value::Number(1.5)), styled.value("line-height"));
+    assert_eq!(Some(Value::Number(0.5)), styled.value("opacity"));
+    assert_eq!(Some(Value::Number(4.0)), styled.value("flex-grow"));
+    assert_eq!(Some(Value::Number(3.0)), styled.value("flex-shrink"));
+}
+
+#[test]
+fn custom_properties() {
+    use crate::css::{Color, Unit::Px};
+    let root = crate::parser::parse("<div><p><span><em></em></span></p></div>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        div { --gap: 4px; --color: #ff0000; --loop: var(--loop); margin: var(--gap) 0; }
+        p { --double: calc(var(--gap) * 2); --self: var(--self, 1px); color: var(--color); padding: var(--missing, 1px var(--double)); }
+        span { color: var(--loop); width: var(--nothing); --bad: #ggg; --unit: 10qq; background-color: var(--bad); height: var(--unit); }
+        em { --word: foo; --auto: auto; color: rgb(var(--word)); width: calc(var(--auto) + 1px); }
+    "#.to_string());
+    let styled = style_tree(&root, &[&stylesheet]);
+    let (div, p) = (&styled, &styled.children[0]);
+    let span = &p.children[0];
+    let red = Some(Value::ColorValue(Color { r: 255, g: 0, b: 0, a: 255 }));
+
+    assert_eq!(Some(Value::Length(4.0, Px)), div.value("margin-top"));
+    assert_eq!(Some(Value::Number(0.0)), div.value("margin-right"));
+    assert_eq!(None, div.value("--loop"));
+    assert_eq!(Some(Value::Tokens("calc(4px * 2)".to_string())), p.value("--double"));
+    assert_eq!(Some(Value::Tokens("4px".to_string())), span.value("--gap"));
+    assert_eq!(None, p.value("--self"));
+    assert_eq!(red, p.value("color"));
+    assert_eq!(Some(Value::Length(1.0, Px)), p.value("padding-top"));
+    assert_eq!(Some(Value::Length(8.0, Px)), p.value("padding-right"));
+    // Invalid at computed-value time: `color` inherits, `width` takes its initial value.
+    assert_eq!(red, span.value("color"));
+    assert_eq!(Some(Value::Keyword("auto".to_string())), span.value("width"));
+    // So are substituted values that don't parse, or that the property doesn't accept.
+    assert_eq!(Some(Value::ColorValue(Color { r: 0, g: 0, b: 0, a: 0 })), span.value("background-color"));
+    assert_eq!(Some(Value::Keyword("auto".to_string())), span.value("height"));
+    let em = &span.children[0];
+    assert_eq!(red, em.value("color"));
+    assert_eq!(Some(Value::Keyword("auto".to_string())), em.value("width"));
+}
+
+#[test]
+fn media_rules_follow_the_device() {
+    use crate::css::Unit::Px;
+    let root = crate::parser::parse("<div></div>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        div { width: 100px; }
+        @media (min-width: 600px) { div { width: 200px; } @media print { div { width: 300px; } } }
+        @font-face { font-family: x; src: url(x.ttf); }
+        @charset "utf-8";
+    "#.to_string());
+    let width = |device: &Device| style_tree_for_device(&root, &[&stylesheet], device).value("width");
+    assert_eq!(Some(Value::Length(100.0, Px)), width(&Device::screen(400.0, 600.0)));
+    assert_eq!(Some(Value::Length(200.0, Px)), width(&Device::screen(800.0, 600.0)));
+    let print = Device { media_type: crate::media::MediaType::Print, ..Device::default() };
+    assert_eq!(Some(Value::Length(300.0, Px)), width(&print));
+}
+
+#[test]
+fn cascade_layers() {
+    use crate::css::Unit::Px;
+    let root = crate::parser::parse("<div id='d' class='c'></div>".to_string());
+    let stylesheet = crate::css::parse(r#"
+        @layer reset, base, components.buttons;
+        @layer components {
+            #d { width: 1px; height: 1px !important; }
+            @layer buttons { #d { width: 2px; padding: 2px; } }
+        }
+        @layer base { div { width: 3px; height: 3px !important; margin: 3px; } }
+        @layer { .c { padding: 4px; } }
+        @layer reset { div { margin: 5px !important; opacity: 0.5; } }
+        div { opacity: 1; }
+    "#.to_string());
+    let styled = style_tree(&root, &[&stylesheet]);
+    // A later layer wins over specificity; a parent layer's own rules beat its nested layers.
+    assert_eq!(Some(Value::Length(1.0, Px)), styled.value("width"));
+    // The anonymous layer comes after every layer declared before it.
+    assert_eq!(Some(Value::Length(4.0, Px)), styled.value("padding-top"));
+    // Important declarations in earlier layers win.
+    assert_eq!(Some(Value::Length(3.0, Px)), styled.value("height"));
+    assert_eq!(Some(Value::Length(5.0, Px)), styled.value("margin-top"));
+    // Unlayered rules beat layered ones.
+    assert_eq!(Some(Value::Number(1.0)), styled.value("opacity"));
+}
+
+#[test]
+fn style_sharing() {
+    use crate::css::Unit::Px;
+    let root = crate::parser::parse(r#"<table>
+        <tr><td class='n'></td><td class='n'></td><td class='n'></td><td class='n' title='x'></td></tr>
+        <tr><td class='n'></td><td class='n'></td><td id='i' class='n'></td><td class='n' style='width: 9px'></td></tr>
+    </table>"#.to_string());
+    let stylesheet = crate::css::parse("
+        td.n { width: 1px; } td:nth-child(2) { width: 2px; } tr + tr td { height: 3px; } #i { width: 4px; }
+        td::before { content: attr(title); }
+    ".to_string());
+    let (styled, stats) = style_tree_with_stats(&root, &[&stylesheet], &Device::default());
+    let cells = |row: usize| -> Vec<&StyledNode> {
+        styled.children.iter().filter(|c| c.pseudo_element.is_none() && c.text().is_none()).nth(row).unwrap()
+            .children.iter().filter(|c| c.text().is_none()).collect()
+    };
+    let widths = |row: usize| cells(row).iter().map(|c| c.value("width").unwrap()).collect::<Vec<_>>();
+    let px = |v: f32| Value::Length(v, Px);
+    assert_eq!(vec![px(1.0), px(2.0), px(1.0), px(1.0)], widths(0));
+    assert_eq!(vec![px(1.0), px(2.0), px(4.0), px(9.0)], widths(1));
+    assert_eq!(Some(px(3.0)), cells(1)[0].value("height"));
+    assert!(!Arc::ptr_eq(&cells(0)[0].computed_values, &cells(1)[0].computed_values));
+    assert_eq!("x", cells(0)[3].before.as_ref().unwrap().text().unwrap());
+    // Which elements share depends on how the tree is split between threads.
+    if cfg!(not(feature = "parallel")) {
+        assert!(Arc::ptr_eq(&cells(0)[0].computed_values, &cells(0)[2].computed_values));
+        // The rows share, as do the first and third cells of the first row. Cells in
+        // different rows don't, as they don't agree on `tr + tr td`.
+        assert_eq!(StyleSharingStats { hits: 2, misses: 6 }, stats);
+    }
+}
+
+#[test]
+fn inspect_matched_rules() {
+    let root = crate::parser::parse("<div><p id='x' class='a' style='margin-top: 3px'></p></div>".to_string());
+    let user = crate::css::parse_with_origin("p { color: green !important; }".to_string(), Origin::User);
+    let author = crate::css::parse("
+        .a { color: red; margin: 1px; }
+        div p#x { margin-left: 2px; }
+        p { width: 5px; color: blue !important; }
+    ".to_string());
+    let p = &root.children[0];
+    let info = get_computed_style(&root, p, &[&user, &author], &Device::default()).unwrap();
+    let styled = style_tree(&root, &[&user, &author]);
+    assert_eq!(*styled.children[0].computed_values, info.computed_values);
+    assert_eq!(Some(&Value::ColorValue(crate::css::Color { r: 0, g: 128, b: 0, a: 255 })),
+               info.computed_values.get("color"));
+
+    // User-agent rules come first, and the `style` attribute last.
+    let summary: Vec<(RuleSource, Specificity, Vec<bool>)> = info.matched_rules.iter()
+        .map(|m| (m.source.clone(), m.specificity, m.overridden.clone()))
+        .collect();
+    let at = |line, column| SourceLocation { line, column };
+    let user_agent = summary.iter().take_while(|(source, _, _)| matches!(source, RuleSource::UserAgent(_))).count();
+    assert!(user_agent > 0);
+    assert_eq!(vec![
+        (RuleSource::Stylesheet(0, Vec::new(), at(1, 1)), (0, 0, 1), vec![false]),
+        (RuleSource::Stylesheet(1, Vec::new(), at(4, 9)), (0, 0, 1), vec![false, true]),
+        (RuleSource::Stylesheet(1, Vec::new(), at(2, 9)), (0, 1, 0), vec![true, false]),
+        (RuleSource::Stylesheet(1, Vec::new(), at(3, 9)), (1, 0, 2), vec![false]),
+        (RuleSource::StyleAttribute, (0, 0, 0), vec![false]),
+    ], summary[user_agent..]);
+    assert_eq!(Some("div p#x".to_string()),
+               info.matched_rules[user_agent + 3].selector.as_ref().map(crate::serialize::serialize_selector));
+
+    let text = get_computed_style(&root, &crate::dom::text("x".to_string()), &[], &Device::default());
+    assert!(text.is_none());
+
+    // Rules from imported stylesheets give the imports they were reached through.
+    let mut author = crate::css::parse("@import 'a.css'; p { width: 1px; }".to_string());
+    if let CssRule::Import(ref mut import) = author.rules[0] {
+        import.stylesheet = Some(crate::css::parse("\np { height: 1px; }".to_string()));
+    }
+    let info = get_computed_style(&root, p, &[&author], &Device::default()).unwrap();
+    let sources: Vec<RuleSource> = info.matched_rules.iter().map(|m| m.source.clone()).skip(user_agent).collect();
+    assert_eq!(vec![
+        RuleSource::Stylesheet(0, vec!["a.css".to_string()], at(2, 1)),
+        RuleSource::Stylesheet(0, Vec::new(), at(1, 18)),
+        RuleSource::StyleAttribute,
+    ], sources);
+}
@@ -0,0 +1,71 @@
+use crate::css;
+use crate::properties;
+use crate::variables;
+
+/// The condition of an `@supports` rule.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SupportsCondition {
+    /// `(<property>: <value>)`, with the value kept as source text.
+    Declaration(String, String),
+    Not(Box<SupportsCondition>),
+    And(Vec<SupportsCondition>),
+    Or(Vec<SupportsCondition>),
+    /// Anything else, such as `selector(...)`, kept as source text. It never matches.
+    Unknown(String),
+}
+
+impl SupportsCondition {
+    pub fn matches(&self) -> bool {
+        match *self {
+            SupportsCondition::Declaration(ref name, ref value) => supports_declaration(name, value),
+            SupportsCondition::Not(ref condition) => !condition.matches(),
+            SupportsCondition::And(ref conditions) => conditions.iter().all(|c| c.matches()),
+            SupportsCondition::Or(ref conditions) => conditions.iter().any(|c| c.matches()),
+            SupportsCondition::Unknown(_) => false,
+        }
+    }
+}
+
+/// Whether the declaration `name: value` would be used by the cascade: the property is
+/// one we implement and the value is valid for it. Shorthands must expand to valid longhands.
+pub fn supports_declaration(name: &str, value: &str) -> bool {
+    if variables::is_custom_property(name) {
+        return true;
+    }
+    if properties::longhands(name).is_none() && properties::lookup(name).is_none() {
+        return false;
+    }
+    let Some(value) = css::parse_value(value.to_string()) else {
+        return false;
+    };
+    properties::is_valid(name, &value)
+}
+
+#[test]
+fn supports_conditions() {
+    use crate::css::{parse, CssRule};
+    let conditions = [
+        "(display: block)", "(display: grid)", "(width: calc(100% - 1em))", "(width: 1fr)", "(colour: red)",
+        "(margin: 0 auto)", "(border: 1px solid rgb(0 0 0 / 50%))", "(--anything: { x })", "not (display: grid)",
+        "(color: red) and (opacity: 0.5)", "(display: flex) or (display: inline)", "selector(a > b)",
+        "((display: none) and (not (float: left)))", "(color: #ggg)", "(width: calc(1px + 1cqw))",
+        "(color: rgb(from red r g b))", "(background: url(a b))",
+    ];
+    let source: String = conditions.iter().map(|c| format!("@supports {c} {{ p {{ color: red }} }}\n")).collect();
+    let matches: Vec<bool> = parse(source).rules.iter().map(|rule| match *rule {
+        CssRule::Supports(ref condition, _) => condition.matches(),
+        _ => panic!("expected @supports"),
+    }).collect();
+    assert_eq!(vec![
+        true, false, true, false, false, true, true, true, true, true, true, false, true, false, false, false, false,
+    ], matches);
+
+    // Rules with invalid conditions are dropped. An unclosed parenthesis runs to the end.
+    let parsed = parse("
+        @supports (display: block) and (color: red) or (opacity: 1) { p {} }
+        @supports not { p {} }
+        @supports (display: block) { a {} }
+        @supports (display: block { p {} }
+    ".to_string());
+    assert_eq!(1, parsed.rules.len());
+}
@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+use crate::css::{self, Value};
+use crate::properties;
+use crate::style::PropertyMap;
+
+pub fn is_custom_property(name: &str) -> bool {
+    name.starts_with("--")
+}
+
+/// Substitute `var()` references within the custom properties named in `names`.
+///
+/// A custom property that references an invalid one without a fallback, or that is
+/// part of a reference cycle, becomes invalid at computed-value time and is removed.
+pub fn resolve_custom_properties<'n>(values: &mut PropertyMap, names: impl Iterator<Item=&'n String>) {
+    let mut resolver = Resolver { values, resolved: HashMap::new(), stack: Vec::new(), cyclic: HashSet::new() };
+    for name in names {
+        resolver.resolve(name);
+    }
+    let resolved = resolver.resolved;
+    for (name, tokens) in resolved {
+        match tokens {
+            Some(tokens) => { values.insert(name, Value::Tokens(tokens)); }
+            None => { values.remove(&name); }
+        }
+    }
+}
+
+struct Resolver<'v> {
+    values: &'v PropertyMap,
+    resolved: HashMap<String, Option<String>>,
+    /// Custom properties whose references are being resolved, outermost first.
+    stack: Vec<String>,
+    cyclic: HashSet<String>,
+}
+
+impl Resolver<'_> {
+    fn resolve(&mut self, name: &str) -> Option<String> {
+        if let Some(tokens) = self.resolved.get(name) {
+            return tokens.clone();
+        }
+        if let Some(start) = self.stack.iter().position(|n| n == name) {
+            // Every property in the cycle is invalid, whatever fallbacks it has.
+            self.cyclic.extend(self.stack[start..].iter().cloned());
+            return None;
+        }
+        let tokens = match self.values.get(name) {
+            Some(Value::Tokens(tokens)) => tokens.clone(),
+            _ => return None,
+        };
+        self.stack.push(name.to_string());
+        let substituted = substitute(&tokens, &mut |var| self.resolve(var));
+        self.stack.pop();
+        let result = substituted.filter(|_| !self.cyclic.contains(name));
+        self.resolved.insert(name.to_string(), result.clone());
+        result
+    }
+}
+
+/// Replace every `var(--name, fallback)` in `tokens`, looking variables up with `lookup`.
+/// Returns `None` if a variable is invalid and has no fallback.
+pub fn substitute(tokens: &str, lookup: &mut dyn FnMut(&str) -> Option<String>) -> Option<String> {
+    let mut result = String::new();
+    let mut rest = tokens;
+    while let Some(start) = find_var(rest) {
+        result.push_str(&rest[..start]);
+        let arguments_start = start + "var(".len();
+        let arguments_end = arguments_start + matching_paren(&rest[arguments_start..])?;
+        let arguments = &rest[arguments_start..arguments_end];
+        let (name, fallback) = match top_level_comma(arguments) {
+            Some(comma) => (arguments[..comma].trim(), Some(&arguments[comma + 1..])),
+            None => (arguments.trim(), None),
+        };
+        if !is_custom_property(name) {
+            return None;
+        }
+        match lookup(name) {
+            Some(value) => result.push_str(&value),
+            None => result.push_str(&substitute(fallback?.trim(), lookup)?),
+        }
+        rest = &rest[arguments_end + 1..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+/// Substitute variables into a value that was declared as `Value::Pending` and parse the
+/// result as the value of `name`. Returns `None` if the value is invalid at computed-value time.
+pub fn resolve_pending(name: &str, property: &str, tokens: &str, values: &PropertyMap) -> Option<Value> {
+    let substituted = substitute(tokens, &mut |var| match values.get(var) {
+        Some(Value::Tokens(tokens)) => Some(tokens.clone()),
+        _ => None,
+    })?;
+    let value = css::parse_value(substituted)?;
+    properties::expand(property, &value).into_iter()
+        .find(|(longhand, _)| longhand == name)
+        .map(|(_, value)| value)
+        .filter(|value| properties::accepts(name, value))
+}
+
+/// The byte offset of the next `var(`, ignoring case and the insides of strings.
+fn find_var(tokens: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tokens.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, 'v' | 'V') if tokens[i..].get(..4).is_some_and(|s| s.eq_ignore_ascii_case("var(")) &&
+                !tokens[..i].ends_with(|c: char| c.is_alphanumeric() || c == '-' || c == '_') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The offset of the `)` closing a function whose arguments start `tokens`.
+fn matching_paren(tokens: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in tokens.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return Some(i),
+            (None, ')') => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn top_level_comma(arguments: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in arguments.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[test]
+fn substitution_and_cycles() {
+    let mut values: PropertyMap = [
+        ("--a", "1px var(--b)"), ("--b", "var(--missing, 2px)"), ("--c", "var(--d, 3px)"),
+        ("--d", "var(--c)"), ("--e", "var(--c, 4px)"), ("--f", "calc(var(--a,0) * 2)"),
+    ].iter().map(|&(name, tokens)| (name.to_string(), Value::Tokens(tokens.to_string()))).collect();
+    let names: Vec<String> = values.keys().cloned().collect();
+    resolve_custom_properties(&mut values, names.iter());
+
+    let tokens = |name: &str| match values.get(name) {
+        Some(Value::Tokens(tokens)) => Some(tokens.as_str()),
+        _ => None,
+    };
+    assert_eq!(Some("1px 2px"), tokens("--a"));
+    assert_eq!(Some("2px"), tokens("--b"));
+    assert_eq!((None, None), (tokens("--c"), tokens("--d")));
+    assert_eq!(Some("4px"), tokens("--e"));
+    assert_eq!(Some("calc(1px 2px * 2)"), tokens("--f"));
+}
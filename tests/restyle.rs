@@ -0,0 +1,185 @@
+// integration test: incremental restyles give the same styles as styling from scratch
+
+extern crate robinson;
+
+use robinson::{css, dom, media, parser, style};
+use robinson::style::{Restyler, StyledNode};
+
+const STYLESHEET: &str = "
+    div, p, ul, li { display: block; }
+    .a { color: red; }
+    .b p { width: 10px; }
+    .c + p { height: 5px; }
+    li:nth-child(2 of .d) { margin-left: 3px; }
+    li:first-child { padding-top: 1px; }
+    p:empty { opacity: 0.5; }
+    #e li { font-size: 2em; }
+    ul { counter-reset: item; }
+    li { counter-increment: item; }
+    li::before { content: counter(item) attr(title); }
+";
+
+const DOCUMENT: &str = concat!(
+    "<div>",
+    "<div class='b'><p>x</p><p></p></div>",
+    "<p class='c'></p><p></p><p></p>",
+    "<ul><li class='d'></li><li></li><li class='d'></li><li class='d'></li></ul>",
+    "<ul id='f'><li><p></p></li><li></li></ul>",
+    "</div>",
+);
+
+fn dump(node: &StyledNode, out: &mut String) {
+    out.push_str(&format!("{:?} {:?} {:?}\n", node.style(), node.text(), node.value("opacity")));
+    for child in node.before.as_deref().into_iter().chain(&node.children).chain(node.after.as_deref()) {
+        dump(child, out);
+    }
+}
+
+/// Restyle `root` incrementally, check the result against styling from scratch, and
+/// return how many elements were restyled.
+fn check(restyler: &mut Restyler, root: &mut dom::Node, stylesheet: &css::Stylesheet) -> usize {
+    let mut incremental = String::new();
+    dump(&restyler.style(root, &[stylesheet], &media::Device::default()), &mut incremental);
+    let mut full = String::new();
+    dump(&style::style_tree(root, &[stylesheet]), &mut full);
+    assert_eq!(full, incremental);
+    restyler.restyled_elements()
+}
+
+fn element_count(node: &dom::Node) -> usize {
+    let own = matches!(node.node_type, dom::NodeType::Element(_)) as usize;
+    own + node.children.iter().map(element_count).sum::<usize>()
+}
+
+#[test]
+fn restyles_only_affected_elements() {
+    let mut root = parser::parse(DOCUMENT.to_string());
+    let mut stylesheet = css::parse(STYLESHEET.to_string());
+    let mut restyler = Restyler::new();
+    assert_eq!(element_count(&root), check(&mut restyler, &mut root, &stylesheet));
+    assert_eq!(0, check(&mut restyler, &mut root, &stylesheet));
+
+    // `.a` only affects the element itself.
+    root.children[2].set_attribute("class", "a".to_string());
+    assert_eq!(1, check(&mut restyler, &mut root, &stylesheet));
+
+    // Removing `.b` affects its descendants, but not the element itself.
+    root.children[0].set_attribute("class", "".to_string());
+    assert_eq!(2, check(&mut restyler, &mut root, &stylesheet));
+
+    // `.c` affects the following siblings and their descendants.
+    root.children[1].set_attribute("class", "".to_string());
+    assert_eq!(2 + 5 + 4, check(&mut restyler, &mut root, &stylesheet));
+
+    // An id affects the descendants of the element.
+    root.children[5].set_attribute("id", "e".to_string());
+    assert_eq!(3, check(&mut restyler, &mut root, &stylesheet));
+
+    // Attributes that no selector tests don't restyle anything; `attr()` is reevaluated anyway.
+    root.children[4].children[1].set_attribute("title", "!".to_string());
+    assert_eq!(0, check(&mut restyler, &mut root, &stylesheet));
+
+    // A new rule only restyles the elements it matches, and their descendants if
+    // their style changes.
+    stylesheet.rules.push(css::parse("ul { color: blue; }".to_string()).rules.remove(0));
+    assert_eq!(2 + 6 + 1, check(&mut restyler, &mut root, &stylesheet));
+}
+
+#[test]
+fn structural_changes() {
+    let mut root = parser::parse(DOCUMENT.to_string());
+    let stylesheet = css::parse(STYLESHEET.to_string());
+    let mut restyler = Restyler::new();
+    check(&mut restyler, &mut root, &stylesheet);
+
+    // Inserting a child can change the position of its siblings, and whether its parent is empty.
+    let item = parser::parse("<li class='d'><p></p></li>".to_string());
+    root.children[4].insert_child(0, item);
+    assert_eq!(2 + 4 + 1, check(&mut restyler, &mut root, &stylesheet));
+
+    root.children[2].insert_child(0, dom::text("x".to_string()));
+    root.children[4].remove_child(2);
+    check(&mut restyler, &mut root, &stylesheet);
+
+    let removed = root.children[4].remove_child(0);
+    root.children[5].insert_child(1, removed);
+    check(&mut restyler, &mut root, &stylesheet);
+
+    // Whether an element is empty can affect its siblings.
+    let mut root = parser::parse("<div><p class='x'></p><span></span><span></span></div>".to_string());
+    let stylesheet = css::parse(".x:empty + span { width: 5px; } .x:empty ~ span { height: 5px; }".to_string());
+    let mut restyler = Restyler::new();
+    check(&mut restyler, &mut root, &stylesheet);
+    root.children[0].insert_child(0, dom::text("x".to_string()));
+    assert_eq!(2, check(&mut restyler, &mut root, &stylesheet));
+    root.children[0].remove_child(0);
+    check(&mut restyler, &mut root, &stylesheet);
+}
+
+/// A small xorshift generator, so the changes are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// The path of a random element, as child indices from the root.
+fn random_element(node: &dom::Node, rng: &mut Rng) -> Vec<usize> {
+    let elements: Vec<usize> = node.children.iter().enumerate()
+        .filter(|(_, c)| matches!(c.node_type, dom::NodeType::Element(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if elements.is_empty() || rng.below(3) == 0 {
+        return Vec::new();
+    }
+    let i = elements[rng.below(elements.len())];
+    let mut path = vec![i];
+    path.extend(random_element(&node.children[i], rng));
+    path
+}
+
+fn node_at<'a>(node: &'a mut dom::Node, path: &[usize]) -> &'a mut dom::Node {
+    path.iter().fold(node, |node, &i| &mut node.children[i])
+}
+
+#[test]
+fn random_changes() {
+    let mut root = parser::parse(DOCUMENT.to_string());
+    let mut stylesheet = css::parse(STYLESHEET.to_string());
+    let mut restyler = Restyler::new();
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+    let classes = ["", "a", "b", "c", "d", "a c"];
+    for _ in 0..200 {
+        for _ in 0..rng.below(3) + 1 {
+            let path = random_element(&root, &mut rng);
+            let node = node_at(&mut root, &path);
+            match rng.below(5) {
+                0 | 1 => node.set_attribute("class", classes[rng.below(classes.len())].to_string()),
+                2 => if rng.below(2) == 0 {
+                    node.set_attribute("id", "e".to_string());
+                } else {
+                    node.remove_attribute("id");
+                },
+                3 => {
+                    let index = rng.below(node.children.len() + 1);
+                    let tag = ["p", "li", "div"][rng.below(3)];
+                    node.insert_child(index, parser::parse(format!("<{tag} class='d'><p></p></{tag}>")));
+                }
+                _ => if !node.children.is_empty() {
+                    let index = rng.below(node.children.len());
+                    node.remove_child(index);
+                },
+            }
+        }
+        if rng.below(10) == 0 {
+            let rule = ["p { color: green; }", ".d { width: 7px; }", "div > li { height: 2px; }"][rng.below(3)];
+            stylesheet.rules.insert(rng.below(stylesheet.rules.len() + 1), css::parse(rule.to_string()).rules.remove(0));
+        }
+        check(&mut restyler, &mut root, &stylesheet);
+    }
+}
@@ -0,0 +1,155 @@
+// integration test: serialization round-trips through the parser
+
+extern crate robinson;
+
+use robinson::{css, parser, serialize, style};
+
+/// A small xorshift generator, so the generated stylesheets are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+const TAGS: &[&str] = &["div", "p", "span", "h1", "li", "*"];
+const CLASSES: &[&str] = &["a", "b", "note", "x-y"];
+const PROPERTIES: &[&str] = &["color", "background-color", "width", "height", "margin-left", "padding-top", "font-family", "content", "display"];
+
+fn compound(rng: &mut Rng, depth: usize) -> String {
+    let mut result = rng.pick(TAGS).to_string();
+    for _ in 0..rng.below(3) {
+        result.push('.');
+        result.push_str(rng.pick(CLASSES));
+    }
+    if rng.below(4) == 0 {
+        result.push_str("#main");
+    }
+    match rng.below(10) {
+        0 => result.push_str(":first-child"),
+        1 => result.push_str(rng.pick(&[":nth-child(2n+1)", ":nth-child(-n+3)", ":nth-last-child(4)", ":nth-of-type(odd)"])),
+        2 if depth == 0 => result.push_str(&format!(":not({})", selector(rng, depth + 1))),
+        3 if depth == 0 => result.push_str(&format!(":is({}, {})", compound(rng, depth + 1), compound(rng, depth + 1))),
+        4 if depth == 0 => result.push_str(&format!(":has(> {})", compound(rng, depth + 1))),
+        5 => result.push_str("::before"),
+        _ => {}
+    }
+    result
+}
+
+fn selector(rng: &mut Rng, depth: usize) -> String {
+    let mut result = compound(rng, depth);
+    for _ in 0..rng.below(3) {
+        let combinator = rng.pick(&[" ", " > ", " + ", " ~ "]);
+        result = format!("{result}{combinator}{}", compound(rng, depth).replace("::before", ""));
+    }
+    result
+}
+
+fn color(rng: &mut Rng) -> String {
+    match rng.below(6) {
+        0 => rng.pick(&["red", "rebeccapurple", "transparent", "white", "lightgoldenrodyellow"]).to_string(),
+        1 => format!("#{:06x}", rng.next() & 0xffffff),
+        2 => format!("#{:08x}", rng.next() & 0xffffffff),
+        3 => format!("rgb({} {} {} / {}%)", rng.below(256), rng.below(256), rng.below(256), rng.below(101)),
+        4 => format!("hsl({}deg 50% 40%)", rng.below(360)),
+        _ => format!("oklch(0.{} 0.1 {})", rng.below(10), rng.below(360)),
+    }
+}
+
+fn length(rng: &mut Rng) -> String {
+    match rng.below(5) {
+        0 => format!("{}px", rng.below(200)),
+        1 => format!("{}.{}em", rng.below(4), rng.below(10)),
+        2 => format!("{}%", rng.below(100)),
+        3 => format!("calc({}% - {}px * 2)", rng.below(100), rng.below(50)),
+        _ => format!("clamp(1px, {}vw, 300px)", rng.below(50)),
+    }
+}
+
+fn declaration(rng: &mut Rng) -> String {
+    let name = rng.pick(PROPERTIES);
+    let value = match name {
+        "color" | "background-color" => color(rng),
+        "font-family" => rng.pick(&["Georgia, serif", "\"Times New Roman\", serif", "monospace"]).to_string(),
+        "content" => rng.pick(&["\"a \\\"quoted\\\" string\"", "\"#\" counter(item)", "url(\"img.png\")", "none"]).to_string(),
+        "display" => rng.pick(&["block", "inline", "none"]).to_string(),
+        _ if rng.below(5) == 0 => "var(--gap, 4px)".to_string(),
+        _ => length(rng),
+    };
+    let important = if rng.below(6) == 0 { " !important" } else { "" };
+    format!("{name}: {value}{important}")
+}
+
+fn style_rule(rng: &mut Rng) -> String {
+    let selectors: Vec<String> = (0..=rng.below(2)).map(|_| selector(rng, 0)).collect();
+    let declarations: Vec<String> = (0..rng.below(4)).map(|_| declaration(rng)).collect();
+    format!("{} {{ {} }}", selectors.join(", "), declarations.join("; "))
+}
+
+fn stylesheet(rng: &mut Rng) -> String {
+    let mut rules = vec![":root { --gap: 3px }".to_string()];
+    for _ in 0..rng.below(8) {
+        let rule = match rng.below(8) {
+            0 => format!("@media {} {{ {} }}", rng.pick(&["print", "screen and (min-width: 600px)", "not all and (400px < width <= 700px)", "(orientation: landscape) or (color)"]), style_rule(rng)),
+            1 => format!("@supports {} {{ {} }}", rng.pick(&["(display: block)", "not (color: 1fr)", "(width: 1px) and (height: 2px)"]), style_rule(rng)),
+            2 => format!("@layer {} {{ {} }}", rng.pick(&["base", "base.reset", ""]), style_rule(rng)),
+            3 => "@layer base, theme.dark;".to_string(),
+            4 => rules.last().unwrap().clone(),
+            _ => style_rule(rng),
+        };
+        rules.push(rule);
+    }
+    rules.join("\n")
+}
+
+fn computed_styles(sheet: &css::Stylesheet) -> Vec<Vec<Option<css::Value>>> {
+    let html = "<div id='main' class='a b'><p class='note'>x</p><span class='a'></span><ul><li class='x-y'></li><li></li></ul></div>";
+    let root = parser::parse(html.to_string());
+    let styled = style::style_tree(&root, &[sheet]);
+    let mut result = Vec::new();
+    let mut stack = vec![&styled];
+    while let Some(node) = stack.pop() {
+        result.push(PROPERTIES.iter().map(|name| node.value(name)).collect());
+        stack.extend(&node.children);
+    }
+    result
+}
+
+#[test]
+fn serialized_stylesheets_reparse_to_the_same_rules() {
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+    for _ in 0..300 {
+        let source = stylesheet(&mut rng);
+        let parsed = css::parse(source.clone());
+        let serialized = serialize::serialize(&parsed);
+        assert_eq!(parsed, css::parse(serialized.clone()), "{source}\n=> {serialized}");
+        assert_eq!(serialized, serialize::serialize(&css::parse(serialized.clone())));
+    }
+}
+
+#[test]
+fn minified_stylesheets_cascade_the_same() {
+    let mut rng = Rng(0x9e3779b97f4a7c15);
+    for _ in 0..300 {
+        let source = stylesheet(&mut rng);
+        let parsed = css::parse(source.clone());
+        let minified = serialize::minify(&parsed);
+        let reparsed = css::parse(minified.clone());
+        assert_eq!(minified, serialize::minify(&reparsed), "{source}");
+        assert_eq!(computed_styles(&parsed), computed_styles(&reparsed), "{source}\n=> {minified}");
+        assert!(minified.len() <= serialize::serialize(&parsed).len());
+    }
+}
@@ -2,8 +2,6 @@
 
 extern crate robinson;
 
-use std::collections::HashMap;
-
 #[test]
 fn test_parse_style_attr() {
     use robinson::{parser, dom, css};
@@ -19,3 +17,31 @@ fn test_parse_style_attr() {
     let expected = css::parse("html { color: red; }".to_string());
     assert_eq!(expected, stylesheet);
 }
+
+#[test]
+fn test_style_attr_cascade() {
+    use robinson::{parser, css, style};
+
+    let html = "<div id=\"box\" style=\"background: red; display: block\"></div>".to_string();
+    let root = parser::parse(html);
+    let stylesheet = css::parse("#box { background: blue; display: none; }".to_string());
+    let styled = style::style_tree(&root, &[&stylesheet]);
+
+    let red = css::Value::ColorValue(css::Color { r: 255, g: 0, b: 0, a: 255 });
+    assert_eq!(Some(red), styled.value("background-color"));
+    assert_eq!(style::Display::BLOCK, styled.display());
+}
+
+#[test]
+fn test_style_attr_below_author_important() {
+    use robinson::{parser, css, style};
+
+    let html = "<div style=\"display: block; background: red !important;\"></div>".to_string();
+    let root = parser::parse(html);
+    let stylesheet = css::parse("div { display: none !important; background: blue !important; }".to_string());
+    let styled = style::style_tree(&root, &[&stylesheet]);
+
+    let red = css::Value::ColorValue(css::Color { r: 255, g: 0, b: 0, a: 255 });
+    assert_eq!(style::Display::None, styled.display());
+    assert_eq!(Some(red), styled.value("background-color"));
+}
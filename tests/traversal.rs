@@ -0,0 +1,110 @@
+// integration test: styling and layout give the same result on every run, and the same
+// result as the sequential traversal, which is stored in traversal.expected. Run with
+// `cargo test --features parallel` to check the parallel traversal against it.
+
+extern crate robinson;
+
+use robinson::{css, layout, parser, style};
+use robinson::layout::{BoxType, Dimensions, LayoutBox};
+use robinson::style::StyledNode;
+
+const STYLESHEET: &str = "
+    section, ul, li, p { display: block; }
+    ul { margin: 0; }
+    section { counter-reset: item; padding: 4px; margin-bottom: 2%; }
+    li { counter-increment: item; height: 10px; }
+    li::before { content: counter(item) \". \"; }
+    li:first-child { height: 20px; }
+    .wide { padding-left: 5%; border-top-width: 1px; }
+    section > p { height: 2em; margin: 0 auto; width: 50%; }
+";
+
+fn document() -> String {
+    let mut html = String::from("<div>");
+    for i in 0..40usize {
+        html.push_str(&format!("<section class='{}'><p></p><ul>", if i.is_multiple_of(3) { "wide" } else { "" }));
+        for _ in 0..(i % 7 + 1) {
+            html.push_str("<li>x</li>");
+        }
+        html.push_str("</ul></section>");
+    }
+    html.push_str("</div>");
+    html
+}
+
+fn dump_style(node: &StyledNode, out: &mut String) {
+    out.push_str(&format!("{:?} {:?} {:?}\n", node.display(), node.text(), node.value("height")));
+    for child in node.before.as_deref().into_iter().chain(&node.children).chain(node.after.as_deref()) {
+        dump_style(child, out);
+    }
+}
+
+fn dump_layout(layout_box: &LayoutBox, out: &mut String) {
+    let d = layout_box.dimensions;
+    let kind = match layout_box.box_type {
+        BoxType::BlockNode(_) => "block",
+        BoxType::InlineNode(_) => "inline",
+        BoxType::InlineBlockNode(_) => "inline-block",
+        BoxType::AnonymousBlock => "anonymous",
+        BoxType::TextRun(_) => "text",
+        BoxType::LineBox => "line",
+        BoxType::TextFragment(_, ref text) => text,
+    };
+    let (c, m) = (d.content, d.margin);
+    out.push_str(&format!("{} {} {} {} {} {} {} {} {}\n", kind, c.x, c.y, c.width, c.height, m.left, m.top, m.right, m.bottom));
+    for child in &layout_box.children {
+        dump_layout(child, out);
+    }
+}
+
+fn run(root: &robinson::dom::Node, stylesheet: &css::Stylesheet) -> String {
+    let styled = style::style_tree(root, &[stylesheet]);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+    let mut dump = String::new();
+    dump_style(&styled, &mut dump);
+    dump_layout(&layout_root, &mut dump);
+    dump
+}
+
+#[test]
+fn traversal_is_deterministic() {
+    let root = parser::parse(document());
+    let stylesheet = css::parse(STYLESHEET.to_string());
+    // Regenerate traversal.expected from a sequential build when the output changes on purpose.
+    let expected = include_str!("traversal.expected");
+    for _ in 0..10 {
+        assert!(run(&root, &stylesheet) == expected);
+    }
+}
+
+#[test]
+fn counters_and_positions_follow_document_order() {
+    let root = parser::parse(document());
+    let stylesheet = css::parse(STYLESHEET.to_string());
+    let styled = style::style_tree(&root, &[&stylesheet]);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    // The last section has five items, numbered in order.
+    let ul = &styled.children[39].children[1];
+    let markers: Vec<_> = ul.children.iter().map(|li| li.before.as_ref().unwrap().text().unwrap()).collect();
+    assert_eq!(vec!["1. ", "2. ", "3. ", "4. ", "5. "], markers);
+
+    // Each section is as tall as its paragraph and items, plus padding, and is followed
+    // by a margin of 2% of the 800px width.
+    let sections = &layout_root.children;
+    let height = |i: usize| 32.0 + 20.0 + 10.0 * (i % 7) as f32 + 8.0 + if i.is_multiple_of(3) { 1.0 } else { 0.0 };
+    let mut y = 0.0;
+    for (i, section) in sections.iter().enumerate() {
+        let d = section.dimensions;
+        assert_eq!(y + 4.0 + d.border.top, d.content.y);
+        y += height(i) + 16.0;
+    }
+    let p = sections[1].children[0].dimensions;
+    assert_eq!((202.0, 396.0, 198.0), (p.content.x, p.content.width, p.margin.right));
+}